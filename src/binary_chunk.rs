@@ -24,6 +24,8 @@ const LUA_VNUMFLT: u8 = LUA_TNUMBER | (1 << 4);
 const LUA_VSHRSHR: u8 = LUA_TSTRING;
 const LUA_VLNGSHR: u8 = LUA_TSTRING | (1 << 4);
 
+const LUAI_MAXSHORTLEN: usize = 40;
+
 const LUAC_DATA: [u8; 6] = *b"\x19\x93\r\n\x1a\n";
 
 const LUAC_INT: Integer = 0x5678;
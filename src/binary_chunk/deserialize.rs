@@ -81,8 +81,8 @@ fn load_function<'gc, R: Read>(
     let source = load_nullable_str(gc, reader)?.unwrap_or(parent_source);
     let line_defined = load_int(reader)?;
     let last_line_defined = load_int(reader)?;
-    reader.read_u8()?; // numparams
-    reader.read_u8()?; // is_vararg
+    let num_params = reader.read_u8()?;
+    let is_vararg = reader.read_u8()? != 0;
     let max_stack_size = reader.read_u8()?;
 
     let code = load_code(reader)?;
@@ -118,12 +118,16 @@ fn load_function<'gc, R: Read>(
 
     // Upvalue
     let n = load_int(reader)?;
+    let mut upvalue_names = Vec::with_capacity(n as usize);
     for _ in 0..n {
-        load_nullable_str(gc, reader)?; // name
+        let name = load_nullable_str(gc, reader)?; // name
+        upvalue_names.push(name.unwrap_or_else(|| gc.allocate_string(B("?"))));
     }
 
     Ok(LuaClosureProto {
         max_stack_size,
+        num_params,
+        is_vararg,
         lines_defined: if line_defined > 0 {
             LineRange::Lines(line_defined..=last_line_defined)
         } else {
@@ -149,6 +153,11 @@ fn load_function<'gc, R: Read>(
         } else {
             Some(local_variables.into_boxed_slice())
         },
+        upvalue_names: if upvalue_names.is_empty() {
+            None
+        } else {
+            Some(upvalue_names.into_boxed_slice())
+        },
     })
 }
 
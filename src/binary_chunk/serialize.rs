@@ -21,42 +21,77 @@ pub fn dump<W: Write>(writer: &mut W, proto: &LuaClosureProto) -> std::io::Resul
     writer.write_f64::<NativeEndian>(super::LUAC_NUM)?;
 
     writer.write_u8(proto.upvalues.len() as u8)?;
-    dump_function(writer, proto)?;
+    dump_function(writer, proto, None)?;
 
     Ok(())
 }
 
-fn dump_function<W: Write>(writer: &mut W, proto: &LuaClosureProto) -> std::io::Result<()> {
+fn dump_function<'gc, W: Write>(
+    writer: &mut W,
+    proto: &LuaClosureProto<'gc>,
+    parent_source: Option<LuaString<'gc>>,
+) -> std::io::Result<()> {
     let (line_defined, last_line_defined) = match &proto.lines_defined {
         LineRange::File => (0, 0),
         LineRange::Lines(range) => (*range.start(), *range.end()),
     };
 
-    dump_string(writer, proto.source)?; // source
+    // source is omitted if it is the same as the parent's
+    let source = (parent_source != Some(proto.source)).then_some(proto.source);
+    dump_string(writer, source)?;
     dump_int(writer, line_defined)?;
     dump_int(writer, last_line_defined)?;
-    writer.write_u8(0)?; // numparams
-    writer.write_u8(0)?; // is_vararg
+    writer.write_u8(proto.num_params)?;
+    writer.write_u8(proto.is_vararg as u8)?;
     writer.write_u8(proto.max_stack_size)?;
 
     dump_code(writer, &proto.code)?;
     dump_constants(writer, &proto.constants)?;
     dump_upvalues(writer, &proto.upvalues)?;
-    dump_protos(writer, &proto.protos)?;
-
-    dump_int(writer, 0)?; // lineinfo
-    dump_int(writer, 0)?; // abslineinfo
-    dump_int(writer, 0)?; // locvar
-    dump_int(writer, 0)?; // upvalue
+    dump_protos(writer, &proto.protos, proto.source)?;
+    dump_debug(writer, proto)?;
 
     Ok(())
 }
 
-fn dump_protos<W: Write>(writer: &mut W, protos: &[Gc<LuaClosureProto>]) -> std::io::Result<()> {
+fn dump_protos<'gc, W: Write>(
+    writer: &mut W,
+    protos: &[Gc<LuaClosureProto<'gc>>],
+    source: LuaString<'gc>,
+) -> std::io::Result<()> {
     dump_size(writer, protos.len())?;
     for proto in protos {
-        dump_function(writer, proto)?;
+        dump_function(writer, proto, Some(source))?;
+    }
+    Ok(())
+}
+
+fn dump_debug<W: Write>(writer: &mut W, proto: &LuaClosureProto) -> std::io::Result<()> {
+    let line_info = proto.line_info.as_deref().unwrap_or_default();
+    dump_size(writer, line_info.len())?;
+    writer.write_all(line_info)?;
+
+    let abs_line_info = proto.abs_line_info.as_deref().unwrap_or_default();
+    dump_size(writer, abs_line_info.len())?;
+    for abs in abs_line_info {
+        dump_int(writer, abs.pc)?;
+        dump_int(writer, abs.line)?;
     }
+
+    let local_vars = proto.local_vars.as_deref().unwrap_or_default();
+    dump_size(writer, local_vars.len())?;
+    for var in local_vars {
+        dump_string(writer, var.name)?;
+        dump_int(writer, var.pc.start)?;
+        dump_int(writer, var.pc.end)?;
+    }
+
+    let upvalue_names = proto.upvalue_names.as_deref().unwrap_or_default();
+    dump_size(writer, upvalue_names.len())?;
+    for name in upvalue_names {
+        dump_string(writer, *name)?;
+    }
+
     Ok(())
 }
 
@@ -123,7 +158,11 @@ fn dump_constants<W: Write>(writer: &mut W, constants: &[Value]) -> std::io::Res
                 writer.write_f64::<NativeEndian>(*x)?;
             }
             Value::String(s) => {
-                writer.write_u8(super::LUA_VLNGSHR)?;
+                writer.write_u8(if s.len() <= super::LUAI_MAXSHORTLEN {
+                    super::LUA_VSHRSHR
+                } else {
+                    super::LUA_VLNGSHR
+                })?;
                 dump_string(writer, *s)?;
             }
             _ => unreachable!(),
@@ -8,15 +8,18 @@ use crate::{
     parser::ast::{
         BinaryOp, Block, Chunk, Expression, FunctionArguments, FunctionExpression, UnaryOp,
     },
-    runtime::Metamethod,
+    runtime::{ops, Metamethod},
     types::{
-        Integer, LuaClosureProto, LuaString, RegisterIndex, UpvalueDescription, UpvalueIndex, Value,
+        Integer, LuaClosureProto, LuaString, Number, RegisterIndex, UpvalueDescription,
+        UpvalueIndex, Value,
     },
 };
+use bstr::{ByteSlice, B};
 use ir::{ConstantIndex25, ConstantIndex8, IrAddress, IrInstruction, Label, ProtoIndex, RkIndex};
 use std::{
     collections::{hash_map, HashMap},
     num::NonZeroU8,
+    ops::{Add, Mul, RangeInclusive, Sub},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -39,8 +42,36 @@ pub enum CodegenError {
     #[error("cannot use '...' outside a vararg function")]
     VarArgExpressionOutsideVarArgFunction,
 
-    #[error("break outside loop")]
-    BreakOutsideLoop,
+    #[error("break outside a loop at line {line}")]
+    BreakOutsideLoop { line: u32 },
+
+    #[error("label '{label}' already defined on line {line}")]
+    DuplicateLabel { label: String, line: u32 },
+
+    #[error("no visible label '{label}' for <goto> at line {line}")]
+    NoVisibleLabel { label: String, line: u32 },
+
+    #[error("<goto {label}> at line {line} jumps into the scope of local '{local}'")]
+    JumpIntoScopeOfLocal {
+        label: String,
+        line: u32,
+        local: String,
+    },
+
+    #[error("attempt to assign to const variable '{0}'")]
+    AssignToConstVariable(String),
+
+    #[error("unknown attribute '{0}'")]
+    UnknownAttribute(String),
+
+    #[error("multiple to-be-closed variables in local list")]
+    MultipleToBeClosedVariables,
+
+    #[error("{location} {source}")]
+    Located {
+        location: String,
+        source: Box<CodegenError>,
+    },
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -135,22 +166,26 @@ enum LazyRValue<'gc> {
         callee: Box<LazyRValue<'gc>>,
         args: FunctionArguments<'gc>,
         may_return_multiple_values: bool,
+        line: u32,
     },
     MethodCall {
         table: Box<LazyRValue<'gc>>,
         name: LuaString<'gc>,
         args: FunctionArguments<'gc>,
         may_return_multiple_values: bool,
+        line: u32,
     },
     UnaryOp {
         op: UnaryOp,
         inner: Box<LazyRValue<'gc>>,
+        line: u32,
     },
     BinaryOp {
         op: BinaryOp,
         lhs: Box<LazyRValue<'gc>>,
         rhs: Box<LazyRValue<'gc>>,
         flipped: bool,
+        line: u32,
     },
     ShortCircuit {
         op: BinaryOp,
@@ -202,6 +237,21 @@ impl LazyRValue<'_> {
             _ => false,
         }
     }
+
+    /// Whether the value can be discharged into the first of the temporary
+    /// registers it refers to, as each of them is read by a single
+    /// instruction before it may be overwritten.
+    fn can_discharge_over_temporaries(&self) -> bool {
+        match self {
+            Self::LValue(_) | Self::Constant(_) | Self::Proto(_) | Self::VarArg { .. } => true,
+            Self::FunctionCall { callee, .. } => callee.can_discharge_over_temporaries(),
+            Self::MethodCall { table, .. } => table.can_discharge_over_temporaries(),
+            Self::UnaryOp { .. }
+            | Self::BinaryOp { .. }
+            | Self::ShortCircuit { .. }
+            | Self::Comparison { .. } => false,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -210,23 +260,33 @@ struct Frame<'gc> {
     max_stack_size: u8,
 
     ir_code: Vec<IrInstruction>,
+    ir_lines: Vec<u32>,
     label_ir_addresses: Vec<Option<IrAddress>>,
 
     constants: HashMap<Value<'gc>, usize>,
     upvalues: HashMap<UpvalueDescription, UpvalueIndex>,
+    upvalue_names: Vec<LuaString<'gc>>,
     protos: Vec<LuaClosureProto<'gc>>,
 
-    local_variable_stack: Vec<(Option<LuaString<'gc>>, RegisterIndex)>,
+    local_variable_stack: Vec<LocalVariable<'gc>>,
+    local_variable_ranges: Vec<LocalVariableRange<'gc>>,
+    scopes: Vec<Scope>,
+    visible_labels: Vec<VisibleLabel<'gc>>,
+    pending_gotos: Vec<PendingGoto<'gc>>,
 
     num_fixed_args: u8,
     is_vararg: bool,
     needs_to_close_upvalues: bool,
+
+    /// `None` for the main chunk.
+    lines_defined: Option<RangeInclusive<u32>>,
 }
 
-impl Frame<'_> {
+impl<'gc> Frame<'gc> {
     fn allocate_upvalue(
         &mut self,
         upvalue: UpvalueDescription,
+        name: LuaString<'gc>,
     ) -> Result<UpvalueIndex, CodegenError> {
         let i = self.upvalues.len();
         match self.upvalues.entry(upvalue) {
@@ -235,6 +295,7 @@ impl Frame<'_> {
                 if let Ok(i) = i.try_into() {
                     let i = UpvalueIndex(i);
                     entry.insert(i);
+                    self.upvalue_names.push(name);
                     Ok(i)
                 } else {
                     Err(CodegenError::TooManyUpvalues)
@@ -242,18 +303,122 @@ impl Frame<'_> {
             }
         }
     }
+
+    fn capture_local_variable(&mut self, name: LuaString) {
+        self.needs_to_close_upvalues = true;
+        if let Some(i) = self
+            .local_variable_stack
+            .iter()
+            .rposition(|var| var.name == Some(name))
+        {
+            if let Some(scope) = self
+                .scopes
+                .iter_mut()
+                .rfind(|scope| scope.num_active_local_vars <= i)
+            {
+                scope.has_captured_local_vars = true;
+            }
+        }
+    }
+
+    fn register_level(&self, num_local_vars: usize) -> RegisterIndex {
+        let level = self.local_variable_stack[..num_local_vars]
+            .iter()
+            .filter_map(LocalVariable::register)
+            .map(|i| i.0 + 1)
+            .max()
+            .unwrap_or_default();
+        RegisterIndex(level)
+    }
+
+    fn local_variable_name(&self, i: usize) -> String {
+        self.local_variable_stack[i]
+            .name
+            .map(|name| name.to_str_lossy().into_owned())
+            .unwrap_or_default()
+    }
 }
 
-#[derive(Default)]
-struct Loop {
-    break_label: Option<Label>,
+struct LocalVariable<'gc> {
+    name: Option<LuaString<'gc>>,
+    kind: LocalVariableKind<'gc>,
+}
+
+enum LocalVariableKind<'gc> {
+    Register {
+        register: RegisterIndex,
+        is_readonly: bool,
+    },
+    /// Compile-time constant that does not occupy a register.
+    Constant(Value<'gc>),
+}
+
+impl LocalVariable<'_> {
+    const fn register(&self) -> Option<RegisterIndex> {
+        match self.kind {
+            LocalVariableKind::Register { register, .. } => Some(register),
+            LocalVariableKind::Constant(_) => None,
+        }
+    }
+
+    const fn is_readonly(&self) -> bool {
+        match self.kind {
+            LocalVariableKind::Register { is_readonly, .. } => is_readonly,
+            LocalVariableKind::Constant(_) => true,
+        }
+    }
+}
+
+impl<'gc> From<(Option<LuaString<'gc>>, RegisterIndex)> for LocalVariable<'gc> {
+    fn from((name, register): (Option<LuaString<'gc>>, RegisterIndex)) -> Self {
+        Self {
+            name,
+            kind: LocalVariableKind::Register {
+                register,
+                is_readonly: false,
+            },
+        }
+    }
+}
+
+/// Range of IR addresses where a local variable is active, recorded for debug
+/// information.
+struct LocalVariableRange<'gc> {
+    name: LuaString<'gc>,
+    start: IrAddress,
+    end: Option<IrAddress>,
+}
+
+struct Scope {
+    num_active_local_vars: usize,
+    first_visible_label: usize,
+    first_pending_goto: usize,
+    is_loop: bool,
+    has_captured_local_vars: bool,
+}
+
+struct VisibleLabel<'gc> {
+    name: LuaString<'gc>,
+    line: u32,
+    label: Label,
+    num_active_local_vars: usize,
+}
+
+struct PendingGoto<'gc> {
+    name: LuaString<'gc>,
+    line: u32,
+    label: Label,
+    num_active_local_vars: usize,
+    needs_to_close_upvalues: bool,
 }
 
 struct CodeGenerator<'gc> {
     gc: &'gc GcContext,
     source: LuaString<'gc>,
     frames: Vec<Frame<'gc>>,
-    loops: Vec<Loop>,
+
+    /// Line attributed to emitted instructions.
+    current_line: u32,
 }
 
 impl<'gc> CodeGenerator<'gc> {
@@ -262,15 +427,17 @@ impl<'gc> CodeGenerator<'gc> {
             gc,
             source,
             frames: Default::default(),
-            loops: Default::default(),
+            current_line: 1,
         }
     }
 
     fn enter_frame(&mut self) {
         self.frames.push(Frame::default());
+        self.enter_scope(false);
     }
 
     fn finish_frame(&mut self) -> Result<LuaClosureProto<'gc>, CodegenError> {
+        self.leave_scope()?;
         ir::lower_ir(self.gc, self.source, self.frames.pop().unwrap())
     }
 
@@ -279,7 +446,53 @@ impl<'gc> CodeGenerator<'gc> {
     }
 
     fn emit(&mut self, insn: IrInstruction) {
-        self.current_frame().ir_code.push(insn);
+        let line = self.current_line;
+        let current = self.current_frame();
+        current.ir_code.push(insn);
+        current.ir_lines.push(line);
+    }
+
+    /// Moves the line attributed to instructions forward to `line`, following
+    /// the progress of the parser through the source.
+    fn advance_line(&mut self, line: u32) {
+        self.current_line = self.current_line.max(line);
+    }
+
+    /// Attributes an error detected while generating code to the current line.
+    // refer to "luaK_semerror" in lcode.c
+    fn semantic_error(&self, error: CodegenError) -> CodegenError {
+        let source = self.source.to_str_lossy();
+        CodegenError::Located {
+            location: format!(
+                "{}:{}:",
+                crate::chunk_id_from_source(&source),
+                self.current_line
+            ),
+            source: Box::new(error),
+        }
+    }
+
+    /// Changes the line of the last emitted instruction.
+    // refer to "luaK_fixline" in lcode.c
+    fn fix_line(&mut self, line: u32) {
+        *self.current_frame().ir_lines.last_mut().unwrap() = line;
+    }
+
+    /// Marks the last `n` local variables as active from the current address.
+    fn activate_local_variables(&mut self, n: usize) {
+        let for_state = self.gc.allocate_string(B("(for state)"));
+        let current = self.current_frame();
+        let start = IrAddress(current.ir_code.len());
+        let first = current.local_variable_stack.len() - n;
+        for var in &current.local_variable_stack[first..] {
+            if var.register().is_some() {
+                current.local_variable_ranges.push(LocalVariableRange {
+                    name: var.name.unwrap_or(for_state),
+                    start,
+                    end: None,
+                });
+            }
+        }
     }
 
     fn declare_label(&mut self) -> Label {
@@ -294,59 +507,215 @@ impl<'gc> CodeGenerator<'gc> {
         current.label_ir_addresses[label.0] = Some(IrAddress(current.ir_code.len()));
     }
 
-    fn push_loop(&mut self) {
-        self.loops.push(Default::default());
+    fn current_scope(&mut self) -> &mut Scope {
+        self.current_frame().scopes.last_mut().unwrap()
     }
 
-    fn pop_loop(&mut self) -> Result<(), CodegenError> {
-        if let Some(label) = self.loops.pop().unwrap().break_label {
-            self.place_label_here(label)
+    fn enter_scope(&mut self, is_loop: bool) {
+        let current = self.current_frame();
+        let scope = Scope {
+            num_active_local_vars: current.local_variable_stack.len(),
+            first_visible_label: current.visible_labels.len(),
+            first_pending_goto: current.pending_gotos.len(),
+            is_loop,
+            has_captured_local_vars: false,
+        };
+        current.scopes.push(scope);
+    }
+
+    fn leave_scope(&mut self) -> Result<(), CodegenError> {
+        let current = self.current_frame();
+        let scope = current.scopes.pop().unwrap();
+        let level = current.register_level(scope.num_active_local_vars);
+        let num_removed = current.local_variable_stack[scope.num_active_local_vars..]
+            .iter()
+            .filter(|var| var.register().is_some())
+            .count();
+        let end = IrAddress(current.ir_code.len());
+        current
+            .local_variable_ranges
+            .iter_mut()
+            .rev()
+            .filter(|range| range.end.is_none())
+            .take(num_removed)
+            .for_each(|range| range.end = Some(end));
+        current
+            .local_variable_stack
+            .truncate(scope.num_active_local_vars);
+
+        let mut has_closed_upvalues = false;
+        if scope.is_loop {
+            let label = self.declare_label();
+            self.place_label_here(label);
+            let name = self.gc.allocate_string(B("break"));
+            if self.solve_pending_gotos(
+                name,
+                label,
+                scope.num_active_local_vars,
+                scope.first_pending_goto,
+            )? {
+                self.emit(IrInstruction::Close { base: level });
+                has_closed_upvalues = true;
+            }
+        }
+
+        let is_nested = !self.current_frame().scopes.is_empty();
+        if is_nested && scope.has_captured_local_vars && !has_closed_upvalues {
+            self.emit(IrInstruction::Close { base: level });
         }
+
+        let current = self.current_frame();
+        current.visible_labels.truncate(scope.first_visible_label);
+        let pending_gotos = &mut current.pending_gotos[scope.first_pending_goto..];
+        if is_nested {
+            for goto in pending_gotos {
+                if goto.num_active_local_vars > scope.num_active_local_vars {
+                    goto.needs_to_close_upvalues |= scope.has_captured_local_vars;
+                }
+                goto.num_active_local_vars = scope.num_active_local_vars;
+            }
+        } else if let Some(goto) = pending_gotos.first() {
+            let error = if goto.name.as_ref() == b"break" {
+                CodegenError::BreakOutsideLoop { line: goto.line }
+            } else {
+                CodegenError::NoVisibleLabel {
+                    label: goto.name.to_str_lossy().into_owned(),
+                    line: goto.line,
+                }
+            };
+            return Err(self.semantic_error(error));
+        }
+
         Ok(())
     }
 
+    /// Resolves pending gotos starting from `first_pending_goto` that jump to
+    /// the label. Returns true if any of them leaves a scope with captured local
+    /// variables.
+    fn solve_pending_gotos(
+        &mut self,
+        name: LuaString<'gc>,
+        label: Label,
+        num_active_local_vars: usize,
+        first_pending_goto: usize,
+    ) -> Result<bool, CodegenError> {
+        let current = self.current_frame();
+        let mut needs_to_close_upvalues = false;
+        let mut i = first_pending_goto;
+        while i < current.pending_gotos.len() {
+            if current.pending_gotos[i].name != name {
+                i += 1;
+                continue;
+            }
+            let goto = current.pending_gotos.remove(i);
+            if goto.num_active_local_vars < num_active_local_vars {
+                let error = CodegenError::JumpIntoScopeOfLocal {
+                    label: name.to_str_lossy().into_owned(),
+                    line: goto.line,
+                    local: current.local_variable_name(goto.num_active_local_vars),
+                };
+                return Err(self.semantic_error(error));
+            }
+            needs_to_close_upvalues |= goto.needs_to_close_upvalues;
+            current.label_ir_addresses[goto.label.0] = current.label_ir_addresses[label.0];
+        }
+        Ok(needs_to_close_upvalues)
+    }
+
+    fn emit_pending_goto(&mut self, name: LuaString<'gc>) {
+        let label = self.declare_label();
+        self.emit(IrInstruction::Jump { target: label });
+        let line = self.current_line;
+        let current = self.current_frame();
+        let goto = PendingGoto {
+            name,
+            line,
+            label,
+            num_active_local_vars: current.local_variable_stack.len(),
+            needs_to_close_upvalues: false,
+        };
+        current.pending_gotos.push(goto);
+    }
+
     fn resolve_name(&mut self, name: LuaString<'gc>) -> Result<LazyLValue, CodegenError> {
+        if let Some(constant) = self.find_constant(name) {
+            return Ok(self.discharge_to_new_register(constant)?.into());
+        }
         match self.try_resolve_name(name)? {
             Some(LValue::Register(r)) => Ok(r.into()),
             Some(LValue::Upvalue(u)) => Ok(u.into()),
             None => {
-                let env = match self.try_resolve_name(self.gc.allocate_string(LUA_ENV))? {
-                    Some(env) => env,
-                    None => unreachable!(),
-                };
+                let env = self.resolve_name(self.gc.allocate_string(LUA_ENV))?;
                 self.resolve_table_field(env, name)
             }
         }
     }
 
-    fn try_resolve_name(&mut self, name: LuaString) -> Result<Option<LValue>, CodegenError> {
+    /// Finds the innermost local variable with the name, including ones in
+    /// enclosing functions.
+    fn find_local_variable(&self, name: LuaString) -> Option<&LocalVariable<'gc>> {
+        self.frames.iter().rev().find_map(|frame| {
+            frame
+                .local_variable_stack
+                .iter()
+                .rfind(|var| var.name == Some(name))
+        })
+    }
+
+    fn find_constant(&self, name: LuaString) -> Option<Value<'gc>> {
+        match self.find_local_variable(name) {
+            Some(LocalVariable {
+                kind: LocalVariableKind::Constant(constant),
+                ..
+            }) => Some(*constant),
+            _ => None,
+        }
+    }
+
+    fn check_readonly(&self, name: LuaString) -> Result<(), CodegenError> {
+        if self
+            .find_local_variable(name)
+            .is_some_and(LocalVariable::is_readonly)
+        {
+            let error = CodegenError::AssignToConstVariable(name.to_str_lossy().into_owned());
+            Err(self.semantic_error(error))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn try_resolve_name(&mut self, name: LuaString<'gc>) -> Result<Option<LValue>, CodegenError> {
         self.try_resolve_name_at_level(name, self.frames.len() - 1)
     }
 
     fn try_resolve_name_at_level(
         &mut self,
-        name: LuaString,
+        name: LuaString<'gc>,
         level: usize,
     ) -> Result<Option<LValue>, CodegenError> {
-        if let Some((_, register)) = self.frames[level]
+        if let Some(var) = self.frames[level]
             .local_variable_stack
             .iter()
-            .rfind(|(n, _)| *n == Some(name))
+            .rfind(|var| var.name == Some(name))
         {
-            return Ok(Some((*register).into()));
+            return match var.kind {
+                LocalVariableKind::Register { register, .. } => Ok(Some(register.into())),
+                // constants are resolved before reaching here
+                LocalVariableKind::Constant(_) => unreachable!(),
+            };
         }
 
         if level > 0 {
             return match self.try_resolve_name_at_level(name, level - 1)? {
                 Some(LValue::Register(index)) => {
                     let desc = UpvalueDescription::Register(index);
-                    let index = self.frames[level].allocate_upvalue(desc)?;
-                    self.frames[level - 1].needs_to_close_upvalues = true;
+                    let index = self.frames[level].allocate_upvalue(desc, name)?;
+                    self.frames[level - 1].capture_local_variable(name);
                     Ok(Some(LValue::Upvalue(index)))
                 }
                 Some(LValue::Upvalue(index)) => {
                     let desc = UpvalueDescription::Upvalue(index);
-                    let index = self.frames[level].allocate_upvalue(desc)?;
+                    let index = self.frames[level].allocate_upvalue(desc, name)?;
                     Ok(Some(LValue::Upvalue(index)))
                 }
                 None => Ok(None),
@@ -355,7 +724,7 @@ impl<'gc> CodeGenerator<'gc> {
 
         if name.as_ref() == LUA_ENV {
             let desc = UpvalueDescription::Upvalue(UpvalueIndex(0));
-            let index = self.frames[0].allocate_upvalue(desc)?;
+            let index = self.frames[0].allocate_upvalue(desc, name)?;
             Ok(Some(LValue::Upvalue(index)))
         } else {
             Ok(None)
@@ -473,24 +842,29 @@ impl<'gc> CodeGenerator<'gc> {
 
     fn emit_function(&mut self, expr: FunctionExpression<'gc>) -> Result<ProtoIndex, CodegenError> {
         self.enter_frame();
+        self.current_line = expr.line_defined;
 
         let num_fixed_args = expr.params.len().try_into().unwrap();
         let current = self.current_frame();
         current.num_fixed_args = num_fixed_args;
         current.is_vararg = expr.is_vararg;
-        if expr.is_vararg {
-            self.emit(IrInstruction::PrepareVarArg { num_fixed_args });
-        }
+        current.lines_defined = Some(expr.line_defined..=expr.last_line_defined);
 
         for param in expr.params {
             let register = self.allocate_register()?;
             self.current_frame()
                 .local_variable_stack
-                .push((Some(param), register));
+                .push((Some(param), register).into());
+        }
+        self.activate_local_variables(num_fixed_args as usize);
+
+        if expr.is_vararg {
+            self.emit(IrInstruction::PrepareVarArg { num_fixed_args });
         }
 
         let has_return = expr.body.return_statement.is_some();
-        self.codegen_block(expr.body)?;
+        self.codegen_statement_list(expr.body, true)?;
+        self.current_line = expr.last_line_defined;
         if !has_return {
             let Frame {
                 num_fixed_args,
@@ -516,6 +890,7 @@ impl<'gc> CodeGenerator<'gc> {
         callee: impl Into<LazyRValue<'gc>>,
         args: FunctionArguments<'gc>,
         dest: RegisterIndex,
+        line: u32,
     ) -> Result<(), CodegenError> {
         self.discharge_to_register(callee, dest)?;
         let num_fixed_args = self.emit_func_args(args, RegisterIndex(dest.0 + 1))?;
@@ -523,6 +898,7 @@ impl<'gc> CodeGenerator<'gc> {
             callee: dest,
             num_fixed_args,
         });
+        self.fix_line(line);
         self.current_frame().register_top = RegisterIndex(dest.0 + 1);
         Ok(())
     }
@@ -533,6 +909,7 @@ impl<'gc> CodeGenerator<'gc> {
         name: LuaString<'gc>,
         args: FunctionArguments<'gc>,
         dest: RegisterIndex,
+        line: u32,
     ) -> Result<(), CodegenError> {
         let table = self.discharge_to_any_register(table)?;
         let key = self.discharge_to_rk(name)?;
@@ -544,6 +921,7 @@ impl<'gc> CodeGenerator<'gc> {
             callee: dest,
             num_fixed_args: num_fixed_args.map(|n| n + 1),
         });
+        self.fix_line(line);
 
         self.current_frame().register_top = RegisterIndex(dest.0 + 1);
         Ok(())
@@ -650,15 +1028,17 @@ impl<'gc> CodeGenerator<'gc> {
 
         let last_value = values.pop();
         for value in values {
+            let dest = self.current_frame().register_top;
             let value = self.evaluate_expr(value)?;
-            let register = self.discharge_to_new_register(value)?;
+            let register = self.discharge_to_next_register(value, dest)?;
             registers.push(register);
         }
 
         if let Some(last_value) = last_value {
+            let dest = self.current_frame().register_top;
             let value = self.evaluate_expr(last_value)?;
             if value.may_have_multiple_values() {
-                let base = self.discharge_to_new_register(value)?;
+                let base = self.discharge_to_next_register(value, dest)?;
                 if let Some(num_extra) = num_expected.checked_sub(num_values) {
                     for i in 0..=num_extra {
                         let i: u8 = i.try_into().unwrap();
@@ -667,7 +1047,7 @@ impl<'gc> CodeGenerator<'gc> {
                     }
                 }
             } else {
-                let register = self.discharge_to_new_register(value)?;
+                let register = self.discharge_to_next_register(value, dest)?;
                 registers.push(register);
             }
         }
@@ -756,18 +1136,20 @@ impl<'gc> CodeGenerator<'gc> {
                 callee,
                 args,
                 may_return_multiple_values: _,
+                line,
             } => {
-                self.emit_func_call(*callee, args, dest)?;
+                self.emit_func_call(*callee, args, dest, line)?;
             }
             LazyRValue::MethodCall {
                 table,
                 name,
                 args,
                 may_return_multiple_values: _,
+                line,
             } => {
-                self.emit_method_call(*table, name, args, dest)?;
+                self.emit_method_call(*table, name, args, dest, line)?;
             }
-            LazyRValue::UnaryOp { op, inner } => {
+            LazyRValue::UnaryOp { op, inner, line } => {
                 let operand = if let LazyRValue::LValue(LazyLValue::Register(lhs)) = *inner {
                     lhs
                 } else {
@@ -775,12 +1157,14 @@ impl<'gc> CodeGenerator<'gc> {
                     dest
                 };
                 self.emit(IrInstruction::UnaryOp { op, dest, operand });
+                self.fix_line(line);
             }
             LazyRValue::BinaryOp {
                 op,
                 lhs,
                 rhs,
                 flipped,
+                line,
             } => {
                 if op == BinaryOp::Concat {
                     let lhs = if dest.0 + 1 == self.current_frame().register_top.0 {
@@ -792,6 +1176,7 @@ impl<'gc> CodeGenerator<'gc> {
                     self.ensure_register_window(lhs, 2)?;
                     self.discharge_to_register(*rhs, RegisterIndex(lhs.0 + 1))?;
                     self.emit(IrInstruction::Concatenate { dest: lhs });
+                    self.fix_line(line);
                     if lhs != dest {
                         self.emit(IrInstruction::Move { dest, source: lhs });
                     }
@@ -810,108 +1195,8 @@ impl<'gc> CodeGenerator<'gc> {
                     }
                 };
 
-                if let LazyRValue::Constant(constant) = rhs {
-                    match constant {
-                        Value::Integer(i) => {
-                            match op {
-                                BinaryOp::Add | BinaryOp::Shr => {
-                                    if let Ok(rhs) = i.try_into() {
-                                        self.emit(IrInstruction::BinaryOpImmediate {
-                                            op,
-                                            dest,
-                                            lhs,
-                                            rhs,
-                                            metamethod: op.metamethod(),
-                                            flipped,
-                                        });
-                                        return Ok(());
-                                    }
-                                }
-                                BinaryOp::Sub => {
-                                    if let Ok(rhs) = (-i).try_into() {
-                                        self.emit(IrInstruction::BinaryOpImmediate {
-                                            op: BinaryOp::Add,
-                                            dest,
-                                            lhs,
-                                            rhs,
-                                            metamethod: Metamethod::Sub,
-                                            flipped,
-                                        });
-                                        return Ok(());
-                                    }
-                                }
-                                BinaryOp::Shl => {
-                                    if let Ok(rhs) = (-i).try_into() {
-                                        self.emit(IrInstruction::BinaryOpImmediate {
-                                            op: BinaryOp::Shr,
-                                            dest,
-                                            lhs,
-                                            rhs,
-                                            metamethod: Metamethod::Shl,
-                                            flipped,
-                                        });
-                                        return Ok(());
-                                    }
-                                }
-                                _ => (),
-                            }
-
-                            let op_has_constant_variant = matches!(
-                                op,
-                                BinaryOp::Add
-                                    | BinaryOp::Sub
-                                    | BinaryOp::Mul
-                                    | BinaryOp::Div
-                                    | BinaryOp::IDiv
-                                    | BinaryOp::Pow
-                                    | BinaryOp::Mod
-                                    | BinaryOp::BAnd
-                                    | BinaryOp::BXor
-                                    | BinaryOp::BOr
-                            );
-                            if op_has_constant_variant {
-                                if let Some(rhs) = self.try_allocate_rk_constant(constant) {
-                                    self.emit(IrInstruction::BinaryOpConstant {
-                                        op,
-                                        dest,
-                                        lhs,
-                                        rhs,
-                                        flipped,
-                                    });
-                                    return Ok(());
-                                }
-                            }
-                        }
-                        Value::Number(_) => {
-                            let op_has_constant_variant = matches!(
-                                op,
-                                BinaryOp::Add
-                                    | BinaryOp::Sub
-                                    | BinaryOp::Mul
-                                    | BinaryOp::Div
-                                    | BinaryOp::IDiv
-                                    | BinaryOp::Pow
-                                    | BinaryOp::Mod
-                            );
-                            if op_has_constant_variant {
-                                if let Some(rhs) = self.try_allocate_rk_constant(constant) {
-                                    self.emit(IrInstruction::BinaryOpConstant {
-                                        op,
-                                        dest,
-                                        lhs,
-                                        rhs,
-                                        flipped,
-                                    });
-                                    return Ok(());
-                                }
-                            }
-                        }
-                        _ => (),
-                    }
-                }
-
-                let rhs = self.discharge_to_any_register(rhs)?;
-                self.emit(IrInstruction::BinaryOp { op, dest, lhs, rhs });
+                self.emit_arithmetic(op, dest, lhs, rhs, flipped)?;
+                self.fix_line(line);
             }
             LazyRValue::ShortCircuit { op, lhs, rhs } => {
                 let source = self.discharge_to_any_register(*lhs)?;
@@ -951,6 +1236,128 @@ impl<'gc> CodeGenerator<'gc> {
         Ok(())
     }
 
+    fn emit_arithmetic(
+        &mut self,
+        op: BinaryOp,
+        dest: RegisterIndex,
+        lhs: RegisterIndex,
+        rhs: LazyRValue<'gc>,
+        flipped: bool,
+    ) -> Result<(), CodegenError> {
+        if let LazyRValue::Constant(constant) = rhs {
+            match constant {
+                Value::Integer(i) => {
+                    match op {
+                        BinaryOp::Add | BinaryOp::Shr => {
+                            if let Ok(rhs) = i.try_into() {
+                                self.emit(IrInstruction::BinaryOpImmediate {
+                                    op,
+                                    dest,
+                                    lhs,
+                                    rhs,
+                                    metamethod: op.metamethod(),
+                                    metamethod_rhs: rhs,
+                                    flipped,
+                                });
+                                return Ok(());
+                            }
+                        }
+                        BinaryOp::Sub => {
+                            // refer to "finishbinexpneg" in lcode.c
+                            if let (Ok(rhs), Ok(metamethod_rhs)) =
+                                (i.wrapping_neg().try_into(), i.try_into())
+                            {
+                                self.emit(IrInstruction::BinaryOpImmediate {
+                                    op: BinaryOp::Add,
+                                    dest,
+                                    lhs,
+                                    rhs,
+                                    metamethod: Metamethod::Sub,
+                                    metamethod_rhs,
+                                    flipped,
+                                });
+                                return Ok(());
+                            }
+                        }
+                        BinaryOp::Shl => {
+                            // refer to "finishbinexpneg" in lcode.c
+                            if let (Ok(rhs), Ok(metamethod_rhs)) =
+                                (i.wrapping_neg().try_into(), i.try_into())
+                            {
+                                self.emit(IrInstruction::BinaryOpImmediate {
+                                    op: BinaryOp::Shr,
+                                    dest,
+                                    lhs,
+                                    rhs,
+                                    metamethod: Metamethod::Shl,
+                                    metamethod_rhs,
+                                    flipped,
+                                });
+                                return Ok(());
+                            }
+                        }
+                        _ => (),
+                    }
+
+                    let op_has_constant_variant = matches!(
+                        op,
+                        BinaryOp::Add
+                            | BinaryOp::Sub
+                            | BinaryOp::Mul
+                            | BinaryOp::Div
+                            | BinaryOp::IDiv
+                            | BinaryOp::Pow
+                            | BinaryOp::Mod
+                            | BinaryOp::BAnd
+                            | BinaryOp::BXor
+                            | BinaryOp::BOr
+                    );
+                    if op_has_constant_variant {
+                        if let Some(rhs) = self.try_allocate_rk_constant(constant) {
+                            self.emit(IrInstruction::BinaryOpConstant {
+                                op,
+                                dest,
+                                lhs,
+                                rhs,
+                                flipped,
+                            });
+                            return Ok(());
+                        }
+                    }
+                }
+                Value::Number(_) => {
+                    let op_has_constant_variant = matches!(
+                        op,
+                        BinaryOp::Add
+                            | BinaryOp::Sub
+                            | BinaryOp::Mul
+                            | BinaryOp::Div
+                            | BinaryOp::IDiv
+                            | BinaryOp::Pow
+                            | BinaryOp::Mod
+                    );
+                    if op_has_constant_variant {
+                        if let Some(rhs) = self.try_allocate_rk_constant(constant) {
+                            self.emit(IrInstruction::BinaryOpConstant {
+                                op,
+                                dest,
+                                lhs,
+                                rhs,
+                                flipped,
+                            });
+                            return Ok(());
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let rhs = self.discharge_to_any_register(rhs)?;
+        self.emit(IrInstruction::BinaryOp { op, dest, lhs, rhs });
+        Ok(())
+    }
+
     fn discharge_to_new_register(
         &mut self,
         rvalue: impl Into<LazyRValue<'gc>>,
@@ -960,6 +1367,26 @@ impl<'gc> CodeGenerator<'gc> {
         Ok(register)
     }
 
+    /// Discharges `rvalue`, evaluated while `dest` was the first free
+    /// register, into `dest`. This keeps the values assigned to new local
+    /// variables in consecutive registers, whatever temporaries they needed.
+    fn discharge_to_next_register(
+        &mut self,
+        rvalue: impl Into<LazyRValue<'gc>>,
+        dest: RegisterIndex,
+    ) -> Result<RegisterIndex, CodegenError> {
+        let rvalue = rvalue.into();
+        let current = self.current_frame();
+        if current.register_top == dest || rvalue.can_discharge_over_temporaries() {
+            current.register_top = dest;
+            return self.discharge_to_new_register(rvalue);
+        }
+        let source = self.discharge_to_new_register(rvalue)?;
+        self.emit(IrInstruction::Move { dest, source });
+        self.current_frame().register_top = RegisterIndex(dest.0 + 1);
+        Ok(dest)
+    }
+
     fn discharge_to_any_register(
         &mut self,
         rvalue: impl Into<LazyRValue<'gc>>,
@@ -1080,4 +1507,68 @@ impl BinaryOp {
             _ => unreachable!(),
         }
     }
+
+    /// Evaluates the operation on numeric constants at compile time. Returns
+    /// `None` if the operation cannot be folded safely, e.g. it would raise an
+    /// error or produce NaN or -0.0.
+    fn fold<'gc>(&self, lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
+        let is_numeric = |value| matches!(value, Value::Integer(_) | Value::Number(_));
+        if !is_numeric(lhs) || !is_numeric(rhs) {
+            return None;
+        }
+        let result = match self {
+            Self::Add => ops::arithmetic(lhs, rhs, Integer::wrapping_add, Number::add)?,
+            Self::Sub => ops::arithmetic(lhs, rhs, Integer::wrapping_sub, Number::sub)?,
+            Self::Mul => ops::arithmetic(lhs, rhs, Integer::wrapping_mul, Number::mul)?,
+            Self::Pow | Self::Div | Self::IDiv | Self::Mod => {
+                let x = lhs.to_number_without_string_coercion()?;
+                let y = rhs.to_number_without_string_coercion()?;
+                match self {
+                    Self::Pow => Value::Number(x.powf(y)),
+                    _ if y == 0.0 => return None,
+                    Self::Div => Value::Number(x / y),
+                    Self::IDiv => ops::division(lhs, rhs, ops::idivi, ops::idivf).ok()??,
+                    Self::Mod => ops::division(lhs, rhs, ops::modi, ops::modf).ok()??,
+                    _ => unreachable!(),
+                }
+            }
+            Self::BAnd | Self::BXor | Self::BOr | Self::Shl | Self::Shr => {
+                let x = lhs.to_integer_without_string_coercion()?;
+                let y = rhs.to_integer_without_string_coercion()?;
+                Value::Integer(match self {
+                    Self::BAnd => x & y,
+                    Self::BXor => x ^ y,
+                    Self::BOr => x | y,
+                    Self::Shl => ops::shl(x, y),
+                    Self::Shr => ops::shr(x, y),
+                    _ => unreachable!(),
+                })
+            }
+            _ => return None,
+        };
+        match result {
+            Value::Number(x) if x.is_nan() || x == 0.0 => None,
+            result => Some(result),
+        }
+    }
+}
+
+impl UnaryOp {
+    /// Evaluates the operation on a constant at compile time. Returns `None`
+    /// if the operation cannot be folded safely.
+    fn fold<'gc>(&self, value: Value<'gc>) -> Option<Value<'gc>> {
+        let result = match (self, value) {
+            (Self::Not, value) => Value::Boolean(!value.to_boolean()),
+            (Self::Unm, Value::Integer(i)) => Value::Integer(i.wrapping_neg()),
+            (Self::Unm, Value::Number(x)) => Value::Number(-x),
+            (Self::BNot, value @ (Value::Integer(_) | Value::Number(_))) => {
+                Value::Integer(!value.to_integer_without_string_coercion()?)
+            }
+            _ => return None,
+        };
+        match result {
+            Value::Number(x) if x.is_nan() || x == 0.0 => None,
+            result => Some(result),
+        }
+    }
 }
@@ -1,6 +1,7 @@
 use super::{
     ir::{IrInstruction, RkIndex},
-    CodeGenerator, CodegenError, Frame, LValue, LazyLValue, LazyRValue,
+    CodeGenerator, CodegenError, Frame, LValue, LazyLValue, LazyRValue, LocalVariable,
+    LocalVariableKind, VisibleLabel,
 };
 use crate::{
     parser::ast::{
@@ -12,14 +13,15 @@ use crate::{
     },
     types::{Integer, LuaString, RegisterIndex, Value},
 };
-use bstr::B;
+use bstr::{ByteSlice, B};
 use std::num::NonZeroU8;
 
 impl<'gc> CodeGenerator<'gc> {
     pub fn codegen_chunk(&mut self, chunk: Chunk<'gc>) -> Result<(), CodegenError> {
         self.emit(IrInstruction::PrepareVarArg { num_fixed_args: 0 });
-        let has_return = chunk.0.return_statement.is_some();
-        self.codegen_block(chunk.0)?;
+        let has_return = chunk.block.return_statement.is_some();
+        self.codegen_statement_list(chunk.block, true)?;
+        self.current_line = chunk.last_line;
         if !has_return {
             let Frame {
                 num_fixed_args,
@@ -43,14 +45,43 @@ impl<'gc> CodeGenerator<'gc> {
     }
 
     pub fn codegen_block(&mut self, block: Block<'gc>) -> Result<(), CodegenError> {
-        for statement in block.statements {
-            self.codegen_statement(statement)?;
+        self.enter_scope(false);
+        self.codegen_statement_list(block, true)?;
+        self.leave_scope()
+    }
+
+    /// Generates code for statements of the block in the current scope.
+    /// `scope_ends_with_block` is false if the scope continues after the block
+    /// (e.g. `until` condition of `repeat` statement).
+    pub fn codegen_statement_list(
+        &mut self,
+        block: Block<'gc>,
+        scope_ends_with_block: bool,
+    ) -> Result<(), CodegenError> {
+        // labels followed only by other labels until the end of the scope are
+        // considered to be outside the scope of preceding local variables
+        let num_statements = block.statements.len();
+        let num_trailing_labels = if scope_ends_with_block && block.return_statement.is_none() {
+            block
+                .statements
+                .iter()
+                .rev()
+                .take_while(|(statement, _)| matches!(statement, Statement::Label(_)))
+                .count()
+        } else {
+            0
+        };
+        for (i, (statement, line)) in block.statements.into_iter().enumerate() {
+            let is_at_end_of_scope = i >= num_statements - num_trailing_labels;
+            self.current_line = line;
+            self.codegen_statement(statement, is_at_end_of_scope)?;
         }
         if let Some(mut return_statement) = block.return_statement {
-            let (base, count) = match return_statement.0.len() {
+            self.current_line = return_statement.line;
+            let (base, count) = match return_statement.values.len() {
                 0 => (RegisterIndex(0), Some(0)),
                 1 => {
-                    let expr = return_statement.0.pop().unwrap();
+                    let expr = return_statement.values.pop().unwrap();
                     let value = self.evaluate_expr(expr)?;
                     let count = (!value.may_have_multiple_values()).then_some(1);
                     let base = self.discharge_to_any_register(value)?;
@@ -58,7 +89,7 @@ impl<'gc> CodeGenerator<'gc> {
                 }
                 _ => {
                     let base = self.allocate_register()?;
-                    let count = self.emit_open_expr_list(return_statement.0, base)?;
+                    let count = self.emit_open_expr_list(return_statement.values, base)?;
                     (base, count.map(|c| c.try_into().unwrap()))
                 }
             };
@@ -109,7 +140,8 @@ impl<'gc> CodeGenerator<'gc> {
         let mut next_index_offset = 0;
         let mut num_pending_fields = 0;
 
-        let mut emit_field = |field| -> Result<(), CodegenError> {
+        let mut emit_field = |(field, line)| -> Result<(), CodegenError> {
+            self.advance_line(line);
             match field {
                 TableField::List(expr) => {
                     num_pending_fields += 1;
@@ -140,13 +172,15 @@ impl<'gc> CodeGenerator<'gc> {
             Ok(())
         };
 
-        let last_field = expr.0.pop();
-        for field in expr.0 {
+        let last_line = expr.last_line;
+        let last_field = expr.fields.pop();
+        for field in expr.fields {
             emit_field(field)?;
         }
 
         match last_field {
-            Some(TableField::List(expr)) => {
+            Some((TableField::List(expr), line)) => {
+                self.advance_line(line);
                 num_pending_fields += 1;
                 self.ensure_register_window(table, num_pending_fields as usize + 1)?;
                 let value = self.evaluate_expr(expr)?;
@@ -154,6 +188,7 @@ impl<'gc> CodeGenerator<'gc> {
                 self.discharge_to_register(value, RegisterIndex(table.0 + num_pending_fields))?;
 
                 if is_multi {
+                    self.current_line = last_line;
                     self.emit(IrInstruction::SetList {
                         table,
                         count: None,
@@ -167,6 +202,7 @@ impl<'gc> CodeGenerator<'gc> {
         }
 
         if num_pending_fields > 0 {
+            self.current_line = last_line;
             self.emit(IrInstruction::SetList {
                 table,
                 count: NonZeroU8::new(num_pending_fields),
@@ -207,7 +243,11 @@ impl<'gc> CodeGenerator<'gc> {
         Ok(result)
     }
 
-    fn codegen_statement(&mut self, statement: Statement<'gc>) -> Result<(), CodegenError> {
+    fn codegen_statement(
+        &mut self,
+        statement: Statement<'gc>,
+        is_at_end_of_scope: bool,
+    ) -> Result<(), CodegenError> {
         match statement {
             Statement::If(s) => self.codegen_if_statement(s)?,
             Statement::While(s) => self.codegen_while_statement(s)?,
@@ -217,9 +257,9 @@ impl<'gc> CodeGenerator<'gc> {
             Statement::Function(s) => self.codegen_func_statement(s)?,
             Statement::LocalFunction(s) => self.codegen_local_func_statement(s)?,
             Statement::LocalVariable(s) => self.codegen_local_variable_statement(s)?,
-            Statement::Label(_) => todo!("label"),
+            Statement::Label(name) => self.codegen_label_statement(name, is_at_end_of_scope)?,
             Statement::Break => self.codegen_break_statement()?,
-            Statement::Goto(_) => todo!("goto"),
+            Statement::Goto(name) => self.codegen_goto_statement(name)?,
             Statement::FunctionCall(s) => self.codegen_func_call_statement(s)?,
             Statement::Assignment(s) => self.codegen_assignment_statement(s)?,
         };
@@ -228,7 +268,8 @@ impl<'gc> CodeGenerator<'gc> {
         current.register_top.0 = current
             .local_variable_stack
             .iter()
-            .map(|(_, i)| i.0)
+            .filter_map(LocalVariable::register)
+            .map(|i| i.0)
             .max()
             .map(|i| i + 1)
             .unwrap_or_default();
@@ -236,23 +277,92 @@ impl<'gc> CodeGenerator<'gc> {
         Ok(())
     }
 
-    fn codegen_break_statement(&mut self) -> Result<(), CodegenError> {
-        let break_label = self
-            .loops
-            .last_mut()
-            .ok_or(CodegenError::BreakOutsideLoop)?
-            .break_label;
-        let break_label = match break_label {
-            Some(label) => label,
-            None => {
-                let label = self.declare_label();
-                self.loops.last_mut().unwrap().break_label.replace(label);
-                label
-            }
+    fn codegen_label_statement(
+        &mut self,
+        name: LuaString<'gc>,
+        is_at_end_of_scope: bool,
+    ) -> Result<(), CodegenError> {
+        let line = self.current_line;
+        let current = self.current_frame();
+        if let Some(label) = current
+            .visible_labels
+            .iter()
+            .find(|label| label.name == name)
+        {
+            let error = CodegenError::DuplicateLabel {
+                label: name.to_str_lossy().into_owned(),
+                line: label.line,
+            };
+            return Err(self.semantic_error(error));
+        }
+
+        let num_active_local_vars = if is_at_end_of_scope {
+            current.scopes.last().unwrap().num_active_local_vars
+        } else {
+            current.local_variable_stack.len()
         };
-        self.emit(IrInstruction::Jump {
-            target: break_label,
+        let label = self.declare_label();
+        self.place_label_here(label);
+        self.current_frame().visible_labels.push(VisibleLabel {
+            name,
+            line,
+            label,
+            num_active_local_vars,
         });
+
+        let first_pending_goto = self.current_scope().first_pending_goto;
+        if self.solve_pending_gotos(name, label, num_active_local_vars, first_pending_goto)? {
+            let current = self.current_frame();
+            let base = current.register_level(current.local_variable_stack.len());
+            self.emit(IrInstruction::Close { base });
+        }
+        Ok(())
+    }
+
+    fn codegen_goto_statement(&mut self, name: LuaString<'gc>) -> Result<(), CodegenError> {
+        let current = self.current_frame();
+        let label = current
+            .visible_labels
+            .iter()
+            .rfind(|label| label.name == name);
+        if let Some(label) = label {
+            // backward jump
+            let target = label.label;
+            let num_active_local_vars = label.num_active_local_vars;
+            let leaves_captured_local_vars = current.scopes.iter().enumerate().any(|(i, scope)| {
+                let end = current
+                    .scopes
+                    .get(i + 1)
+                    .map_or(current.local_variable_stack.len(), |next| {
+                        next.num_active_local_vars
+                    });
+                scope.has_captured_local_vars && end > num_active_local_vars
+            });
+            if leaves_captured_local_vars {
+                let base = current.register_level(num_active_local_vars);
+                self.emit(IrInstruction::Close { base });
+            }
+            self.emit(IrInstruction::Jump { target });
+        } else {
+            self.emit_pending_goto(name);
+        }
+        Ok(())
+    }
+
+    fn codegen_break_statement(&mut self) -> Result<(), CodegenError> {
+        if !self
+            .current_frame()
+            .scopes
+            .iter()
+            .any(|scope| scope.is_loop)
+        {
+            let error = CodegenError::BreakOutsideLoop {
+                line: self.current_line,
+            };
+            return Err(self.semantic_error(error));
+        }
+        let name = self.gc.allocate_string(B("break"));
+        self.emit_pending_goto(name);
         Ok(())
     }
 
@@ -299,22 +409,21 @@ impl<'gc> CodeGenerator<'gc> {
         let start_label = self.declare_label();
         self.place_label_here(start_label);
 
-        self.push_loop();
-        let result = self.emit_test_then_block_else_fallthrough(
+        self.enter_scope(true);
+        self.emit_test_then_block_else_fallthrough(
             statement.condition,
             statement.body,
             start_label,
-        );
-        self.pop_loop()?;
-
-        result
+        )?;
+        self.leave_scope()
     }
 
     fn codegen_for_statement(&mut self, statement: ForStatement<'gc>) -> Result<(), CodegenError> {
-        let prev_num_local_vars = self.current_frame().local_variable_stack.len();
+        let line = self.current_line;
+        self.enter_scope(true);
         let base = self.allocate_register()?;
 
-        let (is_generic, body) = match statement {
+        let (is_generic, control_variables, body) = match statement {
             ForStatement::Numerical {
                 control,
                 initial_value,
@@ -325,7 +434,7 @@ impl<'gc> CodeGenerator<'gc> {
                 let init_register = base;
                 self.current_frame()
                     .local_variable_stack
-                    .push((None, init_register));
+                    .push((None, init_register).into());
                 let initial_value = self.evaluate_expr(*initial_value)?;
                 self.discharge_to_register(initial_value, init_register)?;
 
@@ -333,7 +442,7 @@ impl<'gc> CodeGenerator<'gc> {
                 let limit_register = RegisterIndex(base.0 + 1);
                 self.current_frame()
                     .local_variable_stack
-                    .push((None, limit_register));
+                    .push((None, limit_register).into());
                 let limit = self.evaluate_expr(*limit)?;
                 self.discharge_to_register(limit, limit_register)?;
 
@@ -341,21 +450,19 @@ impl<'gc> CodeGenerator<'gc> {
                 let step_register = RegisterIndex(base.0 + 2);
                 self.current_frame()
                     .local_variable_stack
-                    .push((None, step_register));
+                    .push((None, step_register).into());
                 let step = if let Some(step) = step {
                     self.evaluate_expr(*step)?
                 } else {
                     1.into()
                 };
                 self.discharge_to_register(step, step_register)?;
+                self.activate_local_variables(3);
 
                 self.ensure_register_window(base, 4)?;
                 let control_register = RegisterIndex(base.0 + 3);
-                self.current_frame()
-                    .local_variable_stack
-                    .push((Some(control), control_register));
 
-                (false, body)
+                (false, vec![(control, control_register)], body)
             }
             ForStatement::Generic {
                 variables,
@@ -374,17 +481,23 @@ impl<'gc> CodeGenerator<'gc> {
                     self.discharge_to_register(expr_rvalue, register)?;
                     self.current_frame()
                         .local_variable_stack
-                        .push((None, register));
+                        .push((None, register).into());
                 }
 
+                self.activate_local_variables(4);
+
+                // the fourth value is closed when the loop ends
+                self.current_scope().has_captured_local_vars = true;
+                self.current_frame().needs_to_close_upvalues = true;
+
                 self.ensure_register_window(base, 4 + variables.len())?;
-                for (i, variable) in variables.into_iter().enumerate() {
-                    self.current_frame()
-                        .local_variable_stack
-                        .push((Some(variable), RegisterIndex(base.0 + 4 + i as u8)));
-                }
+                let control_variables = variables
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, variable)| (variable, RegisterIndex(base.0 + 4 + i as u8)))
+                    .collect();
 
-                (true, body)
+                (true, control_variables, body)
             }
         };
 
@@ -398,12 +511,21 @@ impl<'gc> CodeGenerator<'gc> {
         let start_label = self.declare_label();
         self.place_label_here(start_label);
 
-        self.push_loop();
+        self.enter_scope(false);
+        let num_control_variables = control_variables.len();
+        for (variable, register) in control_variables {
+            self.current_frame()
+                .local_variable_stack
+                .push((Some(variable), register).into());
+        }
+        self.activate_local_variables(num_control_variables);
         self.codegen_block(body)?;
+        self.leave_scope()?;
         self.place_label_here(end_label);
 
         if is_generic {
             self.emit(IrInstruction::GenericForCall { base });
+            self.fix_line(line);
         }
 
         self.emit(IrInstruction::ForLoop {
@@ -411,13 +533,9 @@ impl<'gc> CodeGenerator<'gc> {
             next_target: start_label,
             is_generic,
         });
-        self.pop_loop()?;
+        self.fix_line(line);
 
-        self.current_frame()
-            .local_variable_stack
-            .truncate(prev_num_local_vars);
-
-        Ok(())
+        self.leave_scope()
     }
 
     fn codegen_repeat_statement(
@@ -427,17 +545,18 @@ impl<'gc> CodeGenerator<'gc> {
         let start_label = self.declare_label();
         self.place_label_here(start_label);
 
-        self.push_loop();
-        let result = self.codegen_block(statement.body);
-        self.pop_loop()?;
-        result?;
+        self.enter_scope(true);
+        self.enter_scope(false);
+        self.codegen_statement_list(statement.body, false)?;
 
+        // the condition can refer to local variables declared in the body
         let condition = self.evaluate_expr(statement.condition)?;
-        match condition {
-            LazyRValue::Constant(Value::Nil | Value::Boolean(false)) => (),
-            LazyRValue::Constant(_) | LazyRValue::Proto(_) => return Ok(()),
+        let jumps_back = match condition {
+            LazyRValue::Constant(Value::Nil | Value::Boolean(false)) => true,
+            LazyRValue::Constant(_) | LazyRValue::Proto(_) => false,
             LazyRValue::Comparison { op, lhs, rhs } => {
                 self.emit_comparison(op, *lhs, *rhs, false)?;
+                true
             }
             condition => {
                 let condition = self.discharge_to_any_register(condition)?;
@@ -445,19 +564,47 @@ impl<'gc> CodeGenerator<'gc> {
                     condition,
                     jump_on: false,
                 });
+                true
             }
+        };
+
+        // repetition must close upvalues of the body
+        let close_upvalues =
+            (jumps_back && self.current_scope().has_captured_local_vars).then(|| {
+                let current = self.current_frame();
+                let level =
+                    current.register_level(current.scopes.last().unwrap().num_active_local_vars);
+                (self.declare_label(), level)
+            });
+        if jumps_back {
+            let target = close_upvalues.map_or(start_label, |(label, _)| label);
+            self.emit(IrInstruction::Jump { target });
+        }
+        self.leave_scope()?;
+
+        if let Some((close_label, base)) = close_upvalues {
+            let exit_label = self.declare_label();
+            self.emit(IrInstruction::Jump { target: exit_label });
+            self.place_label_here(close_label);
+            self.emit(IrInstruction::Close { base });
+            self.emit(IrInstruction::Jump {
+                target: start_label,
+            });
+            self.place_label_here(exit_label);
         }
-        self.emit(IrInstruction::Jump {
-            target: start_label,
-        });
 
-        Ok(())
+        self.leave_scope()
     }
 
     fn codegen_func_statement(
         &mut self,
         mut statement: FunctionStatement<'gc>,
     ) -> Result<(), CodegenError> {
+        let line = self.current_line;
+        if statement.fields.is_empty() && statement.method.is_none() {
+            self.check_readonly(statement.name)?;
+        }
+
         let mut lvalue = self.resolve_name(statement.name)?;
         for field in statement.fields {
             lvalue = self.resolve_table_field(lvalue, field)?;
@@ -473,6 +620,7 @@ impl<'gc> CodeGenerator<'gc> {
 
         let proto = self.emit_function(statement.expression)?;
         self.emit_assignment(lvalue, proto)?;
+        self.fix_line(line);
         Ok(())
     }
 
@@ -483,35 +631,97 @@ impl<'gc> CodeGenerator<'gc> {
         let register = self.allocate_register()?;
         self.current_frame()
             .local_variable_stack
-            .push((Some(statement.name), register));
-        self.codegen_func_statement(statement)
+            .push((Some(statement.name), register).into());
+        let last_line_defined = statement.expression.last_line_defined;
+        self.codegen_func_statement(statement)?;
+
+        // the closure of a local function keeps the line of its `end`
+        self.fix_line(last_line_defined);
+        self.activate_local_variables(1);
+        Ok(())
     }
 
     fn codegen_local_variable_statement(
         &mut self,
-        statement: LocalVariableStatement<'gc>,
+        mut statement: LocalVariableStatement<'gc>,
     ) -> Result<(), CodegenError> {
-        if statement
-            .variables
-            .iter()
-            .any(|var| var.attribute.is_some())
-        {
-            todo!("attribute")
+        let num_variables = statement.variables.len();
+        let mut is_readonly = Vec::with_capacity(num_variables);
+        let mut to_be_closed = None;
+        for (i, variable) in statement.variables.iter().enumerate() {
+            let readonly = match variable.attribute.as_ref().map(AsRef::as_ref) {
+                None => false,
+                Some(b"const") => true,
+                Some(b"close") => {
+                    if to_be_closed.replace(i).is_some() {
+                        return Err(self.semantic_error(CodegenError::MultipleToBeClosedVariables));
+                    }
+                    true
+                }
+                Some(attribute) => {
+                    let error =
+                        CodegenError::UnknownAttribute(attribute.to_str_lossy().into_owned());
+                    return Err(self.semantic_error(error));
+                }
+            };
+            is_readonly.push(readonly);
         }
 
-        let mut value_registers = self
-            .emit_assigned_values(statement.values, statement.variables.len())?
-            .into_iter();
+        // the last <const> variable becomes a compile-time constant if it is
+        // initialized with a constant expression
+        let is_last_const = statement
+            .variables
+            .last()
+            .and_then(|var| var.attribute)
+            .is_some_and(|attribute| attribute.as_ref() == b"const");
+        let mut constant = None;
+        let value_registers = if is_last_const && num_variables == statement.values.len() {
+            let last_value = statement.values.pop().unwrap();
+            let mut registers = self.emit_assigned_values(statement.values, num_variables - 1)?;
+            let dest = self.current_frame().register_top;
+            match self.evaluate_expr(last_value)? {
+                LazyRValue::Constant(value) => constant = Some(value),
+                value => registers.push(self.discharge_to_next_register(value, dest)?),
+            }
+            registers
+        } else {
+            self.emit_assigned_values(statement.values, num_variables)?
+        };
+        let mut value_registers = value_registers.into_iter();
 
-        for variable in statement.variables {
-            let register = if let Some(register) = value_registers.next() {
-                register
-            } else {
-                self.discharge_to_new_register(Value::Nil)?
+        let mut to_be_closed_register = None;
+        for (i, variable) in statement.variables.into_iter().enumerate() {
+            let kind = match constant {
+                Some(constant) if i == num_variables - 1 => LocalVariableKind::Constant(constant),
+                _ => {
+                    let register = if let Some(register) = value_registers.next() {
+                        register
+                    } else {
+                        self.discharge_to_new_register(Value::Nil)?
+                    };
+                    if to_be_closed == Some(i) {
+                        to_be_closed_register = Some(register);
+                    }
+                    LocalVariableKind::Register {
+                        register,
+                        is_readonly: is_readonly[i],
+                    }
+                }
             };
             self.current_frame()
                 .local_variable_stack
-                .push((Some(variable.name), register));
+                .push(LocalVariable {
+                    name: Some(variable.name),
+                    kind,
+                });
+        }
+        self.activate_local_variables(num_variables);
+
+        if let Some(register) = to_be_closed_register {
+            self.emit(IrInstruction::ToBeClosed { register });
+            // to-be-closed variables are closed along with upvalues
+            self.current_scope().has_captured_local_vars = true;
+            self.current_frame().needs_to_close_upvalues = true;
         }
 
         Ok(())
@@ -567,6 +777,7 @@ impl<'gc> CodeGenerator<'gc> {
         &mut self,
         suffixed: SuffixedExpression<'gc>,
     ) -> Result<LazyRValue<'gc>, CodegenError> {
+        self.advance_line(suffixed.line);
         let mut rvalue = self.evaluate_primary(suffixed.primary)?;
         for suffix in suffixed.suffixes {
             rvalue = match suffix {
@@ -584,12 +795,14 @@ impl<'gc> CodeGenerator<'gc> {
                     callee: rvalue.into(),
                     args,
                     may_return_multiple_values: true,
+                    line: suffixed.line,
                 },
                 Suffix::MethodCall { name, args } => LazyRValue::MethodCall {
                     table: rvalue.into(),
                     name,
                     args,
                     may_return_multiple_values: true,
+                    line: suffixed.line,
                 },
             };
         }
@@ -599,24 +812,35 @@ impl<'gc> CodeGenerator<'gc> {
     fn evaluate_primary(&mut self, primary: Primary<'gc>) -> Result<LazyRValue<'gc>, CodegenError> {
         match primary {
             Primary::Name(name) => {
+                if let Some(constant) = self.find_constant(name) {
+                    return Ok(constant.into());
+                }
                 let name = self.resolve_name(name)?;
                 Ok(name.into())
             }
             Primary::Expression(expr) => {
                 let value = self.evaluate_expr(*expr)?;
                 let value = match value {
-                    LazyRValue::FunctionCall { callee, args, .. } => LazyRValue::FunctionCall {
+                    LazyRValue::FunctionCall {
+                        callee, args, line, ..
+                    } => LazyRValue::FunctionCall {
                         callee,
                         args,
                         may_return_multiple_values: false,
+                        line,
                     },
                     LazyRValue::MethodCall {
-                        table, name, args, ..
+                        table,
+                        name,
+                        args,
+                        line,
+                        ..
                     } => LazyRValue::MethodCall {
                         table,
                         name,
                         args,
                         may_return_multiple_values: false,
+                        line,
                     },
                     LazyRValue::VarArg { .. } => LazyRValue::VarArg {
                         may_have_multiple_values: false,
@@ -633,9 +857,15 @@ impl<'gc> CodeGenerator<'gc> {
         expr: UnaryOpExpression<'gc>,
     ) -> Result<LazyRValue<'gc>, CodegenError> {
         let inner = self.evaluate_expr(*expr.inner)?;
+        if let LazyRValue::Constant(value) = inner {
+            if let Some(result) = expr.op.fold(value) {
+                return Ok(result.into());
+            }
+        }
         Ok(LazyRValue::UnaryOp {
             op: expr.op,
             inner: inner.into(),
+            line: expr.line,
         })
     }
 
@@ -656,6 +886,11 @@ impl<'gc> CodeGenerator<'gc> {
         }
 
         let mut rhs = self.evaluate_expr(*expr.rhs)?;
+        if let (LazyRValue::Constant(lhs), LazyRValue::Constant(rhs)) = (&lhs, &rhs) {
+            if let Some(result) = op.fold(*lhs, *rhs) {
+                return Ok(result.into());
+            }
+        }
 
         let op_can_be_flipped = matches!(
             op,
@@ -715,6 +950,7 @@ impl<'gc> CodeGenerator<'gc> {
             lhs: lhs.into(),
             rhs: rhs.into(),
             flipped,
+            line: expr.line,
         })
     }
 
@@ -731,6 +967,7 @@ impl<'gc> CodeGenerator<'gc> {
         &mut self,
         suffixed: SuffixedExpression<'gc>,
     ) -> Result<LazyLValue, CodegenError> {
+        self.advance_line(suffixed.line);
         let mut lvalue = self.resolve_primary(suffixed.primary)?;
         for suffix in suffixed.suffixes {
             lvalue = match suffix {
@@ -738,12 +975,12 @@ impl<'gc> CodeGenerator<'gc> {
                 Suffix::Index(index) => self.resolve_table_index(lvalue, index)?,
                 Suffix::FunctionCall { args } => {
                     let dest = self.allocate_register()?;
-                    self.emit_func_call(lvalue, args, dest)?;
+                    self.emit_func_call(lvalue, args, dest, suffixed.line)?;
                     dest.into()
                 }
                 Suffix::MethodCall { name, args } => {
                     let dest = self.allocate_register()?;
-                    self.emit_method_call(lvalue, name, args, dest)?;
+                    self.emit_method_call(lvalue, name, args, dest, suffixed.line)?;
                     dest.into()
                 }
             };
@@ -760,7 +997,10 @@ impl<'gc> CodeGenerator<'gc> {
 
     fn resolve_variable(&mut self, variable: Variable<'gc>) -> Result<LazyLValue, CodegenError> {
         match variable {
-            Variable::Name(name) => self.resolve_name(name),
+            Variable::Name(name) => {
+                self.check_readonly(name)?;
+                self.resolve_name(name)
+            }
             Variable::TableIndex { table, index } => {
                 let table = self.resolve_suffixed_expr(table)?;
                 self.resolve_table_index(table, index)
@@ -6,7 +6,10 @@ use crate::{
         instruction::{OFFSET_SBX, OFFSET_SC, OFFSET_SJ, UINT17_MAX, UINT25_MAX},
         Instruction, Metamethod, OpCode,
     },
-    types::{Integer, LuaClosureProto, LuaString, RegisterIndex, UpvalueIndex},
+    types::{
+        AbsLineInfo, Integer, LineRange, LocalVariable, LuaClosureProto, LuaString, RegisterIndex,
+        UpvalueIndex,
+    },
 };
 use std::num::NonZeroU8;
 
@@ -205,6 +208,9 @@ pub enum IrInstruction {
         lhs: RegisterIndex,
         rhs: ImmediateI8,
         metamethod: Metamethod,
+        // operand passed to the metamethod, which is the negation of `rhs`
+        // when `x - k` is coded as `x + -k` and `x << k` as `x >> -k`
+        metamethod_rhs: ImmediateI8,
         flipped: bool,
     },
     BinaryOpConstant {
@@ -259,6 +265,12 @@ pub enum IrInstruction {
     Jump {
         target: Label,
     },
+    Close {
+        base: RegisterIndex,
+    },
+    ToBeClosed {
+        register: RegisterIndex,
+    },
     Call {
         callee: RegisterIndex,
         num_fixed_args: Option<u8>,
@@ -313,7 +325,12 @@ pub(super) fn lower_ir<'gc>(
     let mut pending_instructions = Vec::new();
 
     let mut code = Vec::with_capacity(frame.ir_code.len());
-    for (ir_addr, insn) in frame.ir_code.into_iter().enumerate() {
+    let mut lines = Vec::with_capacity(frame.ir_code.len());
+    let mut ir_addr_to_pc = Vec::with_capacity(frame.ir_code.len() + 1);
+    let ir_code = frame.ir_code.into_iter().zip(frame.ir_lines);
+    for (ir_addr, (insn, line)) in ir_code.enumerate() {
+        ir_addr_to_pc.push(code.len());
+        let mut is_jump_target = false;
         for (label, _) in frame
             .label_ir_addresses
             .iter()
@@ -321,6 +338,7 @@ pub(super) fn lower_ir<'gc>(
             .filter(|(_, a)| **a == Some(IrAddress(ir_addr)))
         {
             label_addresses[label] = Some(Address(code.len()));
+            is_jump_target = true;
         }
 
         match insn {
@@ -358,7 +376,8 @@ pub(super) fn lower_ir<'gc>(
             }
             IrInstruction::LoadNil { dest } => match code.last_mut() {
                 Some(prev_insn)
-                    if prev_insn.opcode() == OpCode::LoadNil
+                    if !is_jump_target
+                        && prev_insn.opcode() == OpCode::LoadNil
                         && prev_insn.a() + prev_insn.b() + 1 == dest.0 as usize
                         && prev_insn.b() < u8::MAX as usize =>
                 {
@@ -508,6 +527,7 @@ pub(super) fn lower_ir<'gc>(
                 lhs,
                 rhs,
                 metamethod,
+                metamethod_rhs,
                 flipped,
             } => {
                 let opcode = op.immdiate_opcode();
@@ -518,7 +538,7 @@ pub(super) fn lower_ir<'gc>(
                 code.push(Instruction::from_a_sb_c_k(
                     OpCode::MmBinI,
                     lhs.0,
-                    rhs.0,
+                    metamethod_rhs.0,
                     metamethod as u8,
                     flipped,
                 ));
@@ -663,6 +683,24 @@ pub(super) fn lower_ir<'gc>(
                     move_and_jump_on,
                 ));
             }
+            IrInstruction::Close { base } => {
+                code.push(Instruction::from_a_b_c_k(
+                    OpCode::Close,
+                    base.0,
+                    0,
+                    0,
+                    false,
+                ));
+            }
+            IrInstruction::ToBeClosed { register } => {
+                code.push(Instruction::from_a_b_c_k(
+                    OpCode::Tbc,
+                    register.0,
+                    0,
+                    0,
+                    false,
+                ));
+            }
             IrInstruction::Call {
                 callee,
                 num_fixed_args,
@@ -769,7 +807,9 @@ pub(super) fn lower_ir<'gc>(
                 pending_instructions.push((addr, insn));
             }
         }
+        lines.resize(code.len(), line);
     }
+    ir_addr_to_pc.push(code.len());
 
     for (addr, insn) in pending_instructions {
         let patched_insn = match insn {
@@ -824,6 +864,18 @@ pub(super) fn lower_ir<'gc>(
     upvalues.sort_unstable_by_key(|(_, i)| *i);
     let upvalues: Vec<_> = upvalues.into_iter().map(|(u, _)| u).collect();
 
+    let line_defined = frame.lines_defined.as_ref().map_or(0, |r| *r.start());
+    let (line_info, abs_line_info) = encode_line_info(&lines, line_defined);
+
+    let local_vars: Vec<_> = frame
+        .local_variable_ranges
+        .into_iter()
+        .map(|range| LocalVariable {
+            name: range.name,
+            pc: ir_addr_to_pc[range.start.0] as u32..ir_addr_to_pc[range.end.unwrap().0] as u32,
+        })
+        .collect();
+
     let protos: Vec<_> = frame
         .protos
         .into_iter()
@@ -832,19 +884,54 @@ pub(super) fn lower_ir<'gc>(
 
     Ok(LuaClosureProto {
         max_stack_size: frame.max_stack_size,
+        num_params: frame.num_fixed_args,
+        is_vararg: frame.is_vararg,
         code: code.into(),
         constants: constants.into(),
         upvalues: upvalues.into(),
         protos: protos.into(),
-        lines_defined: crate::types::LineRange::File,
+        lines_defined: frame
+            .lines_defined
+            .map_or(LineRange::File, LineRange::Lines),
         source,
-        // TODO: generate debug info
-        abs_line_info: None,
-        line_info: None,
-        local_vars: None,
+        abs_line_info: (!abs_line_info.is_empty()).then(|| abs_line_info.into()),
+        line_info: Some(line_info.into()),
+        local_vars: (!local_vars.is_empty()).then(|| local_vars.into()),
+        upvalue_names: Some(frame.upvalue_names.into()),
     })
 }
 
+/// Encodes the line of each instruction as a difference from the line of the
+/// previous instruction, falling back to absolute lines when the difference
+/// does not fit in a byte and periodically so that lookups stay fast.
+// refer to "savelineinfo" in lcode.c
+fn encode_line_info(lines: &[u32], line_defined: u32) -> (Vec<u8>, Vec<AbsLineInfo>) {
+    const MAX_INSTRUCTIONS_WITHOUT_ABS: usize = 128;
+    const LIMIT_LINE_DIFF: i64 = 0x80;
+    const ABS_LINE_INFO: i8 = -0x80;
+
+    let mut line_info = Vec::with_capacity(lines.len());
+    let mut abs_line_info = Vec::new();
+    let mut previous_line = line_defined;
+    let mut num_without_abs = 0;
+    for (pc, &line) in lines.iter().enumerate() {
+        let diff = line as i64 - previous_line as i64;
+        if diff.abs() >= LIMIT_LINE_DIFF || num_without_abs >= MAX_INSTRUCTIONS_WITHOUT_ABS {
+            abs_line_info.push(AbsLineInfo {
+                pc: pc as u32,
+                line,
+            });
+            line_info.push(ABS_LINE_INFO as u8);
+            num_without_abs = 1;
+        } else {
+            line_info.push(diff as i8 as u8);
+            num_without_abs += 1;
+        }
+        previous_line = line;
+    }
+    (line_info, abs_line_info)
+}
+
 impl UnaryOp {
     const fn opcode(&self) -> OpCode {
         match self {
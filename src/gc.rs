@@ -1,22 +1,28 @@
+mod root;
 mod string;
 mod traits;
 
+pub use root::RootedValue;
 pub(crate) use string::BoxedString;
+pub(crate) use traits::WeakTablePass;
 pub use traits::{Finalizer, GarbageCollect, Tracer};
 
 use crate::{
-    runtime::Vm,
-    types::{LuaString, Value},
+    runtime::{Metamethod, Vm},
+    types::{LuaString, Table, Value},
 };
 use hashbrown::hash_map::RawEntryMut;
+use root::RootSet;
 use std::{
     borrow::Cow,
-    cell::{Cell, Ref, RefCell, RefMut},
+    cell::{BorrowError, BorrowMutError, Cell, Ref, RefCell, RefMut},
+    collections::VecDeque,
     fmt::Debug,
     hash::Hash,
     marker::PhantomData,
-    ops::Deref,
+    ops::{Deref, DerefMut},
     ptr::NonNull,
+    sync::{Arc, Mutex},
 };
 use string::StringPool;
 
@@ -31,33 +37,60 @@ impl Default for GcHeap {
             pause: Cell::new(200),
             step_multiplier: Cell::new(100),
             step_size: Cell::new(13),
+            minor_multiplier: Cell::new(20),
+            major_multiplier: Cell::new(100),
 
+            mode: Cell::new(GcMode::Incremental),
             is_running: Cell::new(true),
-            phase: Phase::Pause,
+            memory_limit: Default::default(),
+            phase: Cell::new(Phase::Pause),
             current_white: Default::default(),
             allocated_bytes: Default::default(),
             debt: Default::default(),
             estimate: Default::default(),
 
             root: Default::default(),
+            registry: Default::default(),
+            roots: Default::default(),
+            finobj: Default::default(),
+            tobefnz: Default::default(),
+            is_closing: Default::default(),
 
             all: Default::default(),
             sweep: Default::default(),
             prev_sweep: Default::default(),
             gray: Default::default(),
             gray_again: Default::default(),
+            weak_tables: Default::default(),
+            survival: Default::default(),
+            old1: Default::default(),
+            really_old: Default::default(),
+            mode_key: Default::default(),
 
             string_pool: Default::default(),
+            borrowed_vm: Default::default(),
         };
 
         let vm = gc.allocate_cell(Vm::new(&gc));
         let vm: GcCell<Vm> = unsafe { std::mem::transmute(vm) };
         gc.root = Some(vm);
+        gc.registry = Some(vm.borrow().registry());
+        gc.mode_key = Some(vm.borrow().metamethod_name(Metamethod::Mode));
 
         Self { gc, vm }
     }
 }
 
+impl Drop for GcHeap {
+    // refer to "luaC_freeallobjects" in lgc.c
+    fn drop(&mut self) {
+        // objects with finalizers created from here on are not finalized
+        self.gc.is_closing.set(true);
+        self.gc.separate_tobefnz(true);
+        self.with(|gc, vm| vm.borrow_mut(gc).call_pending_finalizers(gc));
+    }
+}
+
 impl GcHeap {
     pub fn new() -> Self {
         Default::default()
@@ -70,32 +103,27 @@ impl GcHeap {
         f(&mut self.gc, unsafe { std::mem::transmute(self.vm) })
     }
 
+    /// Performs a step of garbage collection, or a full cycle if the memory
+    /// in use exceeds the limit.
     pub fn step(&mut self) {
-        if self.gc.is_running() {
-            self.gc.step();
-        }
+        self.gc.perform_gc();
+    }
+
+    pub fn exceeds_memory_limit(&self) -> bool {
+        self.gc.exceeds_memory_limit()
     }
 
     pub fn full_gc(&mut self) {
         self.gc.full_gc();
     }
 
+    /// Switches the collector to `mode`, returning the previous mode.
+    pub fn set_mode(&mut self, mode: GcMode) -> GcMode {
+        self.gc.set_mode(mode)
+    }
+
     pub fn force_step(&mut self, kbytes: isize) -> bool {
-        let did_step = if kbytes == 0 {
-            self.gc.set_debt(0);
-            self.gc.step();
-            true
-        } else {
-            let debt = kbytes * 1024 + self.gc.debt();
-            self.gc.set_debt(debt);
-            if debt > 0 {
-                self.gc.step();
-                true
-            } else {
-                false
-            }
-        };
-        did_step && self.gc.phase == Phase::Pause
+        self.gc.force_step(kbytes)
     }
 }
 
@@ -109,23 +137,49 @@ pub struct GcContext {
     pause: Cell<usize>,
     step_multiplier: Cell<usize>,
     step_size: Cell<usize>,
+    minor_multiplier: Cell<usize>,
+    major_multiplier: Cell<usize>,
 
+    mode: Cell<GcMode>,
     is_running: Cell<bool>,
-    phase: Phase,
-    current_white: bool,
+    memory_limit: Cell<Option<usize>>,
+    phase: Cell<Phase>,
+    current_white: Cell<bool>,
     allocated_bytes: Cell<usize>,
     debt: Cell<isize>,
-    estimate: usize,
+    estimate: Cell<usize>,
 
     root: Option<GcCell<'static, Vm<'static>>>,
+    registry: Option<GcCell<'static, Table<'static>>>,
+    roots: Arc<Mutex<RootSet>>,
+
+    // objects with finalizers, which are not traced through these lists
+    finobj: RefCell<Vec<Value<'static>>>,
+    // unreachable objects whose finalizers have not been called yet
+    tobefnz: RefCell<VecDeque<Value<'static>>>,
+    is_closing: Cell<bool>,
 
     all: Cell<Option<GcPtr<dyn GarbageCollect>>>,
-    sweep: Option<GcPtr<dyn GarbageCollect>>,
-    prev_sweep: Option<GcPtr<dyn GarbageCollect>>,
-    gray: Vec<GcPtr<dyn GarbageCollect>>,
+    sweep: Cell<Option<GcPtr<dyn GarbageCollect>>>,
+    prev_sweep: Cell<Option<GcPtr<dyn GarbageCollect>>>,
+    gray: RefCell<Vec<GcPtr<dyn GarbageCollect>>>,
     gray_again: RefCell<Vec<GcPtr<dyn GarbageCollect>>>,
+    // tables with weak keys or values found while tracing
+    weak_tables: RefCell<Vec<GcPtr<dyn GarbageCollect>>>,
+    mode_key: Option<LuaString<'static>>,
+
+    // in generational mode, `all` is ordered by age: new objects come
+    // before `survival`, survivals before `old1`, and objects that became old
+    // in the last cycle before `really_old`
+    survival: Cell<Option<GcPtr<dyn GarbageCollect>>>,
+    old1: Cell<Option<GcPtr<dyn GarbageCollect>>>,
+    really_old: Cell<Option<GcPtr<dyn GarbageCollect>>>,
 
     string_pool: RefCell<StringPool>,
+
+    // the VM while `Vm::call` collects garbage with it mutably borrowed from
+    // `root`, so that it is traced through this instead
+    borrowed_vm: Cell<Option<NonNull<Vm<'static>>>>,
 }
 
 impl Drop for GcContext {
@@ -185,26 +239,144 @@ impl GcContext {
         self.step_size.set(step_size);
     }
 
+    pub fn minor_multiplier(&self) -> usize {
+        self.minor_multiplier.get()
+    }
+
+    pub fn set_minor_multiplier(&self, minor_multiplier: usize) {
+        self.minor_multiplier.set(minor_multiplier);
+    }
+
+    pub fn major_multiplier(&self) -> usize {
+        self.major_multiplier.get()
+    }
+
+    pub fn set_major_multiplier(&self, major_multiplier: usize) {
+        self.major_multiplier.set(major_multiplier);
+    }
+
+    pub fn mode(&self) -> GcMode {
+        self.mode.get()
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit.get()
+    }
+
+    /// Sets the maximum number of bytes in use, including the contents of
+    /// strings, tables, threads and userdata. Once it is exceeded, a full
+    /// garbage collection cycle is run, and the running Lua code raises a
+    /// memory error if the limit is still exceeded after that. Large strings
+    /// and arrays are checked against the limit before they are allocated.
+    pub fn set_memory_limit(&self, limit: Option<usize>) {
+        self.memory_limit.set(limit);
+    }
+
+    pub(crate) fn exceeds_memory_limit(&self) -> bool {
+        self.memory_limit
+            .get()
+            .is_some_and(|limit| self.total_bytes() > limit)
+    }
+
     pub fn should_perform_gc(&self) -> bool {
-        self.is_running() && self.debt() > 0
+        (self.is_running() && self.debt() > 0) || self.exceeds_memory_limit()
+    }
+
+    /// See `GcHeap::step`.
+    pub(crate) fn perform_gc(&self) {
+        if self.exceeds_memory_limit() {
+            // refer to "tryagain" in lmem.c
+            self.full_gc();
+        } else if self.is_running() {
+            self.step();
+        }
+    }
+
+    /// See `GcHeap::set_mode`.
+    // refer to "luaC_changemode" in lgc.c
+    pub(crate) fn set_mode(&self, mode: GcMode) -> GcMode {
+        let prev_mode = self.mode.get();
+        if mode != prev_mode {
+            match mode {
+                GcMode::Incremental => self.enter_incremental(),
+                GcMode::Generational => self.enter_generational(),
+            }
+        }
+        prev_mode
+    }
+
+    pub(crate) fn force_step(&self, kbytes: isize) -> bool {
+        let did_step = if kbytes == 0 {
+            self.set_debt(0);
+            self.step();
+            true
+        } else {
+            let debt = kbytes * 1024 + self.debt();
+            self.set_debt(debt);
+            if debt > 0 {
+                self.step();
+                true
+            } else {
+                false
+            }
+        };
+        did_step && self.phase.get() == Phase::Pause
+    }
+
+    /// Runs `f`, which can collect garbage, while `vm` is mutably borrowed
+    /// from the `GcCell` it is owned by.
+    pub(crate) fn with_borrowed_vm<R>(&self, vm: &Vm, f: impl FnOnce() -> R) -> R {
+        let ptr = NonNull::from(vm).cast::<Vm<'static>>();
+        let prev = self.borrowed_vm.replace(Some(ptr));
+        let result = f();
+        self.borrowed_vm.set(prev);
+        result
+    }
+
+    fn registry<'gc>(&'gc self) -> GcCell<'gc, Table<'gc>> {
+        unsafe { std::mem::transmute(self.registry.unwrap()) }
     }
 
     pub fn allocate<T: GarbageCollect>(&self, value: T) -> Gc<T> {
-        let color = Color::White(self.current_white);
+        let color = Color::White(self.current_white.get());
         let mut gc_box = Box::new(std::mem::MaybeUninit::uninit());
         gc_box.write(GcBox {
             color: Cell::new(color),
+            is_finalizable: Cell::new(false),
+            age: Cell::new(Age::New),
+            size: Cell::new(0),
             next: self.all.get(),
             value,
         });
         let ptr = Box::into_raw(gc_box) as *mut GcBox<T>;
         let ptr = unsafe { NonNull::new_unchecked(ptr) };
         self.all.set(Some(into_ptr_to_static(ptr)));
-        self.debt
-            .set(self.debt.get() + std::mem::size_of::<GcBox<T>>() as isize);
+        self.resize(into_ptr_to_static(ptr));
         Gc::new(ptr)
     }
 
+    /// Counts the current size of the object toward the memory in use, in
+    /// place of the size it was counted with before.
+    fn resize(&self, ptr: GcPtr<dyn GarbageCollect>) {
+        let gc_box = unsafe { ptr.as_ref() };
+        let size = std::mem::size_of_val(gc_box) + gc_box.value.heap_size();
+        let old_size = gc_box.size.replace(size);
+        self.debt
+            .set(self.debt.get() + size as isize - old_size as isize);
+    }
+
+    /// Returns whether `size` bytes can be allocated without exceeding the
+    /// memory limit.
+    pub(crate) fn can_allocate(&self, size: usize) -> bool {
+        match self.memory_limit.get() {
+            Some(limit) => self
+                .total_bytes()
+                .checked_add(size)
+                .is_some_and(|total| total <= limit),
+            None => size <= isize::MAX as usize,
+        }
+    }
+
     pub fn allocate_cell<T: GarbageCollect>(&self, value: T) -> GcCell<T> {
         GcCell(self.allocate(GcRefCell::new(value)))
     }
@@ -234,39 +406,90 @@ impl GcContext {
         LuaString(Gc::new(interned))
     }
 
-    fn full_gc(&mut self) {
-        if matches!(self.phase, Phase::Propagate | Phase::Atomic) {
-            self.phase = Phase::Sweep;
-            self.sweep = self.all.get();
+    /// Marks `object` to have its finalizer called once it becomes
+    /// unreachable. Only tables and userdata can have finalizers.
+    // refer to "luaC_checkfinalizer" in lgc.c
+    pub(crate) fn register_finalizer(&self, object: Value) {
+        if self.is_closing.get() {
+            return;
+        }
+        if let Some(gc_box) = finalizable_gc_box(&object) {
+            if !gc_box.is_finalizable.replace(true) {
+                let object: Value<'static> = unsafe { std::mem::transmute(object) };
+                self.finobj.borrow_mut().push(object);
+            }
+        }
+    }
+
+    /// Takes the next unreachable object whose finalizer is to be called.
+    pub(crate) fn pop_object_to_finalize<'gc>(&'gc self) -> Option<Value<'gc>> {
+        let object = self.tobefnz.borrow_mut().pop_front()?;
+        // the object is marked again if it gets a new finalizer
+        finalizable_gc_box(&object)
+            .unwrap()
+            .is_finalizable
+            .set(false);
+        let object: Value<'gc> = unsafe { std::mem::transmute(object) };
+        Some(object)
+    }
+
+    // refer to "separatetobefnz" in lgc.c
+    fn separate_tobefnz(&self, all: bool) {
+        let mut finobj = self.finobj.borrow_mut();
+        let (unreachable, reachable): (Vec<_>, Vec<_>) = finobj.drain(..).partition(|object| {
+            all || matches!(
+                finalizable_gc_box(object).unwrap().color.get(),
+                Color::White(_)
+            )
+        });
+        *finobj = reachable;
+        // finalizers are called in the reverse order of the objects being marked
+        self.tobefnz
+            .borrow_mut()
+            .extend(unreachable.into_iter().rev());
+    }
+
+    pub(crate) fn full_gc(&self) {
+        if self.mode.get() == GcMode::Generational {
+            self.full_gen();
+            return;
+        }
+        if matches!(self.phase.get(), Phase::Propagate | Phase::Atomic) {
+            self.phase.set(Phase::Sweep);
+            self.sweep.set(self.all.get());
             self.prev_sweep.take();
         }
-        while self.phase != Phase::Pause {
+        while self.phase.get() != Phase::Pause {
             self.do_single_step();
         }
         loop {
             self.do_single_step();
-            if self.phase == Phase::Pause {
+            if self.phase.get() == Phase::Pause {
                 break;
             }
         }
-        debug_assert_eq!(self.estimate, self.total_bytes());
+        debug_assert_eq!(self.estimate.get(), self.total_bytes());
         loop {
             self.do_single_step();
-            if self.phase == Phase::Pause {
+            if self.phase.get() == Phase::Pause {
                 break;
             }
         }
         self.set_debt_for_pause_phase();
     }
 
-    fn step(&mut self) {
+    fn step(&self) {
+        if self.mode.get() == GcMode::Generational {
+            self.gen_step();
+            return;
+        }
         let mut debt = self.debt.get();
         let step_size = 1 << self.step_size.get();
         let step_multiplier = self.step_multiplier.get() | 1; // avoid division by zero
         loop {
             let work = self.do_single_step() / step_multiplier;
             debt -= work as isize;
-            if self.phase == Phase::Pause {
+            if self.phase.get() == Phase::Pause {
                 self.set_debt_for_pause_phase();
                 return;
             }
@@ -284,133 +507,220 @@ impl GcContext {
     }
 
     fn set_debt_for_pause_phase(&self) {
-        let estimate = self.estimate / PAUSEADJ;
+        let estimate = self.estimate.get() / PAUSEADJ;
         let threshold = estimate * self.pause.get();
         let debt = self.allocated_bytes.get() as isize + self.debt.get() - threshold as isize;
         self.set_debt(if debt > 0 { 0 } else { debt });
     }
 
+    // refer to "luaC_barrierback_" in lgc.c
     fn write_barrier<T: GarbageCollect>(&self, ptr: GcPtr<T>) {
-        if self.phase != Phase::Propagate {
+        if self.mode.get() == GcMode::Incremental && self.phase.get() != Phase::Propagate {
             return;
         }
 
         let gc_box = unsafe { ptr.as_ref() };
         if gc_box.color.get() == Color::Black {
             gc_box.color.set(Color::Gray);
+            if self.mode.get() == GcMode::Generational {
+                // objects touched in the previous cycle are still in the list
+                if gc_box.age.replace(Age::Touched1) == Age::Touched2 {
+                    return;
+                }
+            }
             self.gray_again.borrow_mut().push(into_ptr_to_static(ptr));
         }
     }
 
-    fn propagate_gray(&mut self, ptr: GcPtr<dyn GarbageCollect>) -> usize {
+    fn propagate_gray(&self, ptr: GcPtr<dyn GarbageCollect>) -> usize {
         let gc_box = unsafe { ptr.as_ref() };
         debug_assert_eq!(gc_box.color.get(), Color::Gray);
-        gc_box.value.trace(&mut Tracer {
-            gray: &mut self.gray,
-        });
+        let mut tracer = Tracer {
+            gray: &mut self.gray.borrow_mut(),
+            weak_tables: &mut self.weak_tables.borrow_mut(),
+            current: Some(ptr),
+            mode_key: self.mode_key.unwrap(),
+            pass: WeakTablePass::Propagate,
+        };
+        let root = self.root.map(|root| into_ptr_to_static(root.0.ptr));
+        match self.borrowed_vm.get() {
+            // the cell of the VM cannot be borrowed while `Vm::call` runs
+            Some(vm) if is_same_object(Some(ptr), root) => {
+                unsafe { vm.as_ref() }.trace(&mut tracer)
+            }
+            _ => gc_box.value.trace(&mut tracer),
+        }
         gc_box.color.set(Color::Black);
-        std::mem::size_of_val(gc_box)
+        gc_box.size.get()
     }
 
-    fn do_single_step(&mut self) -> usize {
-        match self.phase {
+    fn do_single_step(&self) -> usize {
+        match self.phase.get() {
             Phase::Pause => {
                 self.do_pause();
-                self.phase = Phase::Propagate;
+                self.phase.set(Phase::Propagate);
                 WORK2MEM
             }
             Phase::Propagate => {
                 let work = self.do_propagate();
                 if work == 0 {
-                    self.phase = Phase::Atomic;
+                    self.phase.set(Phase::Atomic);
                 }
                 work
             }
             Phase::Atomic => {
                 let work = self.do_atomic();
-                self.phase = Phase::Sweep;
-                self.sweep = self.all.get();
+                self.phase.set(Phase::Sweep);
+                self.sweep.set(self.all.get());
                 self.prev_sweep.take();
-                self.estimate = self.total_bytes();
+                self.estimate.set(self.total_bytes());
                 work
             }
             Phase::Sweep => {
                 let work = self.do_sweep();
                 if work == 0 {
-                    self.phase = Phase::Pause;
+                    self.phase.set(Phase::Pause);
                 }
                 work
             }
         }
     }
 
-    fn do_pause(&mut self) {
-        debug_assert!(self.gray.is_empty());
+    fn do_pause(&self) {
+        debug_assert!(self.gray.borrow().is_empty());
         debug_assert!(self.gray_again.borrow().is_empty());
-        self.root.unwrap().trace(&mut Tracer {
-            gray: &mut self.gray,
-        });
+        self.release_roots();
+        self.trace_roots();
+    }
+
+    fn trace_roots(&self) {
+        let mut tracer = Tracer {
+            gray: &mut self.gray.borrow_mut(),
+            weak_tables: &mut self.weak_tables.borrow_mut(),
+            current: None,
+            mode_key: self.mode_key.unwrap(),
+            pass: WeakTablePass::Propagate,
+        };
+        self.root.unwrap().trace(&mut tracer);
+        for object in self.tobefnz.borrow().iter() {
+            object.trace(&mut tracer);
+        }
     }
 
-    fn do_propagate(&mut self) -> usize {
-        if let Some(ptr) = self.gray.pop() {
+    fn do_propagate(&self) -> usize {
+        if let Some(ptr) = self.pop_gray() {
             self.propagate_gray(ptr)
         } else {
             0
         }
     }
 
-    fn do_atomic(&mut self) -> usize {
-        self.root.unwrap().trace(&mut Tracer {
-            gray: &mut self.gray,
-        });
+    // refer to "atomic" in lgc.c
+    fn do_atomic(&self) -> usize {
+        self.trace_roots();
+
+        let mut work = self.propagate_all();
+        self.gray.swap(&self.gray_again);
+        work += self.propagate_all();
+        work += self.converge_ephemerons();
+
+        // values referring to objects about to be resurrected are cleared
+        // before resurrection, keys are cleared after it
+        self.process_weak_tables(0, WeakTablePass::ClearValues);
+        let num_weak_tables = self.weak_tables.borrow().len();
+
+        // unreachable objects with finalizers are resurrected along with
+        // everything they reference until their finalizers are called
+        self.separate_tobefnz(false);
+        self.trace_roots();
+        work += self.propagate_all();
+        work += self.converge_ephemerons();
+
+        self.process_weak_tables(0, WeakTablePass::ClearKeys);
+        // weak tables only reached through resurrected objects
+        self.process_weak_tables(num_weak_tables, WeakTablePass::ClearValues);
+        self.weak_tables.borrow_mut().clear();
+
+        self.current_white.set(!self.current_white.get());
+        work
+    }
 
+    fn propagate_all(&self) -> usize {
         let mut work = 0;
-        while let Some(ptr) = self.gray.pop() {
+        while let Some(ptr) = self.pop_gray() {
             work += self.propagate_gray(ptr);
         }
-        std::mem::swap(&mut self.gray, &mut self.gray_again.borrow_mut());
-        while let Some(ptr) = self.gray.pop() {
-            work += self.propagate_gray(ptr);
+        work
+    }
+
+    fn pop_gray(&self) -> Option<GcPtr<dyn GarbageCollect>> {
+        self.gray.borrow_mut().pop()
+    }
+
+    /// Marks the values of ephemeron tables whose keys are marked until no
+    /// more objects get marked.
+    // refer to "convergeephemerons" in lgc.c
+    fn converge_ephemerons(&self) -> usize {
+        let mut work = 0;
+        loop {
+            let num_weak_tables = self.weak_tables.borrow().len();
+            self.process_weak_tables(0, WeakTablePass::Converge);
+            let new_work = self.propagate_all();
+            work += new_work;
+            if new_work == 0 && self.weak_tables.borrow().len() == num_weak_tables {
+                return work;
+            }
         }
+    }
 
-        self.current_white = !self.current_white;
-        work
+    fn process_weak_tables(&self, start: usize, pass: WeakTablePass) {
+        let num_weak_tables = self.weak_tables.borrow().len();
+        for i in start..num_weak_tables {
+            let ptr = self.weak_tables.borrow()[i];
+            let gc_box = unsafe { ptr.as_ref() };
+            gc_box.value.trace(&mut Tracer {
+                gray: &mut self.gray.borrow_mut(),
+                weak_tables: &mut Vec::new(),
+                current: Some(ptr),
+                mode_key: self.mode_key.unwrap(),
+                pass,
+            });
+        }
     }
 
-    fn do_sweep(&mut self) -> usize {
+    fn do_sweep(&self) -> usize {
         let mut count = 0;
         let mut work = 0;
         let mut debt = self.debt.get();
 
         let old_debt = debt;
-        let current_white = Color::White(self.current_white);
-        let other_white = Color::White(!self.current_white);
+        let current_white = Color::White(self.current_white.get());
+        let other_white = Color::White(!self.current_white.get());
 
         let mut finalizer = Finalizer {
             string_pool: &mut self.string_pool.borrow_mut(),
         };
 
-        while let Some(ptr) = self.sweep {
+        while let Some(ptr) = self.sweep.get() {
             let gc_box = unsafe { ptr.as_ref() };
-            work += std::mem::size_of_val(gc_box);
+            work += gc_box.size.get();
             if gc_box.color.get() == other_white {
-                if let Some(prev) = &mut self.prev_sweep {
+                if let Some(mut prev) = self.prev_sweep.get() {
                     let prev = unsafe { prev.as_mut() };
                     prev.next = gc_box.next;
                 } else {
                     self.all.set(gc_box.next);
                 }
-                self.sweep = gc_box.next;
-                debt -= std::mem::size_of_val(gc_box) as isize;
+                self.sweep.set(gc_box.next);
+                debt -= gc_box.size.get() as isize;
 
                 gc_box.value.finalize(&mut finalizer);
                 let _ = unsafe { Box::from_raw(ptr.as_ptr()) };
             } else {
                 debug_assert_eq!(gc_box.color.get(), Color::Black);
                 gc_box.color.set(current_white);
-                self.prev_sweep = Some(ptr);
-                self.sweep = gc_box.next;
+                self.prev_sweep.set(Some(ptr));
+                self.sweep.set(gc_box.next);
             }
             count += 1;
             if count >= GCSWEEPMAX {
@@ -419,15 +729,213 @@ impl GcContext {
         }
 
         self.debt.set(debt);
-        self.estimate = (self.estimate as isize + debt - old_debt) as usize;
+        self.estimate
+            .set((self.estimate.get() as isize + debt - old_debt) as usize);
         work
     }
+
+    // refer to "genstep" in lgc.c
+    fn gen_step(&self) {
+        let major_base = self.estimate.get();
+        let major_inc = major_base / 100 * self.major_multiplier.get();
+        if self.debt.get() > 0 && self.total_bytes() > major_base + major_inc {
+            self.full_gen();
+            if self.total_bytes() >= major_base + major_inc / 2 {
+                // less than half of the growth was collected, so most objects
+                // are long-lived and the next major collection can wait
+                self.set_debt_for_pause_phase();
+            }
+        } else {
+            self.young_collection();
+            self.set_minor_debt();
+            // minor collections do not change the base for major ones
+            self.estimate.set(major_base);
+        }
+    }
+
+    fn set_minor_debt(&self) {
+        let debt = self.total_bytes() / 100 * self.minor_multiplier.get();
+        self.set_debt(-(debt as isize));
+    }
+
+    // refer to "fullgen" in lgc.c
+    fn full_gen(&self) {
+        self.enter_incremental();
+        self.enter_generational();
+    }
+
+    /// Runs a full cycle and makes all surviving objects old.
+    // refer to "entergen" and "atomic2gen" in lgc.c
+    fn enter_generational(&self) {
+        while self.phase.get() != Phase::Pause {
+            self.do_single_step();
+        }
+        self.do_single_step();
+        self.do_atomic();
+
+        self.sweep_gen(None, None, true);
+        self.survival.set(self.all.get());
+        self.old1.set(self.all.get());
+        self.really_old.set(self.all.get());
+
+        self.mode.set(GcMode::Generational);
+        // generational mode never goes back to the pause phase
+        self.phase.set(Phase::Propagate);
+        self.estimate.set(self.total_bytes());
+        self.set_minor_debt();
+    }
+
+    // refer to "enterinc" in lgc.c
+    fn enter_incremental(&self) {
+        let white = Color::White(self.current_white.get());
+        let mut it = self.all.get();
+        while let Some(ptr) = it {
+            let gc_box = unsafe { ptr.as_ref() };
+            gc_box.color.set(white);
+            gc_box.age.set(Age::New);
+            it = gc_box.next;
+        }
+        self.gray_again.borrow_mut().clear();
+        self.survival.set(None);
+        self.old1.set(None);
+        self.really_old.set(None);
+
+        self.mode.set(GcMode::Incremental);
+        self.phase.set(Phase::Pause);
+    }
+
+    // refer to "youngcollection" in lgc.c
+    fn young_collection(&self) {
+        self.release_roots();
+        self.mark_old();
+
+        // old objects written to since the last cycle are traversed again
+        let touched = self.gray_again.borrow().clone();
+        for ptr in &touched {
+            unsafe { ptr.as_ref() }.color.set(Color::Gray);
+        }
+        self.do_atomic();
+
+        let prev_survival = self.sweep_gen(None, self.survival.get(), false);
+        self.sweep_gen(prev_survival, self.old1.get(), false);
+        self.really_old.set(self.old1.get());
+        self.old1.set(match prev_survival {
+            Some(ptr) => unsafe { ptr.as_ref() }.next,
+            None => self.all.get(),
+        });
+        self.survival.set(self.all.get());
+
+        // objects touched in this cycle have to be traversed in the next one
+        // too, as the young objects they refer to are not old yet
+        let mut gray_again = self.gray_again.borrow_mut();
+        for ptr in touched {
+            let gc_box = unsafe { ptr.as_ref() };
+            if gc_box.age.get() == Age::Touched1 {
+                gc_box.age.set(Age::Touched2);
+                gray_again.push(ptr);
+            } else {
+                gc_box.age.set(Age::Old);
+            }
+        }
+    }
+
+    /// Traverses the objects that became old in the last cycle, as they may
+    /// refer to survivals.
+    // refer to "markold" in lgc.c
+    fn mark_old(&self) {
+        let mut it = self.old1.get();
+        while !is_same_object(it, self.really_old.get()) {
+            let ptr = it.unwrap();
+            let gc_box = unsafe { ptr.as_ref() };
+            it = gc_box.next;
+            if gc_box.age.get() == Age::Old1 {
+                gc_box.age.set(Age::Old);
+                if gc_box.color.get() == Color::Black {
+                    gc_box.color.set(Color::Gray);
+                    self.gray.borrow_mut().push(ptr);
+                }
+            }
+        }
+    }
+
+    /// Frees the dead objects after `prev` up to `limit` and advances the
+    /// ages of the others, or makes them old if `to_old` is set. Returns the
+    /// last object left before `limit`.
+    // refer to "sweepgen" and "sweep2old" in lgc.c
+    fn sweep_gen(
+        &self,
+        mut prev: Option<GcPtr<dyn GarbageCollect>>,
+        limit: Option<GcPtr<dyn GarbageCollect>>,
+        to_old: bool,
+    ) -> Option<GcPtr<dyn GarbageCollect>> {
+        let mut debt = self.debt.get();
+        let current_white = Color::White(self.current_white.get());
+        let other_white = Color::White(!self.current_white.get());
+
+        let mut finalizer = Finalizer {
+            string_pool: &mut self.string_pool.borrow_mut(),
+        };
+
+        let mut it = match prev {
+            Some(ptr) => unsafe { ptr.as_ref() }.next,
+            None => self.all.get(),
+        };
+        while !is_same_object(it, limit) {
+            let ptr = it.unwrap();
+            let gc_box = unsafe { ptr.as_ref() };
+            it = gc_box.next;
+            if gc_box.color.get() == other_white {
+                if let Some(prev) = &mut prev {
+                    let prev = unsafe { prev.as_mut() };
+                    prev.next = gc_box.next;
+                } else {
+                    self.all.set(gc_box.next);
+                }
+                debt -= gc_box.size.get() as isize;
+
+                gc_box.value.finalize(&mut finalizer);
+                let _ = unsafe { Box::from_raw(ptr.as_ptr()) };
+            } else {
+                debug_assert_eq!(gc_box.color.get(), Color::Black);
+                let age = if to_old {
+                    Age::Old
+                } else {
+                    gc_box.age.get().next()
+                };
+                gc_box.age.set(age);
+                // only old objects stay black between cycles
+                if age == Age::Survival {
+                    gc_box.color.set(current_white);
+                }
+                prev = Some(ptr);
+            }
+        }
+
+        self.debt.set(debt);
+        prev
+    }
+}
+
+fn is_same_object(
+    a: Option<GcPtr<dyn GarbageCollect>>,
+    b: Option<GcPtr<dyn GarbageCollect>>,
+) -> bool {
+    a.map(|ptr| ptr.as_ptr().cast::<()>()) == b.map(|ptr| ptr.as_ptr().cast::<()>())
 }
 
 fn into_ptr_to_static<'a>(ptr: GcPtr<dyn GarbageCollect + 'a>) -> GcPtr<dyn GarbageCollect> {
     unsafe { std::mem::transmute(ptr) }
 }
 
+fn finalizable_gc_box<'a>(object: &Value<'a>) -> Option<&'a GcBox<dyn GarbageCollect + 'a>> {
+    let ptr: GcPtr<dyn GarbageCollect + 'a> = match object {
+        Value::Table(table) => table.0.ptr,
+        Value::UserData(ud) => ud.0.ptr,
+        _ => return None,
+    };
+    Some(unsafe { ptr.as_ref() })
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Phase {
     Pause,
@@ -436,6 +944,40 @@ enum Phase {
     Sweep,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcMode {
+    /// Each cycle traverses all objects, interleaved with the program.
+    Incremental,
+    /// Frequent minor collections only traverse young objects, with
+    /// occasional major collections of all objects.
+    Generational,
+}
+
+// refer to the object ages in lgc.h
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Age {
+    New,
+    // survived one cycle
+    Survival,
+    // became old in the last cycle
+    Old1,
+    Old,
+    // old objects written to in the current cycle
+    Touched1,
+    // old objects written to in the previous cycle
+    Touched2,
+}
+
+impl Age {
+    const fn next(self) -> Self {
+        match self {
+            Self::New => Self::Survival,
+            Self::Survival => Self::Old1,
+            age => age,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum Color {
     White(bool),
@@ -445,6 +987,10 @@ enum Color {
 
 struct GcBox<T: ?Sized + GarbageCollect> {
     color: Cell<Color>,
+    is_finalizable: Cell<bool>,
+    age: Cell<Age>,
+    // bytes counted toward the memory in use, see `GcContext::resize`
+    size: Cell<usize>,
     next: Option<GcPtr<dyn GarbageCollect>>,
     value: T,
 }
@@ -535,6 +1081,17 @@ impl<T: GarbageCollect> Gc<'_, T> {
         let gc_box = unsafe { self.ptr.as_ref() };
         &gc_box.value as *const T
     }
+
+    /// Has to be called after replacing a reference that the object holds
+    /// through interior mutability, like `GcCell::borrow_mut` does.
+    pub(crate) fn write_barrier(&self, gc: &GcContext) {
+        gc.write_barrier(self.ptr);
+    }
+
+    fn is_white(&self) -> bool {
+        let gc_box = unsafe { self.ptr.as_ref() };
+        matches!(gc_box.color.get(), Color::White(_))
+    }
 }
 
 struct GcRefCell<T: GarbageCollect>(RefCell<T>);
@@ -545,7 +1102,11 @@ unsafe impl<T: GarbageCollect> GarbageCollect for GcRefCell<T> {
     }
 
     fn trace(&self, tracer: &mut Tracer) {
-        self.0.borrow().trace(tracer);
+        T::trace_cell(&self.0, tracer);
+    }
+
+    fn heap_size(&self) -> usize {
+        self.0.borrow().heap_size()
     }
 }
 
@@ -591,9 +1152,111 @@ impl<T: GarbageCollect> GcCell<'_, T> {
         self.0 .0.borrow()
     }
 
-    pub fn borrow_mut(&self, gc: &GcContext) -> RefMut<T> {
+    /// Mutably borrows the object, like `RefCell::borrow_mut`.
+    ///
+    /// This returns a `GcRefMut` rather than a `RefMut`, so that the size of
+    /// the object is counted again when the borrow ends. `GcRefMut` derefs
+    /// to `T` like `RefMut`, and `GcRefMut::map` and `GcRefMut::filter_map`
+    /// replace `RefMut::map` and `RefMut::filter_map`.
+    pub fn borrow_mut<'a>(&'a self, gc: &'a GcContext) -> GcRefMut<'a, T> {
         let b = self.0 .0.borrow_mut();
         gc.write_barrier(self.0.ptr);
-        b
+        GcRefMut::new(gc, self.0.ptr, b)
+    }
+
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.0 .0.try_borrow()
+    }
+
+    /// Like `borrow_mut`, but returns an error if the object is borrowed.
+    pub fn try_borrow_mut<'a>(
+        &'a self,
+        gc: &'a GcContext,
+    ) -> Result<GcRefMut<'a, T>, BorrowMutError> {
+        let b = self.0 .0.try_borrow_mut()?;
+        gc.write_barrier(self.0.ptr);
+        Ok(GcRefMut::new(gc, self.0.ptr, b))
+    }
+}
+
+/// A mutable borrow of the object owned by a `GcCell`, returned by
+/// `GcCell::borrow_mut` in place of a `RefMut`. As the object can be resized
+/// while it is borrowed, its size is counted again once the borrow ends.
+pub struct GcRefMut<'a, T: ?Sized> {
+    // dropped before `resize`, which borrows the object
+    value: RefMut<'a, T>,
+    resize: Resize<'a>,
+}
+
+impl<T: ?Sized + Debug> Debug for GcRefMut<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<T: ?Sized> Deref for GcRefMut<'_, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: ?Sized> DerefMut for GcRefMut<'_, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<'a, T: ?Sized> GcRefMut<'a, T> {
+    fn new<U: GarbageCollect + 'a>(
+        gc: &'a GcContext,
+        ptr: GcPtr<GcRefCell<U>>,
+        value: RefMut<'a, T>,
+    ) -> Self {
+        Self {
+            value,
+            resize: Resize {
+                gc,
+                ptr: into_ptr_to_static(ptr),
+            },
+        }
+    }
+
+    /// Like `RefMut::map`.
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> GcRefMut<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let Self { value, resize } = orig;
+        GcRefMut {
+            value: RefMut::map(value, f),
+            resize,
+        }
+    }
+
+    /// Like `RefMut::filter_map`.
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<GcRefMut<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        let Self { value, resize } = orig;
+        match RefMut::filter_map(value, f) {
+            Ok(value) => Ok(GcRefMut { value, resize }),
+            Err(value) => Err(Self { value, resize }),
+        }
+    }
+}
+
+struct Resize<'a> {
+    gc: &'a GcContext,
+    ptr: GcPtr<dyn GarbageCollect>,
+}
+
+impl Drop for Resize<'_> {
+    fn drop(&mut self) {
+        self.gc.resize(self.ptr);
     }
 }
@@ -0,0 +1,97 @@
+use super::GcContext;
+use crate::types::{Integer, Value};
+use std::sync::{Arc, Mutex};
+
+/// Keys of the registry table holding rooted values.
+pub(super) struct RootSet {
+    next: Integer,
+    free: Vec<Integer>,
+    released: Vec<Integer>,
+}
+
+impl Default for RootSet {
+    fn default() -> Self {
+        Self {
+            // registry[1] and registry[2] hold the main thread and the globals
+            next: 3,
+            free: Vec::new(),
+            released: Vec::new(),
+        }
+    }
+}
+
+/// A value kept alive until the handle and all of its clones are dropped,
+/// even when it is not reachable from Lua. Unlike `Value`, it is not tied to
+/// the lifetime of a single `GcHeap::with` call.
+///
+/// The value is stored in the registry table of the `Vm`.
+#[derive(Clone)]
+pub struct RootedValue(Arc<RegistryKey>);
+
+struct RegistryKey {
+    index: Integer,
+    roots: Arc<Mutex<RootSet>>,
+}
+
+impl Drop for RegistryKey {
+    fn drop(&mut self) {
+        // the registry cannot be borrowed here, so the slot is cleared on the
+        // next call to `GcContext::root` or garbage collection cycle
+        self.roots.lock().unwrap().released.push(self.index);
+    }
+}
+
+impl std::fmt::Debug for RootedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RootedValue").field(&self.0.index).finish()
+    }
+}
+
+impl RootedValue {
+    /// Returns the value.
+    ///
+    /// # Panics
+    /// Panics if `gc` belongs to a different heap than the one the value was
+    /// rooted in.
+    pub fn get<'gc>(&self, gc: &'gc GcContext) -> Value<'gc> {
+        assert!(
+            Arc::ptr_eq(&self.0.roots, &gc.roots),
+            "value was rooted in a different heap"
+        );
+        gc.registry().borrow().get_integer_key(self.0.index)
+    }
+}
+
+impl GcContext {
+    pub fn root<'gc>(&'gc self, value: Value<'gc>) -> RootedValue {
+        self.release_roots();
+        let mut roots = self.roots.lock().unwrap();
+        let index = roots.free.pop().unwrap_or_else(|| {
+            roots.next += 1;
+            roots.next - 1
+        });
+        drop(roots);
+
+        self.registry().borrow_mut(self).set(index, value).unwrap();
+        RootedValue(Arc::new(RegistryKey {
+            index,
+            roots: self.roots.clone(),
+        }))
+    }
+
+    /// Clears the registry slots of dropped `RootedValue`s so that they can be
+    /// collected and reused.
+    pub(super) fn release_roots(&self) {
+        let released = std::mem::take(&mut self.roots.lock().unwrap().released);
+        if released.is_empty() {
+            return;
+        }
+        let registry = self.registry();
+        let mut registry = registry.borrow_mut(self);
+        for &index in &released {
+            registry.set(index, Value::Nil).unwrap();
+        }
+        drop(registry);
+        self.roots.lock().unwrap().free.extend(released);
+    }
+}
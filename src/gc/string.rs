@@ -41,6 +41,10 @@ unsafe impl GarbageCollect for BoxedString {
             .unwrap();
         unsafe { table.remove(bucket) };
     }
+
+    fn heap_size(&self) -> usize {
+        self.0.len()
+    }
 }
 
 impl BoxedString {
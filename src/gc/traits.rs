@@ -1,8 +1,70 @@
-use super::{GcPtr, StringPool};
-use std::{collections::BTreeMap, hash::BuildHasher, ops::Deref};
+use super::{GcCell, GcPtr, StringPool};
+use crate::types::{LuaString, Table, Value};
+use std::{cell::RefCell, collections::BTreeMap, hash::BuildHasher, ops::Deref};
 
 pub struct Tracer<'a> {
     pub(super) gray: &'a mut Vec<GcPtr<dyn GarbageCollect>>,
+    pub(super) weak_tables: &'a mut Vec<GcPtr<dyn GarbageCollect>>,
+    pub(super) current: Option<GcPtr<dyn GarbageCollect>>,
+    pub(super) mode_key: LuaString<'static>,
+    pub(super) pass: WeakTablePass,
+}
+
+/// What tracing an object owned by a `GcCell` does with weak tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WeakTablePass {
+    /// Traces the entries that are not weak and registers weak tables to be
+    /// processed in the atomic phase.
+    Propagate,
+    /// Traces the values of ephemeron tables whose keys have been marked.
+    Converge,
+    ClearKeys,
+    ClearValues,
+}
+
+impl Tracer<'_> {
+    pub(crate) const fn weak_table_pass(&self) -> WeakTablePass {
+        self.pass
+    }
+
+    /// Returns whether the keys and the values of a table with `metatable`
+    /// are weak.
+    pub(crate) fn weak_mode(&self, metatable: Option<GcCell<Table>>) -> (bool, bool) {
+        let Some(metatable) = metatable else {
+            return (false, false);
+        };
+        let mode_key: LuaString = unsafe { std::mem::transmute(self.mode_key) };
+        let mode = metatable.borrow().get_field(mode_key);
+        match mode {
+            Value::String(mode) => (mode.contains(&b'k'), mode.contains(&b'v')),
+            _ => (false, false),
+        }
+    }
+
+    /// Registers the object being traced as a weak table, which is processed
+    /// again in the atomic phase.
+    pub(crate) fn register_weak_table(&mut self) {
+        self.weak_tables.extend(self.current);
+    }
+
+    /// Returns whether `value` is an unmarked object, which is to be removed
+    /// from weak tables. Strings are not considered objects for weak tables,
+    /// so they are marked instead.
+    // refer to "iscleared" in lgc.c
+    pub(crate) fn is_cleared(&mut self, value: Value) -> bool {
+        match value {
+            Value::String(s) => {
+                s.trace(self);
+                false
+            }
+            Value::Table(x) => x.0.is_white(),
+            Value::LuaClosure(x) => x.is_white(),
+            Value::NativeClosure(x) => x.is_white(),
+            Value::UserData(x) => x.0.is_white(),
+            Value::Thread(x) => x.0.is_white(),
+            _ => false,
+        }
+    }
 }
 
 pub struct Finalizer<'a> {
@@ -24,6 +86,22 @@ pub unsafe trait GarbageCollect {
 
     #[allow(unused_variables)]
     fn finalize(&self, finalizer: &mut Finalizer) {}
+
+    /// Returns the number of bytes owned by the object outside of it, like
+    /// the contents of a `Vec`, which count toward the memory in use.
+    fn heap_size(&self) -> usize {
+        0
+    }
+
+    /// Traces an object owned by a `GcCell`. Unlike `trace`, the object is
+    /// known to be the one being traced, so it can register itself with
+    /// `Tracer::register_weak_table`.
+    fn trace_cell(cell: &RefCell<Self>, tracer: &mut Tracer)
+    where
+        Self: Sized,
+    {
+        cell.borrow().trace(tracer);
+    }
 }
 
 unsafe impl<T: GarbageCollect> GarbageCollect for &T {
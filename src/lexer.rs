@@ -43,7 +43,8 @@ pub enum LexerError {
 
 pub struct Lexer<'gc, R: Read> {
     inner: LexerInner<'gc, R>,
-    peeked: VecDeque<Token<'gc>>,
+    peeked: VecDeque<(Token<'gc>, usize)>,
+    last_lineno: usize,
 }
 
 impl<'gc, R: Read> Lexer<'gc, R> {
@@ -51,14 +52,20 @@ impl<'gc, R: Read> Lexer<'gc, R> {
         Self {
             inner: LexerInner::new(gc, reader),
             peeked: VecDeque::with_capacity(2),
+            last_lineno: 1,
         }
     }
 
     pub fn consume(&mut self) -> Result<Option<Token<'gc>>, LexerError> {
-        if let Some(peeked) = self.peeked.pop_front() {
+        if let Some((peeked, lineno)) = self.peeked.pop_front() {
+            self.last_lineno = lineno;
             Ok(Some(peeked))
         } else {
-            self.inner.consume_token()
+            let token = self.inner.consume_token()?;
+            if token.is_some() {
+                self.last_lineno = self.inner.lineno;
+            }
+            Ok(token)
         }
     }
 
@@ -81,24 +88,39 @@ impl<'gc, R: Read> Lexer<'gc, R> {
     pub fn peek(&mut self) -> Result<Option<&Token<'gc>>, LexerError> {
         if self.peeked.is_empty() {
             if let Some(token) = self.inner.consume_token()? {
-                self.peeked.push_back(token);
+                self.peeked.push_back((token, self.inner.lineno));
             }
         }
-        Ok(self.peeked.front())
+        Ok(self.peeked.front().map(|(token, _)| token))
     }
 
     pub fn peek2(&mut self) -> Result<Option<&Token>, LexerError> {
         if self.peeked.len() < 2 {
             if let Some(token) = self.inner.consume_token()? {
-                self.peeked.push_back(token);
+                self.peeked.push_back((token, self.inner.lineno));
             }
         }
-        Ok(self.peeked.get(1))
+        Ok(self.peeked.get(1).map(|(token, _)| token))
     }
 
     pub const fn lineno(&self) -> usize {
         self.inner.lineno
     }
+
+    /// Returns the line of the next token, or the current line at the end of
+    /// input.
+    pub fn peek_lineno(&mut self) -> Result<usize, LexerError> {
+        self.peek()?;
+        Ok(self
+            .peeked
+            .front()
+            .map_or(self.inner.lineno, |(_, lineno)| *lineno))
+    }
+
+    /// Returns the line of the last consumed token.
+    pub const fn last_lineno(&self) -> usize {
+        self.last_lineno
+    }
 }
 
 struct LexerInner<'gc, R: Read> {
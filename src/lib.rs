@@ -14,6 +14,8 @@ mod math;
 mod stdlib;
 mod string;
 
+pub use gc::GcRefMut;
+
 use bstr::{ByteSlice, ByteVec};
 use gc::GcContext;
 use std::{borrow::Cow, fmt::Debug, io::Cursor, path::Path};
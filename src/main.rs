@@ -2,8 +2,8 @@ use anyhow::{Error, Result};
 use bstr::{ByteSlice, ByteVec, B};
 use clap::{Parser, Subcommand};
 use mochi_lua::{
-    gc::GcHeap,
-    runtime::{OpCode, Runtime, RuntimeError},
+    gc::{GcHeap, RootedValue},
+    runtime::{ErrorKind, Metamethod, OpCode, Runtime, RuntimeError},
     types::{Integer, LineRange, LuaClosureProto, Table, UpvalueDescription, Value},
 };
 use rustyline::error::ReadlineError;
@@ -96,7 +96,7 @@ fn main() -> Result<()> {
                 let closure = vm.borrow().load(gc, stat, "=(command line)")?;
                 Ok(gc.allocate(closure).into())
             })
-            .map_err(Error::msg)?;
+            .map_err(|err| Error::msg(convert_error_object(&mut runtime, err)))?;
     }
 
     if let Some(script) = &cli.script {
@@ -105,7 +105,7 @@ fn main() -> Result<()> {
                 let closure = vm.borrow().load_file(gc, script)?;
                 Ok(gc.allocate(closure).into())
             })
-            .map_err(Error::msg)?;
+            .map_err(|err| Error::msg(convert_error_object(&mut runtime, err)))?;
     }
 
     if cli.interactive || (cli.execute.is_empty() && cli.script.is_none()) {
@@ -143,12 +143,13 @@ fn do_repl(runtime: &mut Runtime) -> Result<()> {
                 const SOURCE: &str = "=stdin";
 
                 if is_first_line {
-                    let result = runtime.execute(|gc, vm| {
-                        let closure = vm.borrow().load(gc, format!("print({line})"), SOURCE)?;
+                    let result = runtime.execute_with_results(|gc, vm| {
+                        let closure = vm.borrow().load(gc, format!("return {line}"), SOURCE)?;
                         Ok(gc.allocate(closure).into())
                     });
                     match result {
-                        Ok(()) => {
+                        Ok(results) => {
+                            print_results(runtime, results);
                             rl.add_history_entry(line)?;
                             continue;
                         }
@@ -157,7 +158,7 @@ fn do_repl(runtime: &mut Runtime) -> Result<()> {
                             ..
                         }) => (),
                         Err(err) => {
-                            eprintln!("{err}");
+                            eprintln!("{}", convert_error_object(runtime, err));
                             rl.add_history_entry(line)?;
                             continue;
                         }
@@ -167,14 +168,16 @@ fn do_repl(runtime: &mut Runtime) -> Result<()> {
                 }
                 buf.push_str(&line);
 
-                let result = runtime.execute(|gc, vm| match vm.borrow().load(gc, &buf, SOURCE) {
-                    Ok(closure) => Ok(gc.allocate(closure).into()),
-                    Err(err) => Err(err.into()),
+                let result = runtime.execute_with_results(|gc, vm| {
+                    match vm.borrow().load(gc, &buf, SOURCE) {
+                        Ok(closure) => Ok(gc.allocate(closure).into()),
+                        Err(err) => Err(err.into()),
+                    }
                 });
                 match result {
-                    Ok(()) => (),
+                    Ok(results) => print_results(runtime, results),
                     Err(err) if is_incomplete_input_error(&err) => continue,
-                    Err(err) => eprintln!("{err}"),
+                    Err(err) => eprintln!("{}", convert_error_object(runtime, err)),
                 }
                 rl.add_history_entry(&buf)?;
                 buf.clear();
@@ -185,6 +188,60 @@ fn do_repl(runtime: &mut Runtime) -> Result<()> {
     }
 }
 
+// refer to "l_print" in lua.c
+fn print_results(runtime: &mut Runtime, results: Vec<RootedValue>) {
+    if results.is_empty() {
+        return;
+    }
+    let result = runtime.call(|gc, vm| {
+        let print = vm
+            .borrow()
+            .globals()
+            .borrow()
+            .get_field(gc.allocate_string(B("print")));
+        let args = results.iter().map(|value| value.get(gc)).collect();
+        Ok((print, args))
+    });
+    if let Err(err) = result {
+        eprintln!("error calling 'print' ({})", err.kind);
+    }
+}
+
+// refer to "msghandler" in lua.c
+/// Replaces the message of an error raised with a value that is not a string
+/// with the result of its `__tostring` metamethod, if any.
+fn convert_error_object(runtime: &mut Runtime, mut err: RuntimeError) -> RuntimeError {
+    let ErrorKind::ErrorObject { object, .. } = &err.kind else {
+        return err;
+    };
+    let object = object.clone();
+    let tostring = runtime.heap().with(|gc, vm| {
+        let value = object.get(gc);
+        if value.to_string().is_some() {
+            return None;
+        }
+        vm.borrow()
+            .metamethod_of_object(Metamethod::ToString, value)
+            .map(|tostring| gc.root(tostring))
+    });
+    let Some(tostring) = tostring else {
+        return err;
+    };
+    let result = runtime.call_and_convert(
+        |gc, _| Ok((tostring.get(gc), vec![object.get(gc)])),
+        |_, _, results| {
+            results
+                .first()
+                .and_then(|value| value.as_lua_string())
+                .map(|s| s.to_str_lossy().to_string())
+        },
+    );
+    if let (Ok(Some(s)), ErrorKind::ErrorObject { message, .. }) = (result, &mut err.kind) {
+        *message = s;
+    }
+    err
+}
+
 fn is_incomplete_input_error(err: &RuntimeError) -> bool {
     match err {
         RuntimeError {
@@ -248,19 +305,29 @@ impl CompileCommand {
                 range.end()
             )?,
         };
+        let local_vars = proto.local_vars.as_deref().unwrap_or_default();
         writeln!(
             w,
-            "({})\n{}, {}, {}, {}",
+            "({})\n{}{} param{}, {}, {}, {}, {}, {}",
             format_counter("instruction", proto.code.len()),
+            proto.num_params,
+            if proto.is_vararg { "+" } else { "" },
+            if proto.num_params == 1 { "" } else { "s" },
             format_counter("slot", proto.max_stack_size as usize),
             format_counter("upvalue", proto.upvalues.len()),
+            format_counter("local", local_vars.len()),
             format_counter("constant", proto.constants.len()),
             format_counter("function", proto.protos.len())
         )?;
 
         for (i, insn) in proto.code.iter().enumerate() {
             let opcode = insn.opcode();
-            write!(w, "\t{}\t{opcode:9}\t", i + 1)?;
+            write!(w, "\t{}\t", i + 1)?;
+            match proto.func_line(i as u32) {
+                Some(line) if line > 0 => write!(w, "[{line}]\t")?,
+                _ => write!(w, "[-]\t")?,
+            }
+            write!(w, "{opcode:9}\t")?;
             match opcode {
                 OpCode::Return0 => w.write_all(b"\n")?,
                 OpCode::LoadKX
@@ -386,9 +453,25 @@ impl CompileCommand {
                 };
             }
 
+            writeln!(w, "locals ({}):", local_vars.len())?;
+            for (i, var) in local_vars.iter().enumerate() {
+                writeln!(
+                    w,
+                    "\t{i}\t{}\t{}\t{}",
+                    var.name.as_bstr(),
+                    var.pc.start + 1,
+                    var.pc.end + 1
+                )?;
+            }
+
             writeln!(w, "upvalues ({}):", proto.upvalues.len())?;
             for (i, desc) in proto.upvalues.iter().enumerate() {
-                write!(w, "\t{i}\t")?;
+                let name = proto
+                    .upvalue_names
+                    .as_ref()
+                    .and_then(|names| names.get(i))
+                    .map_or(B("-"), |name| name.as_ref());
+                write!(w, "\t{i}\t{}\t", name.as_bstr())?;
                 match desc {
                     UpvalueDescription::Register(i) => writeln!(w, "1\t{}", i.0)?,
                     UpvalueDescription::Upvalue(i) => writeln!(w, "0\t{}", i.0)?,
@@ -110,7 +110,10 @@ impl<'gc, R: Read> Parser<'gc, R> {
     fn parse_chunk(&mut self) -> Result<Chunk<'gc>, ErrorKind> {
         let block = self.parse_block()?;
         self.expect(None)?;
-        Ok(Chunk(block))
+        Ok(Chunk {
+            block,
+            last_line: self.last_line(),
+        })
     }
 
     fn parse_block(&mut self) -> Result<Block<'gc>, ErrorKind> {
@@ -132,12 +135,16 @@ impl<'gc, R: Read> Parser<'gc, R> {
                         return_statement: Some(self.parse_return_statement()?),
                     })
                 }
-                _ => statements.push(self.parse_statement()?),
+                _ => {
+                    let line = self.line()?;
+                    statements.push((self.parse_statement()?, line));
+                }
             }
         }
     }
 
     fn parse_return_statement(&mut self) -> Result<ReturnStatement<'gc>, ErrorKind> {
+        let line = self.line()?;
         self.expect(Token::Return)?;
         let list = match self.lexer.peek()? {
             None
@@ -147,7 +154,7 @@ impl<'gc, R: Read> Parser<'gc, R> {
             _ => self.parse_expr_list()?,
         };
         self.lexer.consume_if_eq(Token::Semicolon)?;
-        Ok(ReturnStatement(list))
+        Ok(ReturnStatement { values: list, line })
     }
 
     fn parse_statement(&mut self) -> Result<Statement<'gc>, ErrorKind> {
@@ -273,6 +280,7 @@ impl<'gc, R: Read> Parser<'gc, R> {
     }
 
     fn parse_func_statement(&mut self) -> Result<FunctionStatement<'gc>, ErrorKind> {
+        let line_defined = self.line()?;
         self.expect(Token::Function)?;
         let name = self.expect_name()?;
         let mut fields = Vec::new();
@@ -316,6 +324,8 @@ impl<'gc, R: Read> Parser<'gc, R> {
                 params,
                 is_vararg,
                 body,
+                line_defined,
+                last_line_defined: self.last_line(),
             },
         })
     }
@@ -445,10 +455,12 @@ impl<'gc, R: Read> Parser<'gc, R> {
             _ => None,
         };
         let mut expr = if let Some(op) = unary_op {
+            let line = self.line()?;
             self.lexer.consume()?;
             let expr = UnaryOpExpression {
                 op,
                 inner: self.parse_sub_expr(UNARY_PRIORITY)?.into(),
+                line,
             };
             Expression::UnaryOp(expr)
         } else {
@@ -518,12 +530,14 @@ impl<'gc, R: Read> Parser<'gc, R> {
             if left_priority <= min_priority {
                 break;
             }
+            let line = self.line()?;
             self.lexer.consume()?;
             let rhs = self.parse_sub_expr(right_priority)?;
             expr = Expression::BinaryOp(BinaryOpExpression {
                 op,
                 lhs: expr.into(),
                 rhs: rhs.into(),
+                line,
             })
         }
 
@@ -531,6 +545,7 @@ impl<'gc, R: Read> Parser<'gc, R> {
     }
 
     fn parse_func_expr(&mut self) -> Result<FunctionExpression<'gc>, ErrorKind> {
+        let line_defined = self.line()?;
         self.expect(Token::Function)?;
         self.expect(Token::LeftParen)?;
 
@@ -560,6 +575,8 @@ impl<'gc, R: Read> Parser<'gc, R> {
             params,
             is_vararg,
             body,
+            line_defined,
+            last_line_defined: self.last_line(),
         })
     }
 
@@ -588,6 +605,7 @@ impl<'gc, R: Read> Parser<'gc, R> {
     }
 
     fn parse_suffixed_expr(&mut self) -> Result<SuffixedExpression<'gc>, ErrorKind> {
+        let line = self.line()?;
         let primary = self.parse_primary()?;
         let mut suffixes = Vec::new();
         loop {
@@ -618,7 +636,11 @@ impl<'gc, R: Read> Parser<'gc, R> {
             };
             suffixes.push(suffix);
         }
-        Ok(SuffixedExpression { primary, suffixes })
+        Ok(SuffixedExpression {
+            primary,
+            suffixes,
+            line,
+        })
     }
 
     fn parse_primary(&mut self) -> Result<Primary<'gc>, ErrorKind> {
@@ -644,31 +666,51 @@ impl<'gc, R: Read> Parser<'gc, R> {
                 }
                 Some(Token::RightCurlyBracket) => {
                     self.lexer.consume()?;
-                    return Ok(TableConstructorExpression(fields));
+                    return Ok(TableConstructorExpression {
+                        fields,
+                        last_line: self.last_line(),
+                    });
                 }
                 Some(Token::Name(name)) if self.lexer.peek2()? == Some(&Token::Assign) => {
+                    let line = self.line()?;
                     self.lexer.consume()?;
                     self.lexer.consume()?;
-                    fields.push(TableField::Record {
+                    let field = TableField::Record {
                         key: TableRecordKey::Name(name),
                         value: self.parse_expr()?,
-                    });
+                    };
+                    fields.push((field, line));
                 }
                 Some(Token::LeftBracket) => {
+                    let line = self.line()?;
                     self.lexer.consume()?;
                     let key = TableRecordKey::Index(self.parse_expr()?);
                     self.expect(Token::RightBracket)?;
                     self.expect(Token::Assign)?;
-                    fields.push(TableField::Record {
+                    let field = TableField::Record {
                         key,
                         value: self.parse_expr()?,
-                    });
+                    };
+                    fields.push((field, line));
+                }
+                _ => {
+                    let line = self.line()?;
+                    fields.push((TableField::List(self.parse_expr()?), line));
                 }
-                _ => fields.push(TableField::List(self.parse_expr()?)),
             }
         }
     }
 
+    /// Returns the line of the next token.
+    fn line(&mut self) -> Result<u32, ErrorKind> {
+        Ok(self.lexer.peek_lineno()? as u32)
+    }
+
+    /// Returns the line of the last consumed token.
+    fn last_line(&self) -> u32 {
+        self.lexer.last_lineno() as u32
+    }
+
     fn expect(&mut self, expected: impl Into<Option<Token<'gc>>>) -> Result<(), ErrorKind> {
         let got = self.lexer.consume()?;
         let expected = expected.into();
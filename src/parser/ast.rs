@@ -1,16 +1,24 @@
 use crate::types::{Integer, LuaString, Number};
 
 #[derive(Debug, Clone)]
-pub struct Chunk<'gc>(pub Block<'gc>);
+pub struct Chunk<'gc> {
+    pub block: Block<'gc>,
+    /// Line of the last token of the chunk.
+    pub last_line: u32,
+}
 
 #[derive(Debug, Clone)]
 pub struct Block<'gc> {
-    pub statements: Vec<Statement<'gc>>,
+    /// Statements paired with the line they start at.
+    pub statements: Vec<(Statement<'gc>, u32)>,
     pub return_statement: Option<ReturnStatement<'gc>>,
 }
 
 #[derive(Debug, Clone)]
-pub struct ReturnStatement<'gc>(pub Vec<Expression<'gc>>);
+pub struct ReturnStatement<'gc> {
+    pub values: Vec<Expression<'gc>>,
+    pub line: u32,
+}
 
 #[derive(Debug, Clone)]
 pub enum Statement<'gc> {
@@ -123,7 +131,12 @@ pub enum Expression<'gc> {
 }
 
 #[derive(Debug, Clone)]
-pub struct TableConstructorExpression<'gc>(pub Vec<TableField<'gc>>);
+pub struct TableConstructorExpression<'gc> {
+    /// Fields paired with the line they start at.
+    pub fields: Vec<(TableField<'gc>, u32)>,
+    /// Line of the closing `}`.
+    pub last_line: u32,
+}
 
 #[derive(Debug, Clone)]
 pub enum TableField<'gc> {
@@ -145,12 +158,15 @@ pub struct FunctionExpression<'gc> {
     pub params: Vec<LuaString<'gc>>,
     pub is_vararg: bool,
     pub body: Block<'gc>,
+    pub line_defined: u32,
+    pub last_line_defined: u32,
 }
 
 #[derive(Debug, Clone)]
 pub struct SuffixedExpression<'gc> {
     pub primary: Primary<'gc>,
     pub suffixes: Vec<Suffix<'gc>>,
+    pub line: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -183,6 +199,7 @@ pub enum FunctionArguments<'gc> {
 pub struct UnaryOpExpression<'gc> {
     pub op: UnaryOp,
     pub inner: Box<Expression<'gc>>,
+    pub line: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -198,6 +215,7 @@ pub struct BinaryOpExpression<'gc> {
     pub op: BinaryOp,
     pub lhs: Box<Expression<'gc>>,
     pub rhs: Box<Expression<'gc>>,
+    pub line: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
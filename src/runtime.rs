@@ -5,24 +5,34 @@ mod bytecode_vm;
 mod debug;
 mod error;
 mod frame;
+mod hook;
+mod interrupt;
 mod metamethod;
 mod opcode;
-mod ops;
+pub(crate) mod ops;
 
 pub use action::{Action, Continuation};
 pub use error::{ErrorKind, Operation, RuntimeError};
-pub(crate) use frame::{ContinuationFrame, Frame, LuaFrame};
+pub use frame::LuaFrame;
+pub(crate) use frame::{ContinuationFrame, Frame};
+pub(crate) use hook::LuaHook;
+pub use hook::{HookAction, HookEvent, HookInfo, HookMask};
 pub use instruction::Instruction;
+pub use interrupt::{Budget, InterruptAction};
 pub use metamethod::Metamethod;
+pub(crate) use metamethod::MetamethodChain;
 pub use opcode::OpCode;
 
 use crate::{
-    gc::{GarbageCollect, GcCell, GcContext, GcHeap, Tracer},
+    gc::{GarbageCollect, GcCell, GcContext, GcHeap, RootedValue, Tracer},
     types::{LuaString, LuaThread, Table, ThreadStatus, Type, Upvalue, Value},
     Error, LuaClosure,
 };
+use bstr::ByteSlice;
 use debug::Name;
-use std::{ops::ControlFlow, path::Path};
+use hook::Hook;
+use interrupt::Interrupt;
+use std::{cell::Cell, ops::ControlFlow, path::Path, task::Poll};
 
 #[derive(Default)]
 pub struct Runtime {
@@ -42,6 +52,39 @@ impl Runtime {
         self.heap
     }
 
+    /// See `Vm::set_instruction_limit`.
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.heap
+            .with(|_, vm| vm.borrow().set_instruction_limit(limit));
+    }
+
+    /// See `Vm::set_interrupt`.
+    pub fn set_interrupt<F>(&mut self, interval: u32, callback: F)
+    where
+        F: 'static + FnMut() -> InterruptAction,
+    {
+        self.heap
+            .with(|gc, vm| vm.borrow_mut(gc).set_interrupt(interval, callback));
+    }
+
+    pub fn remove_interrupt(&mut self) {
+        self.heap
+            .with(|gc, vm| vm.borrow_mut(gc).remove_interrupt());
+    }
+
+    /// See `Vm::set_hook`.
+    pub fn set_hook<F>(&mut self, mask: HookMask, count: u32, callback: F)
+    where
+        F: 'static + FnMut(&HookInfo) -> Result<HookAction, ErrorKind>,
+    {
+        self.heap
+            .with(|gc, vm| vm.borrow_mut(gc).set_hook(mask, count, callback));
+    }
+
+    pub fn remove_hook(&mut self) {
+        self.heap.with(|gc, vm| vm.borrow_mut(gc).remove_hook());
+    }
+
     pub fn execute<F>(&mut self, f: F) -> Result<(), RuntimeError>
     where
         F: for<'gc> FnOnce(
@@ -51,10 +94,118 @@ impl Runtime {
             Value<'gc>,
             Box<dyn std::error::Error + Send + Sync + 'static>,
         >,
+    {
+        self.execute_and_convert(f, |_, _, _| ())
+    }
+
+    /// Like `execute`, but returns the values returned by the function,
+    /// rooted so that they can be used after the call.
+    pub fn execute_with_results<F>(&mut self, f: F) -> Result<Vec<RootedValue>, RuntimeError>
+    where
+        F: for<'gc> FnOnce(
+            &'gc GcContext,
+            GcCell<'gc, Vm<'gc>>,
+        ) -> Result<
+            Value<'gc>,
+            Box<dyn std::error::Error + Send + Sync + 'static>,
+        >,
+    {
+        self.call(|gc, vm| f(gc, vm).map(|callee| (callee, Vec::new())))
+    }
+
+    /// Like `execute`, but returns the values returned by the function,
+    /// converted by `convert` before they leave the heap.
+    pub fn execute_and_convert<F, C, R>(&mut self, f: F, convert: C) -> Result<R, RuntimeError>
+    where
+        F: for<'gc> FnOnce(
+            &'gc GcContext,
+            GcCell<'gc, Vm<'gc>>,
+        ) -> Result<
+            Value<'gc>,
+            Box<dyn std::error::Error + Send + Sync + 'static>,
+        >,
+        C: for<'gc> FnOnce(&'gc GcContext, GcCell<'gc, Vm<'gc>>, Vec<Value<'gc>>) -> R,
+    {
+        self.call_and_convert(
+            |gc, vm| f(gc, vm).map(|callee| (callee, Vec::new())),
+            convert,
+        )
+    }
+
+    /// Calls the function returned by `f` with the arguments returned along
+    /// with it, running the garbage collector as needed, and returns the
+    /// results rooted so that they can be used after the call.
+    pub fn call<F>(&mut self, f: F) -> Result<Vec<RootedValue>, RuntimeError>
+    where
+        F: for<'gc> FnOnce(
+            &'gc GcContext,
+            GcCell<'gc, Vm<'gc>>,
+        ) -> Result<
+            (Value<'gc>, Vec<Value<'gc>>),
+            Box<dyn std::error::Error + Send + Sync + 'static>,
+        >,
+    {
+        self.call_and_convert(f, |gc, _, results| {
+            results.into_iter().map(|value| gc.root(value)).collect()
+        })
+    }
+
+    /// Like `call`, but the results are converted by `convert` before they
+    /// leave the heap.
+    pub fn call_and_convert<F, C, R>(&mut self, f: F, convert: C) -> Result<R, RuntimeError>
+    where
+        F: for<'gc> FnOnce(
+            &'gc GcContext,
+            GcCell<'gc, Vm<'gc>>,
+        ) -> Result<
+            (Value<'gc>, Vec<Value<'gc>>),
+            Box<dyn std::error::Error + Send + Sync + 'static>,
+        >,
+        C: for<'gc> FnOnce(&'gc GcContext, GcCell<'gc, Vm<'gc>>, Vec<Value<'gc>>) -> R,
+    {
+        self.push_call(f)?;
+        loop {
+            if let Poll::Ready(result) = self.run() {
+                result?;
+                break;
+            }
+        }
+        Ok(self.take_results(convert))
+    }
+
+    /// Like `execute_with_results`, but the function returned by `f` is only
+    /// called as `Execution::step` is called, so that the host can do other
+    /// work between slices of its execution.
+    pub fn start<F>(&mut self, f: F) -> Result<Execution<'_>, RuntimeError>
+    where
+        F: for<'gc> FnOnce(
+            &'gc GcContext,
+            GcCell<'gc, Vm<'gc>>,
+        ) -> Result<
+            Value<'gc>,
+            Box<dyn std::error::Error + Send + Sync + 'static>,
+        >,
+    {
+        self.push_call(|gc, vm| f(gc, vm).map(|callee| (callee, Vec::new())))?;
+        Ok(Execution {
+            runtime: self,
+            is_finished: false,
+        })
+    }
+
+    fn push_call<F>(&mut self, f: F) -> Result<(), RuntimeError>
+    where
+        F: for<'gc> FnOnce(
+            &'gc GcContext,
+            GcCell<'gc, Vm<'gc>>,
+        ) -> Result<
+            (Value<'gc>, Vec<Value<'gc>>),
+            Box<dyn std::error::Error + Send + Sync + 'static>,
+        >,
     {
         let result = self.heap.with(|gc, vm| {
-            let value = match f(gc, vm) {
-                Ok(value) => value,
+            let (callee, args) = match f(gc, vm) {
+                Ok(call) => call,
                 Err(err) => return Err(ErrorKind::External(err.into())),
             };
 
@@ -68,37 +219,120 @@ impl Runtime {
             assert!(thread_ref.stack.is_empty());
             assert!(thread_ref.frames.is_empty());
             assert!(thread_ref.open_upvalues.is_empty());
-            thread_ref.stack.push(value);
-            vm.push_frame(&mut thread_ref, 0)?;
+            assert!(thread_ref.tbc_slots.is_empty());
+            thread_ref.stack.push(callee);
+            thread_ref.stack.extend(args);
+            if let Err(err) = vm.push_frame(&mut thread_ref, 0) {
+                *thread_ref = LuaThread::new();
+                vm.thread_stack.clear();
+                return Err(err);
+            }
 
             Ok(())
         });
-        match result {
-            Ok(()) => (),
-            Err(kind) => {
-                return Err(RuntimeError {
-                    kind,
-                    traceback: Vec::new(),
-                })
-            }
-        }
+        result.map_err(|kind| RuntimeError {
+            kind,
+            traceback: Vec::new(),
+        })
+    }
 
+    /// Runs the pushed call until it returns or the current slice of
+    /// `Execution::step` ends.
+    fn run(&mut self) -> Poll<Result<(), RuntimeError>> {
         loop {
             let action = self
                 .heap
-                .with(|gc, vm| vm.borrow_mut(gc).execute_single_step(gc))?;
+                .with(|gc, vm| vm.borrow_mut(gc).execute_single_step(gc));
             match action {
-                RuntimeAction::StepGc => self.heap.step(),
-                RuntimeAction::MutateGc(mutator) => mutator(&mut self.heap),
-                RuntimeAction::Exit => return Ok(()),
+                Ok(RuntimeAction::StepGc) => {
+                    self.heap.step();
+                    if self.heap.exceeds_memory_limit() {
+                        let result = self
+                            .heap
+                            .with(|gc, vm| vm.borrow_mut(gc).raise_memory_error(gc));
+                        if let Err(err) = result {
+                            return Poll::Ready(Err(err));
+                        }
+                    }
+                }
+                Ok(RuntimeAction::MutateGc(mutator)) => self.heap.with(|gc, _| mutator(gc)),
+                Ok(RuntimeAction::Pause) => return Poll::Pending,
+                Ok(RuntimeAction::Exit) => return Poll::Ready(Ok(())),
+                Err(err) => return Poll::Ready(Err(err)),
             }
+            self.heap
+                .with(|gc, vm| vm.borrow_mut(gc).call_pending_finalizers(gc));
+        }
+    }
+
+    fn take_results<C, R>(&mut self, convert: C) -> R
+    where
+        C: for<'gc> FnOnce(&'gc GcContext, GcCell<'gc, Vm<'gc>>, Vec<Value<'gc>>) -> R,
+    {
+        self.heap.with(|gc, vm| {
+            let main_thread = vm.borrow().main_thread;
+            let results = std::mem::take(&mut main_thread.borrow_mut(gc).stack);
+            convert(gc, vm, results)
+        })
+    }
+}
+
+/// A call started by `Runtime::start`, run in slices of bounded work.
+///
+/// Dropping it before the call finishes aborts the call.
+pub struct Execution<'a> {
+    runtime: &'a mut Runtime,
+    is_finished: bool,
+}
+
+impl Execution<'_> {
+    /// Runs the call until it returns or `budget` is used up, and returns
+    /// its results rooted once it has returned.
+    ///
+    /// # Panics
+    /// Panics if the call has already finished.
+    pub fn step(&mut self, budget: Budget) -> Poll<Result<Vec<RootedValue>, RuntimeError>> {
+        assert!(!self.is_finished, "execution has already finished");
+        let runtime = &mut *self.runtime;
+        runtime
+            .heap
+            .with(|_, vm| vm.borrow().interrupt.set_slice(Some(budget)));
+        let result = runtime.run();
+        runtime
+            .heap
+            .with(|_, vm| vm.borrow().interrupt.set_slice(None));
+
+        let Poll::Ready(result) = result else {
+            return Poll::Pending;
+        };
+        self.is_finished = true;
+        Poll::Ready(result.map(|()| {
+            runtime.take_results(|gc, _, results| {
+                results.into_iter().map(|value| gc.root(value)).collect()
+            })
+        }))
+    }
+
+    pub const fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+}
+
+impl Drop for Execution<'_> {
+    fn drop(&mut self) {
+        if !self.is_finished {
+            self.runtime.heap.with(|gc, vm| {
+                vm.borrow_mut(gc).abort(gc, ErrorKind::Interrupted);
+            });
         }
     }
 }
 
 enum RuntimeAction {
     StepGc,
-    MutateGc(Box<dyn Fn(&mut GcHeap)>),
+    MutateGc(Box<dyn Fn(&GcContext)>),
+    /// The slice of `Execution::step` has ended.
+    Pause,
     Exit,
 }
 
@@ -109,6 +343,10 @@ pub struct Vm<'gc> {
     thread_stack: Vec<GcCell<'gc, LuaThread<'gc>>>,
     metamethod_names: [LuaString<'gc>; Metamethod::COUNT],
     metatables: [Option<GcCell<'gc, Table<'gc>>>; Type::COUNT],
+    is_warning_on: bool,
+    is_calling_finalizers: bool,
+    interrupt: Interrupt,
+    hook: Option<Hook>,
 }
 
 unsafe impl GarbageCollect for Vm<'_> {
@@ -134,6 +372,10 @@ impl<'gc> Vm<'gc> {
             thread_stack: Default::default(),
             metamethod_names: Metamethod::allocate_names(gc),
             metatables: Default::default(),
+            is_warning_on: false,
+            is_calling_finalizers: false,
+            interrupt: Default::default(),
+            hook: None,
         }
     }
 
@@ -172,9 +414,9 @@ impl<'gc> Vm<'gc> {
     {
         let proto = crate::load(gc, bytes, source)?;
         let mut closure = LuaClosure::from(gc.allocate(proto));
-        closure
-            .upvalues
-            .push(gc.allocate_cell(Value::Table(self.globals).into()));
+        closure.upvalues.push(Cell::new(
+            gc.allocate_cell(Value::Table(self.globals).into()),
+        ));
         Ok(closure)
     }
 
@@ -185,9 +427,9 @@ impl<'gc> Vm<'gc> {
     ) -> Result<LuaClosure<'gc>, Error> {
         let proto = crate::load_file(gc, path)?;
         let mut closure = LuaClosure::from(gc.allocate(proto));
-        closure
-            .upvalues
-            .push(gc.allocate_cell(Value::Table(self.globals).into()));
+        closure.upvalues.push(Cell::new(
+            gc.allocate_cell(Value::Table(self.globals).into()),
+        ));
         Ok(closure)
     }
 
@@ -223,58 +465,163 @@ impl<'gc> Vm<'gc> {
         self.metatables[ty as usize] = metatable.into();
     }
 
+    /// Sets the metatable of a table or userdata, or the metatable shared by
+    /// all values of the type for other values.
+    ///
+    /// If the metatable has a `__gc` field at this point, the object is
+    /// marked for finalization.
+    pub fn set_metatable_of_object<T>(
+        &mut self,
+        gc: &'gc GcContext,
+        object: Value<'gc>,
+        metatable: T,
+    ) where
+        T: Into<Option<GcCell<'gc, Table<'gc>>>>,
+    {
+        let metatable = metatable.into();
+        match object {
+            Value::Table(table) => table.borrow_mut(gc).set_metatable(metatable),
+            Value::UserData(ud) => ud.borrow_mut(gc).set_metatable(metatable),
+            _ => return self.set_metatable_of_type(object.ty(), metatable),
+        }
+        self.check_finalizer(gc, object, metatable);
+    }
+
+    // refer to "luaC_checkfinalizer" in lgc.c
+    pub(crate) fn check_finalizer(
+        &self,
+        gc: &'gc GcContext,
+        object: Value<'gc>,
+        metatable: Option<GcCell<'gc, Table<'gc>>>,
+    ) {
+        if let Some(metatable) = metatable {
+            let gc_metamethod = metatable
+                .borrow()
+                .get_field(self.metamethod_name(Metamethod::Gc));
+            if !gc_metamethod.is_nil() {
+                gc.register_finalizer(object);
+            }
+        }
+    }
+
+    /// Calls the `__gc` metamethods of the objects found unreachable by the
+    /// garbage collector. Errors raised by them are reported as warnings.
+    // refer to "GCTM" in lgc.c
+    pub(crate) fn call_pending_finalizers(&mut self, gc: &'gc GcContext) {
+        // finalizers are not called while another finalizer is running,
+        // otherwise the nested calls would reverse the order of finalization
+        if self.is_calling_finalizers {
+            return;
+        }
+        self.is_calling_finalizers = true;
+        while let Some(object) = gc.pop_object_to_finalize() {
+            let Some(finalizer) = self.metamethod_of_object(Metamethod::Gc, object) else {
+                continue;
+            };
+            if let Err(err) = self.call(gc, finalizer, vec![object]) {
+                self.warn(format!("error in __gc ({err})"));
+            }
+        }
+        self.is_calling_finalizers = false;
+    }
+
+    pub const fn is_warning_on(&self) -> bool {
+        self.is_warning_on
+    }
+
+    pub fn set_warning_on(&mut self, is_on: bool) {
+        self.is_warning_on = is_on;
+    }
+
+    /// Emits a warning to stderr if warnings are turned on.
+    pub fn warn<S: AsRef<[u8]>>(&self, message: S) {
+        if self.is_warning_on {
+            eprintln!("Lua warning: {}", message.as_ref().as_bstr());
+        }
+    }
+
+    /// Raises a memory error in the running thread, if any.
+    fn raise_memory_error(&mut self, gc: &'gc GcContext) -> Result<(), RuntimeError> {
+        if self.thread_stack.is_empty() {
+            return Ok(());
+        }
+        self.handle_error(gc, ErrorKind::MemoryError)
+    }
+
+    /// Makes sure that `size` bytes can be allocated without exceeding the
+    /// memory limit, running a full garbage collection cycle if they cannot.
+    /// The current thread must not be borrowed.
+    // refer to "tryagain" in lmem.c
+    pub(crate) fn reserve_memory(
+        &mut self,
+        gc: &'gc GcContext,
+        size: usize,
+    ) -> Result<(), ErrorKind> {
+        if !gc.can_allocate(size) {
+            gc.with_borrowed_vm(self, || gc.full_gc());
+            if !gc.can_allocate(size) {
+                return Err(ErrorKind::MemoryError);
+            }
+        }
+        Ok(())
+    }
+
+    /// Limits the number of instructions Lua functions can execute from now
+    /// on, or removes the limit. Once it is reached, the execution is aborted
+    /// with `ErrorKind::Interrupted` until a new limit is set.
+    ///
+    /// Unlike other errors, `ErrorKind::Interrupted` is not caught by `pcall`
+    /// or `coroutine.resume`.
+    pub fn set_instruction_limit(&self, limit: Option<u64>) {
+        self.interrupt.set_limit(limit);
+    }
+
+    pub fn remaining_instructions(&self) -> Option<u64> {
+        self.interrupt.remaining_instructions()
+    }
+
+    /// Sets a callback called every `interval` instructions executed by Lua
+    /// functions. The callback can abort the execution with
+    /// `ErrorKind::Interrupted`, e.g. once a deadline has passed.
+    pub fn set_interrupt<F>(&mut self, interval: u32, callback: F)
+    where
+        F: 'static + FnMut() -> InterruptAction,
+    {
+        self.interrupt
+            .set_callback(interval, Some(Box::new(callback)));
+    }
+
+    pub fn remove_interrupt(&mut self) {
+        self.interrupt.set_callback(0, None);
+    }
+
+    /// Sets a hook called by Lua functions of every thread on the events in
+    /// `mask`, in addition to the hooks set with `debug.sethook`. With
+    /// `mask.count`, it is called every `count` instructions.
+    ///
+    /// The hook can raise an error by returning it, which is unwound like an
+    /// error raised by the hooked function, or suspend the running coroutine
+    /// with `HookAction::Yield`.
+    pub fn set_hook<F>(&mut self, mask: HookMask, count: u32, callback: F)
+    where
+        F: 'static + FnMut(&HookInfo) -> Result<HookAction, ErrorKind>,
+    {
+        self.hook = Some(Hook::new(mask, count, Box::new(callback)));
+    }
+
+    pub fn remove_hook(&mut self) {
+        self.hook = None;
+    }
+
     fn execute_single_step(&mut self, gc: &'gc GcContext) -> Result<RuntimeAction, RuntimeError> {
         while !self.thread_stack.is_empty() {
             match self.execute_next_frame(gc) {
                 Ok(Some(action)) => return Ok(action),
                 Ok(None) => (),
-                Err(kind) => {
-                    let thread = self.current_thread();
-                    let mut thread_ref = thread.borrow_mut(gc);
-
-                    let protection_boundary = thread_ref
-                        .frames
-                        .iter_mut()
-                        .enumerate()
-                        .rev()
-                        .find_map(|(i, frame)| match frame {
-                            Frame::ProtectedCallContinuation {
-                                inner,
-                                callee_bottom,
-                            } => {
-                                inner
-                                    .continuation
-                                    .as_mut()
-                                    .unwrap()
-                                    .set_args(Err(kind.clone()));
-                                Some((i, *callee_bottom))
-                            }
-                            _ => None,
-                        });
-
-                    if let Some((frame_index, boundary)) = protection_boundary {
-                        thread_ref.close_upvalues(gc, boundary);
-                        thread_ref.frames.truncate(frame_index + 1);
-                    } else {
-                        self.thread_stack.pop().unwrap();
-                        thread_ref.status = ThreadStatus::Error(kind.clone());
-
-                        if self.thread_stack.is_empty() {
-                            let traceback = thread_ref.traceback();
-                            *thread_ref = LuaThread::new();
-                            return Err(RuntimeError { kind, traceback });
-                        }
-                        drop(thread_ref);
-
-                        let mut resumer_ref = self.thread_stack.last().unwrap().borrow_mut(gc);
-                        match resumer_ref.frames.as_mut_slice() {
-                            [.., Frame::ResumeContinuation(frame)] => {
-                                frame.continuation.as_mut().unwrap().set_args(Err(kind))
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
-                }
+                Err(kind) => self.handle_error(gc, kind)?,
+            }
+            if self.interrupt.take_pause() {
+                return Ok(RuntimeAction::Pause);
             }
             if gc.should_perform_gc() {
                 return Ok(RuntimeAction::StepGc);
@@ -288,6 +635,281 @@ impl<'gc> Vm<'gc> {
         })
     }
 
+    /// Calls `callee` with `args` and runs it to completion, returning its
+    /// results. Unlike `Action::Call`, this can be used by native functions
+    /// that need the results of a call without returning a continuation.
+    ///
+    /// The frames of the call are run on top of the current thread, so Lua
+    /// functions called this way cannot yield. Garbage can be collected
+    /// during the call, so values that are used after it have to be reachable
+    /// from Lua, like the arguments of the native function calling it.
+    pub fn call(
+        &mut self,
+        gc: &'gc GcContext,
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+    ) -> Result<Vec<Value<'gc>>, ErrorKind> {
+        let is_outermost = self.thread_stack.is_empty();
+        if is_outermost {
+            self.thread_stack.push(self.main_thread);
+        }
+        let depth = self.thread_stack.len();
+        let thread = self.current_thread();
+
+        let mut thread_ref = thread.borrow_mut(gc);
+        let bottom = thread_ref.stack.len();
+        thread_ref.frames.push(Frame::CallBoundary { bottom });
+        thread_ref.stack.push(callee);
+        thread_ref.stack.extend(args);
+        let mut result = self.push_frame(&mut thread_ref, bottom);
+        drop(thread_ref);
+
+        while result.is_ok() {
+            if self.thread_stack.len() == depth
+                && matches!(
+                    thread.borrow().frames.last(),
+                    Some(Frame::CallBoundary { .. })
+                )
+            {
+                break;
+            }
+            match self.execute_next_frame(gc) {
+                Ok(Some(RuntimeAction::MutateGc(mutator))) => {
+                    gc.with_borrowed_vm(self, || mutator(gc));
+                }
+                Ok(_) => (),
+                Err(kind) => {
+                    if let Err(err) = self.handle_error(gc, kind) {
+                        result = Err(err.kind);
+                    }
+                }
+            }
+            if gc.should_perform_gc() {
+                gc.with_borrowed_vm(self, || gc.perform_gc());
+                if gc.exceeds_memory_limit() {
+                    if let Err(err) = self.raise_memory_error(gc) {
+                        result = Err(err.kind);
+                    }
+                }
+            }
+            self.call_pending_finalizers(gc);
+        }
+
+        let mut thread_ref = thread.borrow_mut(gc);
+        match thread_ref.frames.pop() {
+            Some(Frame::CallBoundary { .. }) => (),
+            _ => unreachable!(),
+        }
+        let results = thread_ref.stack.split_off(bottom);
+        drop(thread_ref);
+        if is_outermost {
+            self.thread_stack.pop().unwrap();
+        }
+
+        result.map(|_| results)
+    }
+
+    /// Unwinds the stack to the innermost protected call of the current
+    /// thread, or kills the thread if there is none. Returns an error if the
+    /// error is not caught, either because it reached the bottom of the main
+    /// thread or a boundary of `Vm::call`.
+    fn handle_error(&mut self, gc: &'gc GcContext, kind: ErrorKind) -> Result<(), RuntimeError> {
+        if let ErrorKind::Interrupted = kind {
+            return Err(self.abort(gc, kind));
+        }
+        let mut kind = self.locate_error(kind);
+        loop {
+            let thread = self.current_thread();
+            let mut thread_ref = thread.borrow_mut(gc);
+
+            let protection_boundary = thread_ref
+                .frames
+                .iter_mut()
+                .enumerate()
+                .rev()
+                .take_while(|(_, frame)| !matches!(frame, Frame::CallBoundary { .. }))
+                .find_map(|(i, frame)| match frame {
+                    // a continuation does not catch errors raised by itself
+                    Frame::ProtectedCallContinuation {
+                        inner,
+                        callee_bottom,
+                        ..
+                    } if inner.continuation.is_some() => Some((i, *callee_bottom, None)),
+                    Frame::ProtectedCallWithHandlerContinuation {
+                        inner,
+                        callee_bottom,
+                        handler,
+                    } if inner.continuation.is_some() => Some((i, *callee_bottom, handler.take())),
+                    _ => None,
+                });
+            let call_boundary = thread_ref.call_boundary();
+
+            if let Some((_, _, Some(handler))) = protection_boundary {
+                // the handler runs before unwinding the stack, and the
+                // error is raised again with the value it returns
+                if let Err(err) =
+                    self.push_message_handler_frame(gc, &mut thread_ref, handler, &kind)
+                {
+                    kind = err;
+                    continue;
+                }
+            } else if let Some((frame_index, boundary, None)) = protection_boundary {
+                match &mut thread_ref.frames[frame_index] {
+                    Frame::ProtectedCallContinuation { inner, .. }
+                    | Frame::ProtectedCallWithHandlerContinuation { inner, .. } => inner
+                        .continuation
+                        .as_mut()
+                        .unwrap()
+                        .set_args(Err(kind.clone())),
+                    _ => unreachable!(),
+                }
+                thread_ref.close_upvalues(gc, boundary);
+                thread_ref.frames.truncate(frame_index + 1);
+
+                if let Some(slot) = thread_ref.pop_tbc_slot(boundary) {
+                    if let Err(err) = self.close_with_error(gc, &mut thread_ref, slot, &kind) {
+                        kind = err;
+                        continue;
+                    }
+                }
+            } else if let Some((frame_index, boundary)) = call_boundary {
+                thread_ref.close_upvalues(gc, boundary);
+                thread_ref.frames.truncate(frame_index + 1);
+
+                match thread_ref.pop_tbc_slot(boundary) {
+                    Some(slot) => {
+                        if let Err(err) = self.close_with_error(gc, &mut thread_ref, slot, &kind) {
+                            kind = err;
+                            continue;
+                        }
+                    }
+                    None => {
+                        return Err(RuntimeError {
+                            traceback: thread_ref.traceback(),
+                            kind,
+                        })
+                    }
+                }
+            } else {
+                self.thread_stack.pop().unwrap();
+                thread_ref.status = ThreadStatus::Error(kind.to_error_object(gc));
+
+                if self.thread_stack.is_empty() {
+                    let traceback = thread_ref.traceback();
+                    *thread_ref = LuaThread::new();
+                    return Err(RuntimeError { kind, traceback });
+                }
+                drop(thread_ref);
+
+                let mut resumer_ref = self.thread_stack.last().unwrap().borrow_mut(gc);
+                match resumer_ref.frames.as_mut_slice() {
+                    [.., Frame::ResumeContinuation(frame)] => {
+                        frame.continuation.as_mut().unwrap().set_args(Err(kind))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// Unwinds the stack to the innermost boundary of `Vm::call`, or kills
+    /// all threads if there is none, without calling message handlers or
+    /// closing to-be-closed variables.
+    fn abort(&mut self, gc: &'gc GcContext, kind: ErrorKind) -> RuntimeError {
+        let traceback = self.current_thread().borrow().traceback();
+        loop {
+            let thread = self.current_thread();
+            let mut thread_ref = thread.borrow_mut(gc);
+            if let Some((frame_index, boundary)) = thread_ref.call_boundary() {
+                thread_ref.close_upvalues(gc, boundary);
+                thread_ref.frames.truncate(frame_index + 1);
+                while thread_ref.pop_tbc_slot(boundary).is_some() {}
+                return RuntimeError { kind, traceback };
+            }
+
+            self.thread_stack.pop().unwrap();
+            if self.thread_stack.is_empty() {
+                *thread_ref = LuaThread::new();
+                return RuntimeError { kind, traceback };
+            }
+            thread_ref.status = ThreadStatus::Error(kind.to_error_object(gc));
+        }
+    }
+
+    /// Calls `__close` of the to-be-closed variable at `slot` while unwinding
+    /// the stack, and raises the error again after that to close remaining
+    /// variables.
+    fn close_with_error(
+        &self,
+        gc: &'gc GcContext,
+        thread: &mut LuaThread<'gc>,
+        slot: usize,
+        kind: &ErrorKind,
+    ) -> Result<ControlFlow<()>, ErrorKind> {
+        let reraised = kind.clone();
+        self.push_close_metamethod_frame(thread, slot, kind.to_error_object(gc), move |_, _, _| {
+            Err(reraised.clone())
+        })
+    }
+
+    /// Prefixes the error with the position of the Lua function raising it,
+    /// which is the running Lua function or the caller of the running native
+    /// function. Errors raised with a value by `error` and memory errors are
+    /// left untouched.
+    fn locate_error(&self, kind: ErrorKind) -> ErrorKind {
+        if let ErrorKind::ErrorObject { .. } | ErrorKind::Located { .. } | ErrorKind::MemoryError =
+            kind
+        {
+            return kind;
+        }
+        let thread = self.current_thread();
+        let thread_ref = thread.borrow();
+        // errors raised by native functions are located at their callers
+        let level = match thread_ref.frames.last() {
+            Some(frame) if frame.as_lua().is_some() || frame.is_internal() => 1,
+            _ => 2,
+        };
+        match thread_ref.location(level) {
+            Some(location) => ErrorKind::Located {
+                location,
+                source: Box::new(kind),
+            },
+            None => kind,
+        }
+    }
+
+    /// Calls the message handler of `xpcall` with the error message on top of
+    /// the frames that raised the error.
+    fn push_message_handler_frame(
+        &self,
+        gc: &'gc GcContext,
+        thread: &mut LuaThread<'gc>,
+        handler: Value<'gc>,
+        kind: &ErrorKind,
+    ) -> Result<ControlFlow<()>, ErrorKind> {
+        let bottom = thread.stack.len();
+        thread.frames.push(Frame::ProtectedCallContinuation {
+            inner: ContinuationFrame {
+                bottom,
+                continuation: Some(Continuation::new(
+                    |gc, _, result: Result<Vec<Value>, ErrorKind>| match result {
+                        Ok(results) => Err(ErrorKind::from_error_object(
+                            gc,
+                            results.first().copied().unwrap_or_default(),
+                        )),
+                        Err(_) => Err(ErrorKind::other("error in error handling")),
+                    },
+                )),
+            },
+            callee_bottom: bottom,
+            is_internal: true,
+        });
+        thread.stack.push(handler);
+        thread.stack.push(kind.to_error_object(gc));
+        self.push_frame(thread, bottom)
+    }
+
     pub(crate) fn push_frame(
         &self,
         thread: &mut LuaThread<'gc>,
@@ -309,11 +931,12 @@ impl<'gc> Vm<'gc> {
                 }
                 None => match self.func_name_from_call(thread, bottom) {
                     Some(Name { kind, name }) => Err(ErrorKind::other(format!(
-                        "attempt to call a nil value ({kind} {name:?})"
+                        "attempt to call a {} value ({kind} {name:?})",
+                        value.type_name()
                     ))),
                     None => Err(ErrorKind::TypeError {
                         operation: Operation::Call,
-                        ty: value.ty(),
+                        ty: value.type_name(),
                     }),
                 },
             },
@@ -322,12 +945,33 @@ impl<'gc> Vm<'gc> {
 }
 
 impl<'gc> LuaThread<'gc> {
+    /// Returns the index and the stack bottom of the innermost frame pushed
+    /// by `Vm::call`.
+    fn call_boundary(&self) -> Option<(usize, usize)> {
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, frame)| match frame {
+                Frame::CallBoundary { bottom } => Some((i, *bottom)),
+                _ => None,
+            })
+    }
+
     fn save_pc(&mut self, pc: usize) {
         match self.frames.as_mut_slice() {
             [.., Frame::Lua(frame)] => frame.pc = pc,
             _ => unreachable!(),
         }
     }
+
+    /// Pops the last to-be-closed variable if it is at or above `level`.
+    fn pop_tbc_slot(&mut self, level: usize) -> Option<usize> {
+        match self.tbc_slots.last() {
+            Some(&slot) if slot >= level => self.tbc_slots.pop(),
+            _ => None,
+        }
+    }
 }
 
 impl<'gc> Upvalue<'gc> {
@@ -1,6 +1,6 @@
-use super::{frame::ContinuationFrame, ErrorKind, Frame, RuntimeAction, Vm};
+use super::{frame::ContinuationFrame, ErrorKind, Frame, HookEvent, RuntimeAction, Vm};
 use crate::{
-    gc::{GarbageCollect, GcCell, GcContext, GcHeap, Tracer},
+    gc::{GarbageCollect, GcCell, GcContext, Tracer},
     types::{LuaThread, ThreadStatus, Value},
 };
 
@@ -15,6 +15,12 @@ pub enum Action<'gc> {
         args: Vec<Value<'gc>>,
         continuation: Continuation<'gc, Result<Vec<Value<'gc>>, ErrorKind>>,
     },
+    ProtectedCallWithHandler {
+        callee: Value<'gc>,
+        args: Vec<Value<'gc>>,
+        handler: Value<'gc>,
+        continuation: Continuation<'gc, Result<Vec<Value<'gc>>, ErrorKind>>,
+    },
     TailCall {
         callee: Value<'gc>,
         args: Vec<Value<'gc>>,
@@ -28,7 +34,7 @@ pub enum Action<'gc> {
     },
     Yield(Vec<Value<'gc>>),
     MutateGc {
-        mutator: Box<dyn Fn(&mut GcHeap)>,
+        mutator: Box<dyn Fn(&GcContext)>,
         continuation: Continuation<'gc, ()>,
     },
 }
@@ -188,6 +194,7 @@ impl<'gc> Vm<'gc> {
                         continuation: Some(continuation),
                     },
                     callee_bottom: bottom,
+                    is_internal: false,
                 };
                 thread_ref.stack.push(callee);
                 thread_ref.stack.append(&mut args);
@@ -205,11 +212,32 @@ impl<'gc> Vm<'gc> {
                         continuation: Some(continuation),
                     },
                     callee_bottom: bottom,
+                    is_internal: false,
                 };
                 thread_ref.stack.push(callee);
                 thread_ref.stack.append(&mut args);
                 self.push_frame(&mut thread_ref, bottom)?;
             }
+            Action::ProtectedCallWithHandler {
+                callee,
+                mut args,
+                handler,
+                continuation,
+            } => {
+                thread_ref.stack.truncate(bottom);
+                *thread_ref.frames.last_mut().unwrap() =
+                    Frame::ProtectedCallWithHandlerContinuation {
+                        inner: ContinuationFrame {
+                            bottom,
+                            continuation: Some(continuation),
+                        },
+                        callee_bottom: bottom,
+                        handler: Some(handler),
+                    };
+                thread_ref.stack.push(callee);
+                thread_ref.stack.append(&mut args);
+                self.push_frame(&mut thread_ref, bottom)?;
+            }
             Action::TailCall { callee, mut args } => {
                 thread_ref.frames.pop().unwrap();
                 thread_ref.stack.truncate(bottom);
@@ -218,11 +246,23 @@ impl<'gc> Vm<'gc> {
                 self.push_frame(&mut thread_ref, bottom)?;
             }
             Action::Return(mut results) => {
+                if self.is_return_hooked(&thread_ref) {
+                    // the results are kept on the stack while the hooks run
+                    thread_ref.stack.extend_from_slice(&results);
+                    drop(thread_ref);
+                    self.call_hooks(gc, HookEvent::Return)?;
+                    thread_ref = thread.borrow_mut(gc);
+                }
                 thread_ref.frames.pop().unwrap();
                 thread_ref.stack.truncate(bottom);
                 thread_ref.stack.append(&mut results);
             }
             Action::ReturnArguments => {
+                if self.is_return_hooked(&thread_ref) {
+                    drop(thread_ref);
+                    self.call_hooks(gc, HookEvent::Return)?;
+                    thread_ref = thread.borrow_mut(gc);
+                }
                 thread_ref.frames.pop().unwrap();
             }
             Action::Resume {
@@ -265,33 +305,14 @@ impl<'gc> Vm<'gc> {
 
                 self.thread_stack.push(coroutine);
                 coroutine_ref.status = ThreadStatus::Unresumable;
+                coroutine_ref.is_yielded_by_native = false;
                 coroutine_ref.stack.append(&mut args);
             }
             Action::Yield(values) => {
-                match self.thread_stack.len() {
-                    0 => unreachable!(),
-                    1 => {
-                        return Err(ErrorKind::other(
-                            "attempt to yield from outside a coroutine",
-                        ))
-                    }
-                    _ => (),
-                }
-
-                let resumer = self.thread_stack.pop().unwrap();
-                debug_assert!(GcCell::ptr_eq(&resumer, &thread));
-
+                self.yield_current_thread(gc, &mut thread_ref, values)?;
+                thread_ref.is_yielded_by_native = true;
                 thread_ref.stack.truncate(bottom);
                 thread_ref.frames.pop().unwrap();
-                thread_ref.status = ThreadStatus::Resumable;
-
-                let mut resumer_ref = self.thread_stack.last().unwrap().borrow_mut(gc);
-                match resumer_ref.frames.as_mut_slice() {
-                    [.., Frame::ResumeContinuation(frame)] => {
-                        frame.continuation.as_mut().unwrap().set_args(Ok(values))
-                    }
-                    _ => unreachable!(),
-                }
             }
             Action::MutateGc {
                 mutator,
@@ -310,4 +331,44 @@ impl<'gc> Vm<'gc> {
 
         Ok(None)
     }
+
+    /// Suspends the current thread, passing `values` to the continuation of
+    /// the thread that resumed it.
+    pub(super) fn yield_current_thread(
+        &mut self,
+        gc: &'gc GcContext,
+        thread_ref: &mut LuaThread<'gc>,
+        values: Vec<Value<'gc>>,
+    ) -> Result<(), ErrorKind> {
+        match self.thread_stack.len() {
+            0 => unreachable!(),
+            1 => {
+                return Err(ErrorKind::other(
+                    "attempt to yield from outside a coroutine",
+                ))
+            }
+            _ => (),
+        }
+        if thread_ref
+            .frames
+            .iter()
+            .any(|frame| matches!(frame, Frame::CallBoundary { .. }))
+        {
+            return Err(ErrorKind::other(
+                "attempt to yield across a C-call boundary",
+            ));
+        }
+
+        self.thread_stack.pop().unwrap();
+        thread_ref.status = ThreadStatus::Resumable;
+
+        let mut resumer_ref = self.thread_stack.last().unwrap().borrow_mut(gc);
+        match resumer_ref.frames.as_mut_slice() {
+            [.., Frame::ResumeContinuation(frame)] => {
+                frame.continuation.as_mut().unwrap().set_args(Ok(values))
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
 }
@@ -1,16 +1,20 @@
-use super::{opcode, ops, ErrorKind, Frame, LuaFrame, Metamethod, Operation, Vm};
+use super::{
+    opcode, ops, Action, ErrorKind, Frame, HookAction, HookEvent, LuaFrame, Metamethod, Operation,
+    Vm,
+};
 use crate::{
     gc::GcContext,
-    types::{Integer, Number, Table, Upvalue, UpvalueDescription, Value},
+    types::{Integer, LuaClosureProto, Number, Table, Upvalue, UpvalueDescription, Value},
     LuaClosure,
 };
 use std::{
+    cell::Cell,
     cmp::PartialOrd,
     ops::{Add, BitAnd, BitOr, BitXor, ControlFlow, Div, Mul, Sub},
 };
 
 impl<'gc> Vm<'gc> {
-    pub(super) fn execute_lua_frame(&self, gc: &'gc GcContext) -> Result<(), ErrorKind> {
+    pub(super) fn execute_lua_frame(&mut self, gc: &'gc GcContext) -> Result<(), ErrorKind> {
         let thread = self.current_thread();
         let mut thread_ref = thread.borrow_mut(gc);
 
@@ -24,6 +28,7 @@ impl<'gc> Vm<'gc> {
                 base,
                 mut pc,
                 num_extra_args,
+                ..
             } = frame;
 
             let bottom_value = thread_ref.stack[bottom];
@@ -39,9 +44,70 @@ impl<'gc> Vm<'gc> {
                 thread_ref.stack.resize(new_stack_len, Value::Nil);
             }
 
-            let (lower_stack, stack) = thread_ref.stack.split_at_mut(base);
+            // hooks are called once VARARGPREP has adjusted the arguments
+            let entry_pc = usize::from(proto.is_vararg);
+            let mut hook_mask = self.hook_mask(&thread_ref);
+            self.interrupt
+                .set_tracing(hook_mask.line || hook_mask.count);
+            let mut is_hook_yielded = std::mem::take(&mut thread_ref.is_hook_yielded);
+            if hook_mask.call && pc == entry_pc && frame.old_pc.is_none() {
+                match thread_ref.frames.as_mut_slice() {
+                    [.., Frame::Lua(frame)] => frame.old_pc = Some(entry_pc),
+                    _ => unreachable!(),
+                }
+                let event = if frame.is_tail_call {
+                    HookEvent::TailCall
+                } else {
+                    HookEvent::Call
+                };
+                thread_ref.save_pc(pc + 1);
+                drop(thread_ref);
+                let result = self.call_hooks(gc, event);
+                thread_ref = thread.borrow_mut(gc);
+                thread_ref.save_pc(pc);
+                result?;
+                continue 'start;
+            }
+
+            let (mut lower_stack, mut stack) = thread_ref.stack.split_at_mut(base);
 
             while let Some(&insn) = code.get(pc) {
+                if self.interrupt.tick() {
+                    match self.interrupt.check() {
+                        Ok(ControlFlow::Continue(())) => (),
+                        Ok(ControlFlow::Break(())) => {
+                            thread_ref.save_pc(pc);
+                            return Ok(());
+                        }
+                        Err(err) => {
+                            thread_ref.save_pc(pc);
+                            return Err(err);
+                        }
+                    }
+                    if (hook_mask.line || hook_mask.count) && pc >= entry_pc {
+                        // skips the hooks if they yielded before this
+                        // instruction and the thread has been resumed
+                        if !std::mem::take(&mut is_hook_yielded) {
+                            thread_ref.save_pc(pc + 1);
+                            drop(thread_ref);
+                            let result = self.trace_instruction(gc, pc);
+                            thread_ref = thread.borrow_mut(gc);
+                            if result? == HookAction::Yield {
+                                // keeps the top of values for the instruction
+                                // so that it can be executed once resumed
+                                if insn.opcode().properties().is_in_top && insn.b() == 0 {
+                                    thread_ref.stack.truncate(saved_stack_top);
+                                }
+                                self.yield_from_hook(gc, &mut thread_ref, pc)?;
+                                return Ok(());
+                            }
+                            hook_mask = self.hook_mask(&thread_ref);
+                            self.interrupt
+                                .set_tracing(hook_mask.line || hook_mask.count);
+                            (lower_stack, stack) = thread_ref.stack.split_at_mut(base);
+                        }
+                    }
+                }
                 pc += 1;
 
                 match insn.raw_opcode() {
@@ -65,13 +131,14 @@ impl<'gc> Vm<'gc> {
                     opcode::GETUPVAL => {
                         let value =
                             upvalues[insn.b()]
+                                .get()
                                 .borrow()
                                 .get(thread, base, lower_stack, stack);
                         stack[insn.a()] = value;
                     }
                     opcode::SETUPVAL => {
                         let value = stack[insn.a()];
-                        upvalues[insn.b()].borrow_mut(gc).set(
+                        upvalues[insn.b()].get().borrow_mut(gc).set(
                             gc,
                             thread,
                             base,
@@ -83,6 +150,7 @@ impl<'gc> Vm<'gc> {
                     opcode::GETTABUP => {
                         let table =
                             upvalues[insn.b()]
+                                .get()
                                 .borrow()
                                 .get(thread, base, lower_stack, stack);
                         let rc = match constants[insn.c() as usize] {
@@ -176,6 +244,7 @@ impl<'gc> Vm<'gc> {
                         };
                         let table =
                             upvalues[insn.a()]
+                                .get()
                                 .borrow()
                                 .get(thread, base, lower_stack, stack);
                         let c = insn.c() as usize;
@@ -197,11 +266,17 @@ impl<'gc> Vm<'gc> {
                         let rb = stack[insn.b()];
                         let c = insn.c() as usize;
                         let rkc = if insn.k() { constants[c] } else { stack[c] };
-                        let replaced = ra
+                        let replaced = match ra
                             .borrow_as_table_mut(gc)
                             .map(|mut table| table.replace(rb, rkc))
-                            .transpose()?
-                            .unwrap_or_default();
+                        {
+                            Some(Ok(replaced)) => replaced,
+                            Some(Err(err)) => {
+                                thread_ref.save_pc(pc);
+                                return Err(err.into());
+                            }
+                            None => false,
+                        };
                         if !replaced {
                             thread_ref.save_pc(pc);
                             match self.new_index_slow_path(gc, &mut thread_ref, ra, rb, rkc)? {
@@ -318,14 +393,20 @@ impl<'gc> Vm<'gc> {
                         Integer::wrapping_mul,
                         Number::mul,
                     ),
-                    opcode::MODK => ops::do_arithmetic_with_constant(
-                        stack,
-                        &mut pc,
-                        constants,
-                        insn,
-                        ops::modi,
-                        ops::modf,
-                    ),
+                    opcode::MODK => {
+                        let result = ops::do_division_with_constant(
+                            stack,
+                            &mut pc,
+                            constants,
+                            insn,
+                            ops::modi,
+                            ops::modf,
+                        );
+                        if let Err(err) = result {
+                            thread_ref.save_pc(pc);
+                            return Err(err);
+                        }
+                    }
                     opcode::POWK => ops::do_float_arithmetic_with_constant(
                         stack,
                         &mut pc,
@@ -340,14 +421,20 @@ impl<'gc> Vm<'gc> {
                         insn,
                         Number::div,
                     ),
-                    opcode::IDIVK => ops::do_arithmetic_with_constant(
-                        stack,
-                        &mut pc,
-                        constants,
-                        insn,
-                        ops::idivi,
-                        ops::idivf,
-                    ),
+                    opcode::IDIVK => {
+                        let result = ops::do_division_with_constant(
+                            stack,
+                            &mut pc,
+                            constants,
+                            insn,
+                            ops::idivi,
+                            ops::idivf,
+                        );
+                        if let Err(err) = result {
+                            thread_ref.save_pc(pc);
+                            return Err(err);
+                        }
+                    }
                     opcode::BANDK => ops::do_bitwise_op_with_constant(
                         stack,
                         &mut pc,
@@ -371,7 +458,7 @@ impl<'gc> Vm<'gc> {
                     ),
                     opcode::SHRI => {
                         let rb = stack[insn.b()];
-                        if let Some(lhs) = rb.to_integer_without_string_coercion() {
+                        if let Some(lhs) = rb.to_integer() {
                             let ic = insn.sc() as Integer;
                             stack[insn.a()] = ops::shl(lhs, -ic).into();
                             pc += 1;
@@ -379,7 +466,7 @@ impl<'gc> Vm<'gc> {
                     }
                     opcode::SHLI => {
                         let rb = stack[insn.b()];
-                        if let Some(rhs) = rb.to_integer_without_string_coercion() {
+                        if let Some(rhs) = rb.to_integer() {
                             let ic = insn.sc() as Integer;
                             stack[insn.a()] = ops::shl(ic, rhs).into();
                             pc += 1;
@@ -394,11 +481,23 @@ impl<'gc> Vm<'gc> {
                     opcode::MUL => {
                         ops::do_arithmetic(stack, &mut pc, insn, Integer::wrapping_mul, Number::mul)
                     }
-                    opcode::MOD => ops::do_arithmetic(stack, &mut pc, insn, ops::modi, ops::modf),
+                    opcode::MOD => {
+                        if let Err(err) =
+                            ops::do_division(stack, &mut pc, insn, ops::modi, ops::modf)
+                        {
+                            thread_ref.save_pc(pc);
+                            return Err(err);
+                        }
+                    }
                     opcode::POW => ops::do_float_arithmetic(stack, &mut pc, insn, Number::powf),
                     opcode::DIV => ops::do_float_arithmetic(stack, &mut pc, insn, Number::div),
                     opcode::IDIV => {
-                        ops::do_arithmetic(stack, &mut pc, insn, ops::idivi, ops::idivf)
+                        if let Err(err) =
+                            ops::do_division(stack, &mut pc, insn, ops::idivi, ops::idivf)
+                        {
+                            thread_ref.save_pc(pc);
+                            return Err(err);
+                        }
                     }
                     opcode::BAND => ops::do_bitwise_op(stack, &mut pc, insn, Integer::bitand),
                     opcode::BOR => ops::do_bitwise_op(stack, &mut pc, insn, Integer::bitor),
@@ -478,7 +577,7 @@ impl<'gc> Vm<'gc> {
                     opcode::BNOT => {
                         let a = insn.a();
                         let rb = stack[insn.b()];
-                        let result = if let Some(x) = rb.to_integer_without_string_coercion() {
+                        let result = if let Some(x) = rb.to_integer() {
                             Value::Integer(!x)
                         } else {
                             thread_ref.save_pc(pc);
@@ -555,6 +654,22 @@ impl<'gc> Vm<'gc> {
                                 }
                             }
                             strings.reverse();
+                            let len = strings
+                                .iter()
+                                .fold(0usize, |len, s| len.saturating_add(s.len()));
+                            if !gc.can_allocate(len) {
+                                drop(strings);
+                                thread_ref.save_pc(pc);
+                                drop(thread_ref);
+                                let result = self.reserve_memory(gc, len);
+                                thread_ref = thread.borrow_mut(gc);
+                                result?;
+                                // executes the instruction again, whose hooks
+                                // have already been called
+                                thread_ref.save_pc(pc - 1);
+                                thread_ref.is_hook_yielded = hook_mask.line || hook_mask.count;
+                                continue 'start;
+                            }
                             stack[a] = gc.allocate_string(strings.concat()).into();
                             if gc.should_perform_gc() {
                                 thread_ref.save_pc(pc);
@@ -563,11 +678,34 @@ impl<'gc> Vm<'gc> {
                         }
                     }
                     opcode::CLOSE => {
+                        let level = base + insn.a();
+                        thread_ref.close_upvalues(gc, level);
+                        if let Some(slot) = thread_ref.pop_tbc_slot(level) {
+                            // executes CLOSE again to close remaining variables
+                            thread_ref.save_pc(pc - 1);
+                            match self.push_close_metamethod_frame(
+                                &mut thread_ref,
+                                slot,
+                                Value::Nil,
+                                |_, _, _| Ok(Action::ReturnArguments),
+                            )? {
+                                ControlFlow::Continue(()) => continue 'start,
+                                ControlFlow::Break(()) => return Ok(()),
+                            }
+                        }
                         thread_ref.save_pc(pc);
-                        thread_ref.close_upvalues(gc, base + insn.a());
                         continue 'start;
                     }
-                    opcode::TBC => todo!("TBC"),
+                    opcode::TBC => {
+                        let a = insn.a();
+                        let value = stack[a];
+                        if value.to_boolean() {
+                            thread_ref.save_pc(pc);
+                            self.ensure_closable(proto, value, a, pc - 1)?;
+                            thread_ref.tbc_slots.push(base + a);
+                            continue 'start;
+                        }
+                    }
                     opcode::JMP => pc = (pc as isize + insn.sj() as isize) as usize,
                     opcode::EQ => {
                         let ra = stack[insn.a()];
@@ -805,16 +943,46 @@ impl<'gc> Vm<'gc> {
                         }
                         thread_ref.frames.pop().unwrap();
                         match self.push_frame(&mut thread_ref, bottom)? {
-                            ControlFlow::Continue(()) => continue 'start,
+                            ControlFlow::Continue(()) => {
+                                match thread_ref.frames.as_mut_slice() {
+                                    [.., Frame::Lua(frame)] => frame.is_tail_call = true,
+                                    _ => unreachable!(),
+                                }
+                                continue 'start;
+                            }
                             ControlFlow::Break(()) => return Ok(()),
                         }
                     }
                     opcode::RETURN => {
+                        let a = insn.a();
+                        let b = insn.b();
                         if insn.k() {
                             thread_ref.close_upvalues(gc, bottom);
+                            if let Some(slot) = thread_ref.pop_tbc_slot(base) {
+                                // keeps the top of results so that RETURN can be
+                                // executed again after closing the variable
+                                if b == 0 {
+                                    thread_ref.stack.truncate(saved_stack_top);
+                                }
+                                thread_ref.save_pc(pc - 1);
+                                match self.push_close_metamethod_frame(
+                                    &mut thread_ref,
+                                    slot,
+                                    Value::Nil,
+                                    |_, _, _| Ok(Action::ReturnArguments),
+                                )? {
+                                    ControlFlow::Continue(()) => continue 'start,
+                                    ControlFlow::Break(()) => return Ok(()),
+                                }
+                            }
+                        }
+                        if hook_mask.ret {
+                            thread_ref.save_pc(pc);
+                            drop(thread_ref);
+                            let result = self.call_hooks(gc, HookEvent::Return);
+                            thread_ref = thread.borrow_mut(gc);
+                            result?;
                         }
-                        let a = insn.a();
-                        let b = insn.b();
                         let num_results = if b > 0 {
                             b - 1
                         } else {
@@ -831,6 +999,13 @@ impl<'gc> Vm<'gc> {
                         }
                     }
                     opcode::RETURN0 => {
+                        if hook_mask.ret {
+                            thread_ref.save_pc(pc);
+                            drop(thread_ref);
+                            let result = self.call_hooks(gc, HookEvent::Return);
+                            thread_ref = thread.borrow_mut(gc);
+                            result?;
+                        }
                         thread_ref.stack.truncate(bottom);
                         thread_ref.frames.pop().unwrap();
                         match thread_ref.frames.as_slice() {
@@ -839,7 +1014,14 @@ impl<'gc> Vm<'gc> {
                         }
                     }
                     opcode::RETURN1 => {
-                        thread_ref.stack[bottom] = stack[insn.a()];
+                        if hook_mask.ret {
+                            thread_ref.save_pc(pc);
+                            drop(thread_ref);
+                            let result = self.call_hooks(gc, HookEvent::Return);
+                            thread_ref = thread.borrow_mut(gc);
+                            result?;
+                        }
+                        thread_ref.stack[bottom] = thread_ref.stack[base + insn.a()];
                         thread_ref.stack.truncate(bottom + 1);
                         thread_ref.frames.pop().unwrap();
                         match thread_ref.frames.as_slice() {
@@ -892,7 +1074,18 @@ impl<'gc> Vm<'gc> {
                             pc += insn.bx() + 1;
                         }
                     }
-                    opcode::TFORPREP => pc += insn.bx(),
+                    opcode::TFORPREP => {
+                        let a = insn.a();
+                        let value = stack[a + 3];
+                        if value.to_boolean() {
+                            thread_ref.save_pc(pc);
+                            self.ensure_closable(proto, value, a + 3, pc - 1)?;
+                            thread_ref.save_pc(pc + insn.bx());
+                            thread_ref.tbc_slots.push(base + a + 3);
+                            continue 'start;
+                        }
+                        pc += insn.bx();
+                    }
                     opcode::TFORCALL => {
                         let a = insn.a();
                         thread_ref.save_pc(pc);
@@ -936,10 +1129,28 @@ impl<'gc> Vm<'gc> {
                                 ra.borrow_as_table_mut(gc)
                                     .ok_or_else(|| ErrorKind::TypeError {
                                         operation: Operation::Index,
-                                        ty: ra.ty(),
+                                        ty: ra.type_name(),
                                     })?;
                             let new_array_len = offset + n;
                             if new_array_len > table.array().len() {
+                                let size = (new_array_len - table.array().len())
+                                    * std::mem::size_of::<Value>();
+                                if !gc.can_allocate(size) {
+                                    drop(table);
+                                    thread_ref.save_pc(pc);
+                                    drop(thread_ref);
+                                    let result = self.reserve_memory(gc, size);
+                                    thread_ref = thread.borrow_mut(gc);
+                                    result?;
+                                    // executes the instruction again, whose
+                                    // hooks have already been called
+                                    if insn.b() == 0 {
+                                        thread_ref.stack.truncate(saved_stack_top);
+                                    }
+                                    thread_ref.save_pc(pc - 1 - usize::from(insn.k()));
+                                    thread_ref.is_hook_yielded = hook_mask.line || hook_mask.count;
+                                    continue 'start;
+                                }
                                 table.resize_array(new_array_len);
                             }
                             for (i, x) in stack[a + 1..=a + n].iter().copied().enumerate() {
@@ -959,8 +1170,11 @@ impl<'gc> Vm<'gc> {
                                         gc.allocate_cell(Upvalue::Open { thread, index })
                                     })
                                 }
-                                UpvalueDescription::Upvalue(index) => upvalues[index.0 as usize],
+                                UpvalueDescription::Upvalue(index) => {
+                                    upvalues[index.0 as usize].get()
+                                }
                             })
+                            .map(Cell::new)
                             .collect();
                         thread_ref.stack[base + insn.a()] =
                             gc.allocate(LuaClosure { proto, upvalues }).into();
@@ -1027,6 +1241,10 @@ impl<'gc> Vm<'gc> {
 
                             continue 'start;
                         }
+                        if hook_mask.call {
+                            thread_ref.save_pc(pc);
+                            continue 'start;
+                        }
                     }
                     _ => unreachable!(),
                 }
@@ -1035,4 +1253,26 @@ impl<'gc> Vm<'gc> {
             unreachable!()
         }
     }
+
+    fn ensure_closable(
+        &self,
+        proto: &LuaClosureProto<'gc>,
+        value: Value<'gc>,
+        register: usize,
+        pc: usize,
+    ) -> Result<(), ErrorKind> {
+        if self
+            .metamethod_of_object(Metamethod::Close, value)
+            .is_some()
+        {
+            return Ok(());
+        }
+        // the codegen keeps the n-th active local variable in register n - 1
+        let name = proto
+            .local_name(register as u32 + 1, pc as u32)
+            .unwrap_or("?");
+        Err(ErrorKind::Other(format!(
+            "variable '{name}' got a non-closable value"
+        )))
+    }
 }
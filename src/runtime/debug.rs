@@ -18,11 +18,12 @@ impl<'gc> Vm<'gc> {
         thread: &'a LuaThread<'gc>,
         bottom: usize,
     ) -> Option<Name<'a>> {
+        // only a Lua function calling directly has the name of the callee
         let frame = thread
             .frames
-            .iter()
-            .rev()
-            .find_map(|f| f.as_lua().filter(|l| l.bottom <= bottom))?;
+            .last()?
+            .as_lua()
+            .filter(|f| f.bottom <= bottom)?;
         let closure = thread
             .stack
             .get(frame.bottom)
@@ -217,9 +218,8 @@ impl<'gc> LuaClosureProto<'gc> {
             .unwrap_or("??")
     }
 
-    const fn upvalue_name(&self, _upvalue: usize) -> Option<&str> {
-        // TODO: fetch name from upvalue
-        None
+    fn upvalue_name(&self, upvalue: usize) -> Option<&str> {
+        self.upvalue_names.as_ref()?.get(upvalue)?.as_str().ok()
     }
 
     fn kind_from_insn(&self, pc: usize, insn: Instruction, is_upvalue: bool) -> &'static str {
@@ -242,37 +242,34 @@ impl<'gc> LuaClosureProto<'gc> {
     }
 
     /// Get the line corresponding to instruction `pc` in the function.
-    pub(crate) fn func_line(&self, pc: u32) -> Option<u32> {
+    // refer to "luaG_getfuncline" in ldebug.c
+    pub fn func_line(&self, pc: u32) -> Option<u32> {
         // first gets a base line and from there does the increments until
         // the desired instruction.
-        let mut abs = self.base_line(pc);
         let line_info = self.line_info.as_ref()?;
-        let mut base_line = abs.line;
-        while abs.pc < pc {
-            base_line += line_info[abs.pc as usize] as u32;
-            abs.pc += 1;
-        }
-        Some(base_line)
+        let (first_pc, base_line) = match self.base_line(pc) {
+            Some(abs) => (abs.pc as usize + 1, abs.line),
+            None => (0, self.lines_defined.base_line()),
+        };
+        let line = line_info
+            .get(first_pc..=pc as usize)?
+            .iter()
+            .fold(base_line as i64, |line, &diff| line + diff as i8 as i64);
+        line.try_into().ok()
     }
 
-    fn base_line(&self, pc: u32) -> AbsLineInfo {
-        self.abs_line_info
-            .as_ref()
-            .and_then(|abs| {
-                let i = match abs.binary_search_by_key(&pc, |i| i.pc) {
-                    Ok(i) => i,
-                    Err(i) => i.saturating_sub(1),
-                };
-                abs.get(i).filter(|abs| abs.pc <= pc).cloned()
-            })
-            .unwrap_or_else(|| AbsLineInfo {
-                pc: 0,
-                line: self.lines_defined.base_line(),
-            })
+    /// Returns the last absolute line information at or before `pc`.
+    fn base_line(&self, pc: u32) -> Option<&AbsLineInfo> {
+        let abs = self.abs_line_info.as_ref()?;
+        let i = abs.partition_point(|abs| abs.pc <= pc);
+        i.checked_sub(1).map(|i| &abs[i])
     }
 
-    fn local_name(&self, register: u32, pc: u32) -> Option<&'_ str> {
-        if register == 0 {
+    /// Returns the name of the `n`-th local variable active at `pc`, which
+    /// lives in register `n - 1`.
+    // refer to "luaF_getlocalname" in lfunc.c
+    pub(crate) fn local_name(&self, n: u32, pc: u32) -> Option<&'_ str> {
+        if n == 0 {
             return None;
         }
         self.local_vars
@@ -280,7 +277,7 @@ impl<'gc> LuaClosureProto<'gc> {
             .iter()
             .take_while(|l| l.pc.start <= pc)
             .filter(|l| pc < l.pc.end)
-            .nth(register as usize - 1)
+            .nth(n as usize - 1)
             .and_then(|var| var.name.as_str().ok())
     }
 }
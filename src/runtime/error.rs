@@ -1,4 +1,7 @@
-use crate::types::{TableError, TracebackFrame, Type, Value};
+use crate::{
+    gc::{GcContext, RootedValue},
+    types::{TableError, TracebackFrame, Value},
+};
 use std::{borrow::Cow, fmt::Display, sync::Arc};
 
 #[derive(Debug, thiserror::Error)]
@@ -25,24 +28,27 @@ impl Display for RuntimeError {
 #[derive(Debug, thiserror::Error)]
 pub enum ErrorKind {
     #[error("attempt to {operation} a {ty} value")]
-    TypeError { operation: Operation, ty: Type },
+    TypeError {
+        operation: Operation,
+        ty: Cow<'static, str>,
+    },
 
     #[error("bad argument #{nth} ({message})")]
     ArgumentError { nth: usize, message: &'static str },
 
     #[error("bad argument #{nth} ({expected_type} expected, got {got})",
-        got = got_type.unwrap_or("no value")
+        got = got_type.as_deref().unwrap_or("no value")
     )]
     ArgumentTypeError {
         nth: usize,
         expected_type: &'static str,
-        got_type: Option<&'static str>,
+        got_type: Option<Cow<'static, str>>,
     },
 
     #[error("bad 'for' {what} (number expected, got {got_type})")]
     ForError {
         what: &'static str,
-        got_type: &'static str,
+        got_type: Cow<'static, str>,
     },
 
     #[error(transparent)]
@@ -54,6 +60,24 @@ pub enum ErrorKind {
     #[error("{0}")]
     Other(String),
 
+    #[error("{message}")]
+    ErrorObject {
+        object: RootedValue,
+        message: String,
+    },
+
+    #[error("{location} {source}")]
+    Located {
+        location: String,
+        source: Box<ErrorKind>,
+    },
+
+    #[error("not enough memory")]
+    MemoryError,
+
+    #[error("interrupted")]
+    Interrupted,
+
     #[error(transparent)]
     External(Arc<dyn std::error::Error + Send + Sync>),
 }
@@ -63,7 +87,7 @@ impl Clone for ErrorKind {
         match self {
             Self::TypeError { operation, ty } => Self::TypeError {
                 operation: *operation,
-                ty: *ty,
+                ty: ty.clone(),
             },
             Self::ArgumentError { nth, message } => Self::ArgumentError { nth: *nth, message },
             Self::ArgumentTypeError {
@@ -73,12 +97,25 @@ impl Clone for ErrorKind {
             } => Self::ArgumentTypeError {
                 nth: *nth,
                 expected_type,
-                got_type: *got_type,
+                got_type: got_type.clone(),
+            },
+            Self::ForError { what, got_type } => Self::ForError {
+                what,
+                got_type: got_type.clone(),
             },
-            Self::ForError { what, got_type } => Self::ForError { what, got_type },
             Self::Table(e) => Self::Table(e.clone()),
             Self::Io(e) => Self::Io(std::io::Error::new(e.kind(), e.to_string())),
             Self::Other(s) => Self::Other(s.clone()),
+            Self::ErrorObject { object, message } => Self::ErrorObject {
+                object: object.clone(),
+                message: message.clone(),
+            },
+            Self::Located { location, source } => Self::Located {
+                location: location.clone(),
+                source: source.clone(),
+            },
+            Self::MemoryError => Self::MemoryError,
+            Self::Interrupted => Self::Interrupted,
             Self::External(err) => Self::External(err.clone()),
         }
     }
@@ -89,13 +126,25 @@ impl ErrorKind {
         Self::Other(s.into().into_owned())
     }
 
-    pub fn from_error_object(error_object: Value) -> Self {
-        let msg = if let Some(s) = error_object.to_string() {
+    pub fn from_error_object<'gc>(gc: &'gc GcContext, error_object: Value<'gc>) -> Self {
+        let message = if let Some(s) = error_object.to_string() {
             String::from_utf8_lossy(&s).to_string()
         } else {
             format!("(error object is a {} value)", error_object.ty().name())
         };
-        Self::Other(msg)
+        Self::ErrorObject {
+            object: gc.root(error_object),
+            message,
+        }
+    }
+
+    /// Returns the value raised with the error, or the error message for
+    /// errors not raised with a Lua value.
+    pub fn to_error_object<'gc>(&self, gc: &'gc GcContext) -> Value<'gc> {
+        match self {
+            Self::ErrorObject { object, .. } => object.get(gc),
+            _ => gc.allocate_string(self.to_string().into_bytes()).into(),
+        }
     }
 }
 
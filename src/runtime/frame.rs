@@ -1,4 +1,4 @@
-use super::{Continuation, ErrorKind, Operation, RuntimeAction, Vm};
+use super::{Action, Continuation, ErrorKind, HookEvent, Operation, RuntimeAction, Vm};
 use crate::{
     gc::{GarbageCollect, GcCell, GcContext, Tracer},
     types::Value,
@@ -13,13 +13,30 @@ pub enum Frame<'gc> {
     CallContinuation {
         inner: ContinuationFrame<'gc, Vec<Value<'gc>>>,
         callee_bottom: usize,
+        /// Whether the frame was pushed by the VM, e.g. to call a metamethod,
+        /// rather than by a native function
+        is_internal: bool,
     },
     ProtectedCallContinuation {
         inner: ContinuationFrame<'gc, Result<Vec<Value<'gc>>, ErrorKind>>,
         callee_bottom: usize,
+        /// Whether the frame was pushed by the VM to call the message
+        /// handler, rather than by a native function
+        is_internal: bool,
+    },
+    ProtectedCallWithHandlerContinuation {
+        inner: ContinuationFrame<'gc, Result<Vec<Value<'gc>>, ErrorKind>>,
+        callee_bottom: usize,
+        /// `None` once the handler has been called
+        handler: Option<Value<'gc>>,
     },
     ResumeContinuation(ContinuationFrame<'gc, Result<Vec<Value<'gc>>, ErrorKind>>),
     MutateGcContinuation(ContinuationFrame<'gc, ()>),
+    /// Marks the frames run by `Vm::call`, which are not unwound past by
+    /// errors and cannot yield.
+    CallBoundary {
+        bottom: usize,
+    },
 }
 
 impl<'gc> Frame<'gc> {
@@ -29,16 +46,30 @@ impl<'gc> Frame<'gc> {
             _ => None,
         }
     }
+
+    /// Returns whether the frame was pushed by the VM rather than being the
+    /// frame of a function, so that it is not a level of the call stack.
+    pub const fn is_internal(&self) -> bool {
+        match self {
+            Self::CallContinuation { is_internal, .. }
+            | Self::ProtectedCallContinuation { is_internal, .. } => *is_internal,
+            _ => false,
+        }
+    }
 }
 
 unsafe impl GarbageCollect for Frame<'_> {
     fn trace(&self, tracer: &mut Tracer) {
         match self {
-            Self::Lua(_) | Self::Native { .. } => (),
+            Self::Lua(_) | Self::Native { .. } | Self::CallBoundary { .. } => (),
             Self::CallContinuation { inner, .. } => inner.trace(tracer),
             Self::ProtectedCallContinuation { inner, .. } | Self::ResumeContinuation(inner) => {
                 inner.trace(tracer)
             }
+            Self::ProtectedCallWithHandlerContinuation { inner, handler, .. } => {
+                inner.trace(tracer);
+                handler.trace(tracer);
+            }
             Self::MutateGcContinuation(inner) => inner.trace(tracer),
         }
     }
@@ -50,6 +81,12 @@ pub struct LuaFrame {
     pub base: usize,
     pub pc: usize,
     pub num_extra_args: usize,
+    /// Whether the function was called by a tail call, which replaced the
+    /// frame of its caller.
+    pub is_tail_call: bool,
+    // pc of the last instruction checked for line events, `None` until hooks
+    // are called for the frame
+    pub(crate) old_pc: Option<usize>,
 }
 
 impl LuaFrame {
@@ -59,6 +96,8 @@ impl LuaFrame {
             base: bottom + 1,
             pc: 0,
             num_extra_args: 0,
+            is_tail_call: false,
+            old_pc: None,
         }
     }
 }
@@ -90,10 +129,20 @@ impl<'gc> Vm<'gc> {
         let thread = self.current_thread();
         let mut thread_ref = thread.borrow_mut(gc);
 
-        if let [.., Frame::Lua(_)] = thread_ref.frames.as_slice() {
-            drop(thread_ref);
-            self.execute_lua_frame(gc)?;
-            return Ok(None);
+        match thread_ref.frames.as_slice() {
+            [.., Frame::Lua(_)] => {
+                drop(thread_ref);
+                self.execute_lua_frame(gc)?;
+                return Ok(None);
+            }
+            // the call hooks of a native function are called before its
+            // frame is popped to run it
+            [.., Frame::Native { .. }] if self.hook_mask(&thread_ref).call => {
+                drop(thread_ref);
+                self.call_hooks(gc, HookEvent::Call)?;
+                thread_ref = thread.borrow_mut(gc);
+            }
+            _ => (),
         }
 
         let mut current_frame = thread_ref.frames.pop();
@@ -102,7 +151,9 @@ impl<'gc> Vm<'gc> {
             Some(Frame::Native { bottom, .. }) => {
                 let bottom = *bottom;
                 let callee = thread_ref.stack[bottom];
-                let args = thread_ref.stack.split_off(bottom);
+                // the arguments are left on the stack while the function runs,
+                // so that they survive garbage collection during `Vm::call`
+                let args = thread_ref.stack[bottom..].to_vec();
                 drop(thread_ref);
                 let result = match &callee {
                     Value::NativeFunction(func) => (func.0)(gc, self, args),
@@ -111,10 +162,17 @@ impl<'gc> Vm<'gc> {
                     value => {
                         return Err(ErrorKind::TypeError {
                             operation: Operation::Call,
-                            ty: value.ty(),
+                            ty: value.type_name(),
                         })
                     }
                 };
+                let mut thread_ref = thread.borrow_mut(gc);
+                // the function is kept on the stack for the return hooks
+                let is_return_hooked =
+                    matches!(result, Ok(Action::Return(_))) && self.hook_mask(&thread_ref).ret;
+                thread_ref
+                    .stack
+                    .truncate(bottom + usize::from(is_return_hooked));
                 (bottom, result)
             }
             Some(Frame::CallContinuation {
@@ -124,20 +182,33 @@ impl<'gc> Vm<'gc> {
                         continuation,
                     },
                 callee_bottom,
+                ..
             }) => {
                 let mut continuation = continuation.take().unwrap();
                 continuation.set_args(thread_ref.stack.split_off(*callee_bottom));
                 drop(thread_ref);
                 (*bottom, continuation.call(gc, self))
             }
-            Some(Frame::ProtectedCallContinuation {
-                inner:
-                    ContinuationFrame {
-                        bottom,
-                        continuation,
-                    },
-                callee_bottom,
-            }) => {
+            Some(
+                Frame::ProtectedCallContinuation {
+                    inner:
+                        ContinuationFrame {
+                            bottom,
+                            continuation,
+                        },
+                    callee_bottom,
+                    ..
+                }
+                | Frame::ProtectedCallWithHandlerContinuation {
+                    inner:
+                        ContinuationFrame {
+                            bottom,
+                            continuation,
+                        },
+                    callee_bottom,
+                    ..
+                },
+            ) => {
                 let mut continuation = continuation.take().unwrap();
                 match continuation.args() {
                     Some(Ok(_)) => unreachable!(),
@@ -161,12 +232,15 @@ impl<'gc> Vm<'gc> {
                 drop(thread_ref);
                 (*bottom, continuation.take().unwrap().call(gc, self))
             }
+            Some(Frame::CallBoundary { .. }) => unreachable!(),
             None => {
                 let coroutine = self.thread_stack.pop().unwrap();
                 debug_assert!(GcCell::ptr_eq(&coroutine, &thread));
 
-                let values = std::mem::take(&mut thread_ref.stack);
+                // the results of the main thread are left on its stack for
+                // `Runtime::call`
                 if let Some(coroutine) = self.thread_stack.last() {
+                    let values = std::mem::take(&mut thread_ref.stack);
                     match coroutine.borrow_mut(gc).frames.as_mut_slice() {
                         [.., Frame::ResumeContinuation(frame)] => {
                             frame.continuation.as_mut().unwrap().set_args(Ok(values))
@@ -0,0 +1,331 @@
+use super::{Action, Continuation, ContinuationFrame, ErrorKind, Frame, LuaFrame, Vm};
+use crate::{
+    gc::{GarbageCollect, GcContext, Tracer},
+    types::{LuaThread, Value},
+};
+
+/// Events for which a hook is called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HookMask {
+    pub call: bool,
+    pub ret: bool,
+    pub line: bool,
+    pub count: bool,
+}
+
+impl HookMask {
+    pub const fn is_empty(&self) -> bool {
+        !(self.call || self.ret || self.line || self.count)
+    }
+
+    const fn union(self, other: Self) -> Self {
+        Self {
+            call: self.call || other.call,
+            ret: self.ret || other.ret,
+            line: self.line || other.line,
+            count: self.count || other.count,
+        }
+    }
+}
+
+/// An event for which a hook is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// A function was called. A Lua function is about to execute its first
+    /// instruction.
+    Call,
+    /// Like `Call`, but the function was called by a tail call.
+    TailCall,
+    /// A function is about to return.
+    Return,
+    /// A Lua function is about to execute an instruction at a new line, or
+    /// to jump back to an instruction.
+    Line,
+    /// A Lua function is about to execute an instruction after the number of
+    /// instructions given with the hook.
+    Count,
+}
+
+impl HookEvent {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Call => "call",
+            Self::TailCall => "tail call",
+            Self::Return => "return",
+            Self::Line => "line",
+            Self::Count => "count",
+        }
+    }
+}
+
+/// What the VM does after a hook set with `Vm::set_hook` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Yields the running coroutine with no values before the instruction is
+    /// executed, discarding the values it is resumed with. Only hooks called
+    /// for `HookEvent::Line` and `HookEvent::Count` can yield.
+    Yield,
+}
+
+/// The running function passed to a hook set with `Vm::set_hook`.
+#[derive(Debug)]
+pub struct HookInfo<'a> {
+    pub event: HookEvent,
+    /// Frame of the function, whose `pc` is past the current instruction.
+    /// `None` for native functions.
+    pub frame: Option<&'a LuaFrame>,
+    /// Source of the chunk the function was loaded from, or `=[C]` for
+    /// native functions.
+    pub source: &'a [u8],
+    /// Current line, if the function is a Lua function with line
+    /// information.
+    pub line: Option<u32>,
+}
+
+type HookCallback = Box<dyn FnMut(&HookInfo) -> Result<HookAction, ErrorKind>>;
+
+/// A hook set with `Vm::set_hook`.
+pub(super) struct Hook {
+    mask: HookMask,
+    count: u32,
+    // instructions left before the next count event
+    countdown: u32,
+    callback: HookCallback,
+}
+
+impl Hook {
+    pub fn new(mask: HookMask, count: u32, callback: HookCallback) -> Self {
+        let count = count.max(1);
+        Self {
+            mask,
+            count,
+            countdown: count,
+            callback,
+        }
+    }
+}
+
+/// A Lua function set as the hook of a thread with `debug.sethook`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LuaHook<'gc> {
+    pub function: Value<'gc>,
+    pub mask: HookMask,
+    // instructions between count events
+    pub count: u32,
+    // instructions left before the next count event
+    pub countdown: u32,
+}
+
+unsafe impl GarbageCollect for LuaHook<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.function.trace(tracer);
+    }
+}
+
+impl<'gc> Vm<'gc> {
+    /// Returns the events for which hooks are called in `thread`.
+    pub(super) fn hook_mask(&self, thread: &LuaThread<'gc>) -> HookMask {
+        let mut mask = self.hook.as_ref().map(|hook| hook.mask).unwrap_or_default();
+        if let Some(hook) = thread.hook.filter(|_| thread.hook_frame.is_none()) {
+            mask = mask.union(hook.mask);
+        }
+        mask
+    }
+
+    /// Calls the hooks for a call or return event of the function whose frame
+    /// is on top of the current thread.
+    pub(super) fn call_hooks(
+        &mut self,
+        gc: &'gc GcContext,
+        event: HookEvent,
+    ) -> Result<(), ErrorKind> {
+        let is_hooked = |mask: HookMask| match event {
+            HookEvent::Call | HookEvent::TailCall => mask.call,
+            HookEvent::Return => mask.ret,
+            HookEvent::Line | HookEvent::Count => unreachable!(),
+        };
+        let is_rust_hooked = self.hook.as_ref().is_some_and(|hook| is_hooked(hook.mask));
+        let thread = self.current_thread();
+        let thread_ref = thread.borrow();
+        let is_lua_hooked = thread_ref
+            .hook
+            .is_some_and(|hook| thread_ref.hook_frame.is_none() && is_hooked(hook.mask));
+        drop(thread_ref);
+
+        if self.dispatch_hook(gc, event, is_rust_hooked, is_lua_hooked)? == HookAction::Yield {
+            return Err(ErrorKind::other(
+                "attempt to yield from a call or return hook",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the hooks are called for the return event of the
+    /// native function whose frame is on top of `thread`. Frames pushed by
+    /// the VM do not belong to a function.
+    pub(super) fn is_return_hooked(&self, thread: &LuaThread<'gc>) -> bool {
+        self.hook_mask(thread).ret && !thread.frames.last().is_some_and(Frame::is_internal)
+    }
+
+    /// Calls the count and line hooks before the instruction at `pc` of the
+    /// Lua function running in the current thread is executed. Its saved pc
+    /// has to be past the instruction.
+    // refer to "luaG_traceexec" in ldebug.c
+    pub(super) fn trace_instruction(
+        &mut self,
+        gc: &'gc GcContext,
+        pc: usize,
+    ) -> Result<HookAction, ErrorKind> {
+        let thread = self.current_thread();
+        let events = {
+            let mut thread_ref = thread.borrow_mut(gc);
+            let thread_ref = &mut *thread_ref;
+
+            let is_rust_counted = match &mut self.hook {
+                Some(hook) if hook.mask.count => count_down(&mut hook.countdown, hook.count),
+                _ => false,
+            };
+            let is_lua_counted = match &mut thread_ref.hook {
+                Some(hook) if thread_ref.hook_frame.is_none() && hook.mask.count => {
+                    count_down(&mut hook.countdown, hook.count)
+                }
+                _ => false,
+            };
+
+            let is_rust_lined = self.hook.as_ref().is_some_and(|hook| hook.mask.line);
+            let is_lua_lined = thread_ref
+                .hook
+                .is_some_and(|hook| thread_ref.hook_frame.is_none() && hook.mask.line);
+            let is_new_line = if is_rust_lined || is_lua_lined {
+                let frame = match thread_ref.frames.as_mut_slice() {
+                    [.., Frame::Lua(frame)] => frame,
+                    _ => unreachable!(),
+                };
+                let proto = thread_ref.stack[frame.bottom]
+                    .as_lua_closure()
+                    .unwrap()
+                    .proto;
+                // calls the hook when entering the function, when jumping back
+                // (loops, even to the same line) and when entering a new line
+                let is_new_line = match frame.old_pc {
+                    None => true,
+                    Some(old_pc) => {
+                        pc <= old_pc || proto.func_line(old_pc as u32) != proto.func_line(pc as u32)
+                    }
+                };
+                frame.old_pc = Some(pc);
+                is_new_line
+            } else {
+                false
+            };
+            [
+                (HookEvent::Count, is_rust_counted, is_lua_counted),
+                (
+                    HookEvent::Line,
+                    is_rust_lined && is_new_line,
+                    is_lua_lined && is_new_line,
+                ),
+            ]
+        };
+
+        let mut action = HookAction::Continue;
+        for (event, is_rust_hooked, is_lua_hooked) in events {
+            if (is_rust_hooked || is_lua_hooked)
+                && self.dispatch_hook(gc, event, is_rust_hooked, is_lua_hooked)?
+                    == HookAction::Yield
+            {
+                action = HookAction::Yield;
+            }
+        }
+        Ok(action)
+    }
+
+    fn dispatch_hook(
+        &mut self,
+        gc: &'gc GcContext,
+        event: HookEvent,
+        is_rust_hooked: bool,
+        is_lua_hooked: bool,
+    ) -> Result<HookAction, ErrorKind> {
+        let thread = self.current_thread();
+        let thread_ref = thread.borrow();
+        // native functions are reported like by `debug.getinfo`
+        let (frame, source, line) = match thread_ref.frames.as_slice() {
+            [.., Frame::Lua(frame)] => {
+                let proto = thread_ref.stack[frame.bottom]
+                    .as_lua_closure()
+                    .unwrap()
+                    .proto;
+                (Some(frame), Some(proto.source), proto.current_line(frame))
+            }
+            _ => (None, None, None),
+        };
+        let source = source.as_ref().map_or(b"=[C]".as_slice(), |s| s.as_bytes());
+
+        let mut action = HookAction::Continue;
+        if is_rust_hooked {
+            let hook = self.hook.as_mut().unwrap();
+            action = (hook.callback)(&HookInfo {
+                event,
+                frame,
+                source,
+                line,
+            })?;
+        }
+
+        let function = thread_ref.hook.map(|hook| hook.function);
+        drop(thread_ref);
+        if let Some(function) = function.filter(|_| is_lua_hooked) {
+            let line = match (event, line) {
+                (HookEvent::Line, Some(line)) => Value::Integer(line.into()),
+                _ => Value::Nil,
+            };
+            let args = vec![gc.allocate_string(event.name().as_bytes()).into(), line];
+            let mut thread_ref = thread.borrow_mut(gc);
+            thread_ref.hook_frame = Some(thread_ref.frames.len());
+            drop(thread_ref);
+            let result = self.call(gc, function, args);
+            thread.borrow_mut(gc).hook_frame = None;
+            result?;
+        }
+        Ok(action)
+    }
+
+    /// Suspends the current thread before the instruction at `pc` of its
+    /// running Lua function is executed, after a hook returned
+    /// `HookAction::Yield`.
+    pub(super) fn yield_from_hook(
+        &mut self,
+        gc: &'gc GcContext,
+        thread_ref: &mut LuaThread<'gc>,
+        pc: usize,
+    ) -> Result<(), ErrorKind> {
+        self.yield_current_thread(gc, thread_ref, Vec::new())?;
+        thread_ref.save_pc(pc);
+        thread_ref.is_hook_yielded = true;
+
+        // discards the values the thread is resumed with
+        let bottom = thread_ref.stack.len();
+        thread_ref.frames.push(Frame::CallContinuation {
+            inner: ContinuationFrame {
+                bottom,
+                continuation: Some(Continuation::new(|_, _, _| Ok(Action::ReturnArguments))),
+            },
+            callee_bottom: bottom,
+            is_internal: true,
+        });
+        Ok(())
+    }
+}
+
+/// Counts an instruction, returning `true` every `count` instructions.
+fn count_down(countdown: &mut u32, count: u32) -> bool {
+    *countdown -= 1;
+    if *countdown == 0 {
+        *countdown = count;
+        true
+    } else {
+        false
+    }
+}
@@ -0,0 +1,211 @@
+use super::ErrorKind;
+use std::{
+    cell::{Cell, RefCell},
+    ops::ControlFlow,
+    time::{Duration, Instant},
+};
+
+// instructions between checks of the deadline of a time slice
+const DEADLINE_CHECK_INTERVAL: u32 = 1000;
+
+/// The work `Execution::step` is allowed to do before pausing.
+#[derive(Debug, Clone, Copy)]
+pub enum Budget {
+    /// Number of instructions executed by Lua functions.
+    Instructions(u64),
+    /// Wall time, checked between instructions of Lua functions.
+    Time(Duration),
+}
+
+/// What the VM does after an interrupt callback returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptAction {
+    Continue,
+    /// Aborts the execution with `ErrorKind::Interrupted`.
+    Abort,
+}
+
+type InterruptCallback = Box<dyn FnMut() -> InterruptAction>;
+
+/// Counts the instructions executed by Lua functions to enforce the
+/// instruction limit and call the interrupt callback.
+pub(super) struct Interrupt {
+    // instructions left before the next check
+    countdown: Cell<u32>,
+    // instructions between the last check and the next one
+    period: Cell<u32>,
+    limit: Cell<Option<u64>>,
+    // instructions left in the current slice of `Execution::step`
+    slice: Cell<Option<u64>>,
+    deadline: Cell<Option<Instant>>,
+    is_paused: Cell<bool>,
+    // whether `check` is called before every instruction, for hooks
+    is_tracing: Cell<bool>,
+    interval: u32,
+    // instructions left before the next call of the callback
+    callback_countdown: Cell<u32>,
+    callback: RefCell<Option<InterruptCallback>>,
+}
+
+impl Default for Interrupt {
+    fn default() -> Self {
+        let interrupt = Self {
+            countdown: Default::default(),
+            period: Default::default(),
+            limit: Default::default(),
+            slice: Default::default(),
+            deadline: Default::default(),
+            is_paused: Default::default(),
+            is_tracing: Default::default(),
+            interval: u32::MAX,
+            callback_countdown: Cell::new(u32::MAX),
+            callback: Default::default(),
+        };
+        interrupt.reset_countdown();
+        interrupt
+    }
+}
+
+impl Interrupt {
+    pub fn set_limit(&self, limit: Option<u64>) {
+        self.account();
+        self.limit.set(limit);
+        self.reset_countdown();
+    }
+
+    /// Starts a slice of execution ending once `budget` is used up, or ends
+    /// the current one if `budget` is `None`.
+    pub fn set_slice(&self, budget: Option<Budget>) {
+        let (slice, deadline) = match budget {
+            Some(Budget::Instructions(n)) => (Some(n), None),
+            Some(Budget::Time(duration)) => (None, Some(Instant::now() + duration)),
+            None => (None, None),
+        };
+        self.account();
+        self.slice.set(slice);
+        self.deadline.set(deadline);
+        self.is_paused.set(false);
+        self.reset_countdown();
+    }
+
+    /// Returns `true` once if the current slice has ended.
+    pub fn take_pause(&self) -> bool {
+        self.is_paused.replace(false)
+    }
+
+    pub fn remaining_instructions(&self) -> Option<u64> {
+        let executed = self.period.get() - self.countdown.get();
+        self.limit
+            .get()
+            .map(|limit| limit.saturating_sub(executed as u64))
+    }
+
+    pub fn set_callback(&mut self, interval: u32, callback: Option<InterruptCallback>) {
+        self.account();
+        self.interval = if callback.is_some() {
+            interval.max(1)
+        } else {
+            u32::MAX
+        };
+        *self.callback.get_mut() = callback;
+        self.callback_countdown.set(self.interval);
+        self.reset_countdown();
+    }
+
+    /// Makes `tick` return `true` for every instruction while line or count
+    /// hooks are set.
+    pub fn set_tracing(&self, is_tracing: bool) {
+        if self.is_tracing.get() != is_tracing {
+            self.account();
+            self.is_tracing.set(is_tracing);
+            self.reset_countdown();
+        }
+    }
+
+    /// Counts an instruction about to be executed. Returns `true` if `check`
+    /// has to be called before executing it.
+    #[inline]
+    pub fn tick(&self) -> bool {
+        match self.countdown.get() {
+            0 => true,
+            n => {
+                self.countdown.set(n - 1);
+                false
+            }
+        }
+    }
+
+    /// Returns `ControlFlow::Break` if the Lua function has to return to let
+    /// the current slice end. Frames run by `Vm::call` keep running after
+    /// that, and the execution pauses once they return.
+    pub fn check(&self) -> Result<ControlFlow<()>, ErrorKind> {
+        self.account();
+        if self.limit.get() == Some(0) {
+            return Err(ErrorKind::Interrupted);
+        }
+
+        let mut flow = ControlFlow::Continue(());
+        let is_past_deadline = self
+            .deadline
+            .get()
+            .is_some_and(|deadline| Instant::now() >= deadline);
+        if self.slice.get() == Some(0) || is_past_deadline {
+            self.slice.set(None);
+            self.deadline.set(None);
+            self.is_paused.set(true);
+            flow = ControlFlow::Break(());
+        }
+
+        let should_call_callback = self.callback_countdown.get() == 0;
+        if should_call_callback {
+            self.callback_countdown.set(self.interval);
+        }
+        self.reset_countdown();
+        if self.is_tracing.get() {
+            // the instruction about to be executed is accounted by the next
+            // check
+            self.period.set(1);
+        }
+
+        if should_call_callback {
+            if let Ok(mut callback) = self.callback.try_borrow_mut() {
+                if let Some(callback) = callback.as_mut() {
+                    if callback() == InterruptAction::Abort {
+                        return Err(ErrorKind::Interrupted);
+                    }
+                }
+            }
+        }
+        Ok(flow)
+    }
+
+    /// Subtracts the instructions executed since the last check from the
+    /// counters.
+    fn account(&self) {
+        let executed = self.period.get() - self.countdown.get();
+        self.period.set(self.countdown.get());
+        for counter in [&self.limit, &self.slice] {
+            counter.set(counter.get().map(|n| n.saturating_sub(executed as u64)));
+        }
+        self.callback_countdown
+            .set(self.callback_countdown.get() - executed);
+    }
+
+    fn reset_countdown(&self) {
+        if self.is_tracing.get() {
+            self.period.set(0);
+            self.countdown.set(0);
+            return;
+        }
+
+        let mut period = self.callback_countdown.get();
+        for n in [self.limit.get(), self.slice.get()].into_iter().flatten() {
+            period = period.min(n.try_into().unwrap_or(u32::MAX));
+        }
+        if self.deadline.get().is_some() {
+            period = period.min(DEADLINE_CHECK_INTERVAL);
+        }
+        self.period.set(period);
+        self.countdown.set(period);
+    }
+}
@@ -3,7 +3,7 @@ use super::{
 };
 use crate::{
     gc::GcContext,
-    types::{LuaString, LuaThread, Value},
+    types::{LuaString, LuaThread, Type, Value},
 };
 use bstr::B;
 use std::ops::ControlFlow;
@@ -66,22 +66,36 @@ metamethods!(
     Concat => "__concat",
     Call => "__call",
     Close => "__close",
+    ToString => "__tostring",
+    Name => "__name",
 );
 
+/// Where a chain of `__index` or `__newindex` metamethods ends.
+pub(crate) enum MetamethodChain<'gc, T> {
+    /// The chain ended at a table, giving `T`.
+    Done(T),
+    /// The chain ended at a function, which has to be called with `table`
+    /// followed by the key (and the value for `__newindex`).
+    Call {
+        metamethod: Value<'gc>,
+        table: Value<'gc>,
+    },
+}
+
+// refer to "MAXTAGLOOP" in lvm.c
+const MAX_TAG_LOOP: usize = 2000;
+
 impl<'gc> Vm<'gc> {
-    pub(super) fn index_slow_path<K>(
+    /// Follows the `__index` metamethods of `table_like` once a raw get of
+    /// `key` from it gave nil or was not possible.
+    // refer to "luaV_finishget" in lvm.c
+    pub(crate) fn index_chain(
         &self,
-        thread: &mut LuaThread<'gc>,
         mut table_like: Value<'gc>,
-        key: K,
-        dest: usize,
-    ) -> Result<ControlFlow<()>, ErrorKind>
-    where
-        K: Into<Value<'gc>>,
-    {
-        let key = key.into();
+        key: Value<'gc>,
+    ) -> Result<MetamethodChain<'gc, Value<'gc>>, ErrorKind> {
         let index_key = self.metamethod_name(Metamethod::Index);
-        for _ in 0..2000 {
+        for _ in 0..MAX_TAG_LOOP {
             let metamethod = if let Value::Table(table) = table_like {
                 let metamethod = table
                     .borrow()
@@ -89,8 +103,7 @@ impl<'gc> Vm<'gc> {
                     .map(|metatable| metatable.borrow().get_field(index_key))
                     .unwrap_or_default();
                 if metamethod.is_nil() {
-                    thread.stack[dest] = Value::Nil;
-                    return Ok(ControlFlow::Continue(()));
+                    return Ok(MetamethodChain::Done(Value::Nil));
                 }
                 metamethod
             } else {
@@ -101,29 +114,22 @@ impl<'gc> Vm<'gc> {
                 if metamethod.is_nil() {
                     return Err(ErrorKind::TypeError {
                         operation: Operation::Index,
-                        ty: table_like.ty(),
+                        ty: table_like.type_name(),
                     });
                 }
                 metamethod
             };
             match metamethod {
                 Value::NativeFunction(_) | Value::LuaClosure(_) | Value::NativeClosure(_) => {
-                    return Ok(self.push_metamethod_frame_with_continuation(
-                        thread,
+                    return Ok(MetamethodChain::Call {
                         metamethod,
-                        &[table_like, key],
-                        move |gc, vm, results| {
-                            vm.current_thread().borrow_mut(gc).stack[dest] =
-                                results.first().copied().unwrap_or_default();
-                            Ok(Action::ReturnArguments)
-                        },
-                    ));
+                        table: table_like,
+                    });
                 }
                 Value::Table(table) => {
                     let value = table.borrow().get(key);
                     if !value.is_nil() {
-                        thread.stack[dest] = value;
-                        return Ok(ControlFlow::Continue(()));
+                        return Ok(MetamethodChain::Done(value));
                     }
                 }
                 Value::Nil => unreachable!(),
@@ -134,21 +140,19 @@ impl<'gc> Vm<'gc> {
         Err(ErrorKind::other("'__index' chain too long; possible loop"))
     }
 
-    pub(super) fn new_index_slow_path<K, V>(
+    /// Follows the `__newindex` metamethods of `table_like` once a raw set of
+    /// an existing `key` in it was not possible, setting `value` in the table
+    /// the chain ends at.
+    // refer to "luaV_finishset" in lvm.c
+    pub(crate) fn new_index_chain(
         &self,
         gc: &'gc GcContext,
-        thread: &mut LuaThread<'gc>,
         mut table_like: Value<'gc>,
-        key: K,
-        value: V,
-    ) -> Result<ControlFlow<()>, ErrorKind>
-    where
-        K: Into<Value<'gc>>,
-        V: Into<Value<'gc>>,
-    {
-        let key = key.into();
+        key: Value<'gc>,
+        value: Value<'gc>,
+    ) -> Result<MetamethodChain<'gc, ()>, ErrorKind> {
         let new_index_key = self.metamethod_name(Metamethod::NewIndex);
-        for _ in 0..2000 {
+        for _ in 0..MAX_TAG_LOOP {
             let metamethod = if let Value::Table(table) = table_like {
                 let metamethod = table
                     .borrow()
@@ -157,7 +161,7 @@ impl<'gc> Vm<'gc> {
                     .unwrap_or_default();
                 if metamethod.is_nil() {
                     table.borrow_mut(gc).set(key, value)?;
-                    return Ok(ControlFlow::Continue(()));
+                    return Ok(MetamethodChain::Done(()));
                 }
                 metamethod
             } else {
@@ -168,24 +172,22 @@ impl<'gc> Vm<'gc> {
                 if metamethod.is_nil() {
                     return Err(ErrorKind::TypeError {
                         operation: Operation::Index,
-                        ty: table_like.ty(),
+                        ty: table_like.type_name(),
                     });
                 }
                 metamethod
             };
             match metamethod {
                 Value::NativeFunction(_) | Value::LuaClosure(_) | Value::NativeClosure(_) => {
-                    return Ok(self.push_metamethod_frame(
-                        thread,
+                    return Ok(MetamethodChain::Call {
                         metamethod,
-                        &[table_like, key, value.into()],
-                    ));
+                        table: table_like,
+                    });
                 }
                 Value::Table(table) => {
-                    let value = table.borrow().get(key);
-                    if !value.is_nil() {
-                        table.borrow_mut(gc).set(key, value)?;
-                        return Ok(ControlFlow::Continue(()));
+                    let replaced = table.borrow_mut(gc).replace(key, value)?;
+                    if replaced {
+                        return Ok(MetamethodChain::Done(()));
                     }
                 }
                 Value::Nil => unreachable!(),
@@ -198,6 +200,61 @@ impl<'gc> Vm<'gc> {
         ))
     }
 
+    pub(super) fn index_slow_path<K>(
+        &self,
+        thread: &mut LuaThread<'gc>,
+        table_like: Value<'gc>,
+        key: K,
+        dest: usize,
+    ) -> Result<ControlFlow<()>, ErrorKind>
+    where
+        K: Into<Value<'gc>>,
+    {
+        let key = key.into();
+        match self.index_chain(table_like, key)? {
+            MetamethodChain::Done(value) => {
+                thread.stack[dest] = value;
+                Ok(ControlFlow::Continue(()))
+            }
+            MetamethodChain::Call { metamethod, table } => Ok(self
+                .push_metamethod_frame_with_continuation(
+                    thread,
+                    metamethod,
+                    &[table, key],
+                    move |gc, vm, results| {
+                        vm.current_thread().borrow_mut(gc).stack[dest] =
+                            results.first().copied().unwrap_or_default();
+                        Ok(Action::ReturnArguments)
+                    },
+                )),
+        }
+    }
+
+    pub(super) fn new_index_slow_path<K, V>(
+        &self,
+        gc: &'gc GcContext,
+        thread: &mut LuaThread<'gc>,
+        table_like: Value<'gc>,
+        key: K,
+        value: V,
+    ) -> Result<ControlFlow<()>, ErrorKind>
+    where
+        K: Into<Value<'gc>>,
+        V: Into<Value<'gc>>,
+    {
+        let key = key.into();
+        let value = value.into();
+        match self.new_index_chain(gc, table_like, key, value)? {
+            // returns to collect garbage if the table grew past the memory
+            // limit, which raises a memory error if it is still exceeded
+            MetamethodChain::Done(()) if gc.exceeds_memory_limit() => Ok(ControlFlow::Break(())),
+            MetamethodChain::Done(()) => Ok(ControlFlow::Continue(())),
+            MetamethodChain::Call { metamethod, table } => {
+                Ok(self.push_metamethod_frame(thread, metamethod, &[table, key, value]))
+            }
+        }
+    }
+
     pub(super) fn arithmetic_slow_path(
         &self,
         thread: &mut LuaThread<'gc>,
@@ -212,18 +269,25 @@ impl<'gc> Vm<'gc> {
         let metamethod_value = match metamethod_value {
             Some(value) => value,
             None => {
+                let is_number = |value: Value| value.to_number_without_string_coercion().is_some();
                 let operation = match metamethod {
                     Metamethod::BAnd
                     | Metamethod::BOr
                     | Metamethod::BXor
                     | Metamethod::Shl
                     | Metamethod::Shr
-                    | Metamethod::BNot => Operation::BitwiseOp,
+                    | Metamethod::BNot => {
+                        if is_number(a) && is_number(b) {
+                            return Err(ErrorKind::other("number has no integer representation"));
+                        }
+                        Operation::BitwiseOp
+                    }
                     _ => Operation::Arithmetic,
                 };
+                let culprit = if is_number(a) { b } else { a };
                 return Err(ErrorKind::TypeError {
                     operation,
-                    ty: b.ty(),
+                    ty: culprit.type_name(),
                 });
             }
         };
@@ -254,7 +318,7 @@ impl<'gc> Vm<'gc> {
             .or_else(|| self.metamethod_of_object(metamethod, b))
             .ok_or_else(|| ErrorKind::TypeError {
                 operation: Operation::Compare,
-                ty: b.ty(),
+                ty: b.type_name(),
             })?;
 
         let insn = code[pc - 1];
@@ -287,7 +351,7 @@ impl<'gc> Vm<'gc> {
             .metamethod_of_object(Metamethod::Len, value)
             .ok_or_else(|| ErrorKind::TypeError {
                 operation: Operation::Length,
-                ty: value.ty(),
+                ty: value.type_name(),
             })?;
 
         Ok(self.push_metamethod_frame_with_continuation(
@@ -319,7 +383,7 @@ impl<'gc> Vm<'gc> {
             .or_else(|| self.metamethod_of_object(Metamethod::Concat, rhs))
             .ok_or_else(|| ErrorKind::TypeError {
                 operation: Operation::Concatenate,
-                ty: rhs.ty(),
+                ty: rhs.type_name(),
             })?;
 
         Ok(self.push_metamethod_frame_with_continuation(
@@ -363,7 +427,28 @@ impl<'gc> Vm<'gc> {
                     return Ok(Action::ReturnArguments);
                 }
                 strings.reverse();
-                stack[dest] = gc.allocate_string(strings.concat()).into();
+                let len = strings
+                    .iter()
+                    .fold(0usize, |len, s| len.saturating_add(s.len()));
+                if gc.can_allocate(len) {
+                    stack[dest] = gc.allocate_string(strings.concat()).into();
+                    return Ok(Action::ReturnArguments);
+                }
+
+                // keeps the result of the metamethod reachable while garbage
+                // is collected
+                drop(strings);
+                stack[dest + lhs_index] = concatenated;
+                drop(thread);
+                vm.reserve_memory(gc, len)?;
+                let thread = vm.current_thread();
+                let mut thread = thread.borrow_mut(gc);
+                let strings: Vec<_> = thread.stack[dest..=dest + lhs_index]
+                    .iter()
+                    .map(|value| value.to_string().unwrap())
+                    .collect();
+                let concatenated = gc.allocate_string(strings.concat());
+                thread.stack[dest] = concatenated.into();
                 Ok(Action::ReturnArguments)
             },
         ))
@@ -407,7 +492,44 @@ impl<'gc> Vm<'gc> {
                 continuation: Some(Continuation::new(continuation)),
             },
             callee_bottom: metamethod_bottom,
+            is_internal: true,
         });
         self.push_frame(thread, metamethod_bottom).unwrap()
     }
+
+    /// Pushes a call to `__close` metamethod of the to-be-closed variable at
+    /// `slot` with `error` as the error object.
+    pub(super) fn push_close_metamethod_frame<F>(
+        &self,
+        thread: &mut LuaThread<'gc>,
+        slot: usize,
+        error: Value<'gc>,
+        continuation: F,
+    ) -> Result<ControlFlow<()>, ErrorKind>
+    where
+        F: 'static
+            + Fn(&'gc GcContext, &mut Vm<'gc>, Vec<Value<'gc>>) -> Result<Action<'gc>, ErrorKind>,
+    {
+        let value = thread.stack[slot];
+        let metamethod = self
+            .metamethod_of_object(Metamethod::Close, value)
+            .unwrap_or_default();
+        if metamethod.ty() != Type::Function {
+            return Err(ErrorKind::TypeError {
+                operation: Operation::Call,
+                ty: metamethod.type_name(),
+            });
+        }
+        let metamethod_bottom = thread.stack.len();
+        thread.stack.extend_from_slice(&[metamethod, value, error]);
+        thread.frames.push(Frame::CallContinuation {
+            inner: ContinuationFrame {
+                bottom: metamethod_bottom,
+                continuation: Some(Continuation::new(continuation)),
+            },
+            callee_bottom: metamethod_bottom,
+            is_internal: true,
+        });
+        self.push_frame(thread, metamethod_bottom)
+    }
 }
@@ -1,10 +1,16 @@
-use super::{ErrorKind, Instruction};
+use super::{ErrorKind, Instruction, Metamethod};
 use crate::{
     number_is_valid_integer,
     types::{Integer, Number, Value},
 };
+use std::ops::{Add, Div, Mul, Sub};
 
-fn arithmetic<'gc, I, F>(a: Value<'gc>, b: Value<'gc>, int_op: I, float_op: F) -> Option<Value<'gc>>
+pub(crate) fn arithmetic<'gc, I, F>(
+    a: Value<'gc>,
+    b: Value<'gc>,
+    int_op: I,
+    float_op: F,
+) -> Option<Value<'gc>>
 where
     I: Fn(Integer, Integer) -> Integer,
     F: Fn(Number, Number) -> Number,
@@ -21,6 +27,52 @@ where
     None
 }
 
+/// Like `arithmetic`, but for integer division and modulo, which raise an
+/// error if the divisor is zero.
+pub(crate) fn division<'gc, I, F>(
+    a: Value<'gc>,
+    b: Value<'gc>,
+    int_op: I,
+    float_op: F,
+) -> Result<Option<Value<'gc>>, ErrorKind>
+where
+    I: Fn(Integer, Integer) -> Result<Integer, ErrorKind>,
+    F: Fn(Number, Number) -> Number,
+{
+    if let (Value::Integer(a), Value::Integer(b)) = (a, b) {
+        return Ok(Some(Value::Integer(int_op(a, b)?)));
+    }
+    Ok(arithmetic(a, b, |_, _| unreachable!(), float_op))
+}
+
+/// Performs the arithmetic operation corresponding to `metamethod` without
+/// falling back to metamethods. Returns `None` if either operand is not a
+/// number.
+pub(crate) fn arithmetic_by_metamethod<'gc>(
+    metamethod: Metamethod,
+    a: Value<'gc>,
+    b: Value<'gc>,
+) -> Result<Option<Value<'gc>>, ErrorKind> {
+    let float_op = |float_op: fn(Number, Number) -> Number| match (
+        a.to_number_without_string_coercion(),
+        b.to_number_without_string_coercion(),
+    ) {
+        (Some(a), Some(b)) => Some(Value::Number(float_op(a, b))),
+        _ => None,
+    };
+    Ok(match metamethod {
+        Metamethod::Add => arithmetic(a, b, Integer::wrapping_add, Number::add),
+        Metamethod::Sub => arithmetic(a, b, Integer::wrapping_sub, Number::sub),
+        Metamethod::Mul => arithmetic(a, b, Integer::wrapping_mul, Number::mul),
+        Metamethod::Div => float_op(Number::div),
+        Metamethod::Pow => float_op(Number::powf),
+        Metamethod::Mod => division(a, b, modi, modf)?,
+        Metamethod::IDiv => division(a, b, idivi, idivf)?,
+        Metamethod::Unm => arithmetic(a, a, |x, _| x.wrapping_neg(), |x, _| -x),
+        _ => unreachable!(),
+    })
+}
+
 pub(super) fn compare_with_immediate<I, F>(
     a: Value,
     imm: i16,
@@ -56,6 +108,48 @@ pub(super) fn do_arithmetic<I, F>(
     }
 }
 
+pub(super) fn do_division<I, F>(
+    stack: &mut [Value],
+    pc: &mut usize,
+    insn: Instruction,
+    int_op: I,
+    float_op: F,
+) -> Result<(), ErrorKind>
+where
+    I: Fn(Integer, Integer) -> Result<Integer, ErrorKind>,
+    F: Fn(Number, Number) -> Number,
+{
+    let rb = stack[insn.b()];
+    let rc = stack[insn.c() as usize];
+    if let Some(result) = division(rb, rc, int_op, float_op)? {
+        stack[insn.a()] = result;
+        *pc += 1;
+    }
+    Ok(())
+}
+
+pub(super) fn do_division_with_constant<'gc, I, F>(
+    stack: &mut [Value<'gc>],
+    pc: &mut usize,
+    constants: &[Value<'gc>],
+    insn: Instruction,
+    int_op: I,
+    float_op: F,
+) -> Result<(), ErrorKind>
+where
+    I: Fn(Integer, Integer) -> Result<Integer, ErrorKind>,
+    F: Fn(Number, Number) -> Number,
+{
+    let rb = stack[insn.b()];
+    let kc = constants[insn.c() as usize];
+    debug_assert!(matches!(kc, Value::Integer(_) | Value::Number(_)));
+    if let Some(result) = division(rb, kc, int_op, float_op)? {
+        stack[insn.a()] = result;
+        *pc += 1;
+    }
+    Ok(())
+}
+
 pub(super) fn do_arithmetic_with_constant<'gc, I, F>(
     stack: &mut [Value<'gc>],
     pc: &mut usize,
@@ -148,10 +242,7 @@ where
 {
     let rb = stack[insn.b()];
     let rc = stack[insn.c() as usize];
-    if let (Some(a), Some(b)) = (
-        rb.to_integer_without_string_coercion(),
-        rc.to_integer_without_string_coercion(),
-    ) {
+    if let (Some(a), Some(b)) = (rb.to_integer(), rc.to_integer()) {
         stack[insn.a()] = Value::Integer(int_op(a, b));
         *pc += 1;
     }
@@ -167,7 +258,7 @@ pub(super) fn do_bitwise_op_with_constant<'gc, I>(
     I: Fn(Integer, Integer) -> Integer,
 {
     let rb = stack[insn.b()];
-    if let Some(a) = rb.to_integer_without_string_coercion() {
+    if let Some(a) = rb.to_integer() {
         stack[insn.a()] = match constants[insn.c() as usize] {
             Value::Integer(b) => Value::Integer(int_op(a, b)),
             _ => unreachable!(),
@@ -209,7 +300,7 @@ pub(super) fn do_forprep(for_stack: &mut [Value]) -> Result<bool, ErrorKind> {
                     None => {
                         return Err(ErrorKind::ForError {
                             what: "limit",
-                            got_type: limit_value.ty().name(),
+                            got_type: limit_value.type_name(),
                         })
                     }
                 };
@@ -253,15 +344,15 @@ pub(super) fn do_forprep(for_stack: &mut [Value]) -> Result<bool, ErrorKind> {
 
     let limit = limit_value.to_number().ok_or(ErrorKind::ForError {
         what: "limit",
-        got_type: limit_value.ty().name(),
+        got_type: limit_value.type_name(),
     })?;
     let step = step_value.to_number().ok_or(ErrorKind::ForError {
         what: "step",
-        got_type: step_value.ty().name(),
+        got_type: step_value.type_name(),
     })?;
     let init = init_value.to_number().ok_or(ErrorKind::ForError {
         what: "initial value",
-        got_type: init_value.ty().name(),
+        got_type: init_value.type_name(),
     })?;
     if step == 0.0 {
         return Err(ErrorKind::other("'for' step is zero"));
@@ -284,9 +375,10 @@ pub(super) fn do_forprep(for_stack: &mut [Value]) -> Result<bool, ErrorKind> {
     Ok(true)
 }
 
-pub(super) fn idivi(m: Integer, n: Integer) -> Integer {
-    match n {
-        0 => todo!("attempt to divide by zero"),
+// refer to "luaV_idiv" in lvm.c
+pub(crate) fn idivi(m: Integer, n: Integer) -> Result<Integer, ErrorKind> {
+    Ok(match n {
+        0 => return Err(ErrorKind::other("attempt to perform 'n//0'")),
         -1 => m.wrapping_neg(),
         _ => {
             let q = m / n;
@@ -296,16 +388,17 @@ pub(super) fn idivi(m: Integer, n: Integer) -> Integer {
                 q
             }
         }
-    }
+    })
 }
 
-pub(super) fn idivf(m: Number, n: Number) -> Number {
+pub(crate) fn idivf(m: Number, n: Number) -> Number {
     (m / n).floor()
 }
 
-pub(super) fn modi(m: Integer, n: Integer) -> Integer {
-    match n {
-        0 => todo!("attempt to perform 'n%0'"),
+// refer to "luaV_mod" in lvm.c
+pub(crate) fn modi(m: Integer, n: Integer) -> Result<Integer, ErrorKind> {
+    Ok(match n {
+        0 => return Err(ErrorKind::other("attempt to perform 'n%0'")),
         -1 => 0,
         _ => {
             let r = m % n;
@@ -315,10 +408,10 @@ pub(super) fn modi(m: Integer, n: Integer) -> Integer {
                 r
             }
         }
-    }
+    })
 }
 
-pub(super) fn modf(m: Number, n: Number) -> Number {
+pub(crate) fn modf(m: Number, n: Number) -> Number {
     let r = m % n;
     let c = if r > 0.0 { n < 0.0 } else { r < 0.0 && n > 0.0 };
     if c {
@@ -328,7 +421,7 @@ pub(super) fn modf(m: Number, n: Number) -> Number {
     }
 }
 
-pub(super) const fn shl(x: Integer, y: Integer) -> Integer {
+pub(crate) const fn shl(x: Integer, y: Integer) -> Integer {
     const BITS: Integer = Integer::BITS as Integer;
     if y <= -BITS || BITS <= y {
         0
@@ -339,11 +432,11 @@ pub(super) const fn shl(x: Integer, y: Integer) -> Integer {
     }
 }
 
-pub(super) const fn shr(x: Integer, y: Integer) -> Integer {
+pub(crate) const fn shr(x: Integer, y: Integer) -> Integer {
     shl(x, y.wrapping_neg())
 }
 
-pub(super) fn lt(a: Value, b: Value) -> Option<bool> {
+pub(crate) fn lt(a: Value, b: Value) -> Option<bool> {
     match (a, b) {
         (Value::Integer(a), Value::Integer(b)) => Some(a < b),
         (Value::Number(a), Value::Number(b)) => Some(a < b),
@@ -1,5 +1,6 @@
 mod base;
 mod coroutine;
+mod debug;
 mod file;
 mod helpers;
 mod io;
@@ -39,6 +40,7 @@ pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) {
         (B("math"), math::load),
         (B("io"), io::load),
         (B("os"), os::load),
+        (B("debug"), debug::load),
     ];
 
     for (name, load_lib) in libs {
@@ -1,9 +1,9 @@
-use super::helpers::{set_functions_to_table, ArgumentsExt};
+use super::helpers::{fmt_value, set_functions_to_table, tostring_result, ArgumentsExt};
 use crate::{
-    gc::{GcCell, GcContext},
-    runtime::{Action, Continuation, ErrorKind, Vm},
+    gc::{GcCell, GcContext, GcMode},
+    runtime::{Action, Continuation, ErrorKind, Metamethod, Vm},
     string,
-    types::{Integer, LuaClosure, NativeClosure, NativeFunction, Number, Table, Value},
+    types::{Integer, LuaClosure, NativeFunction, Number, Table, Value},
     LUA_VERSION,
 };
 use bstr::{ByteSlice, B};
@@ -41,6 +41,8 @@ pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>
             (B("tonumber"), base_tonumber),
             (B("tostring"), base_tostring),
             (B("type"), base_type),
+            (B("warn"), base_warn),
+            (B("xpcall"), base_xpcall),
         ],
     );
     globals.set_field(
@@ -48,47 +50,18 @@ pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>
         gc.allocate_string(format!("Lua {}.{}", LUA_VERSION.0, LUA_VERSION.1).into_bytes()),
     );
 
-    let warning_is_on = Rc::new(Cell::new(false));
-    globals.set_field(
-        gc.allocate_string(B("warn")),
-        gc.allocate(NativeClosure::new(move |_, _, args| {
-            let first_message = args.nth(1);
-            let first_message = first_message.to_string()?;
-            if args.without_callee().len() == 1 {
-                if let Some(control) = first_message.strip_prefix(b"@") {
-                    match control {
-                        b"on" => warning_is_on.set(true),
-                        b"off" => warning_is_on.set(false),
-                        _ => (),
-                    }
-                    return Ok(Action::Return(Vec::new()));
-                }
-            }
-
-            let mut concatenated = first_message.to_vec();
-            for i in 2..args.len() {
-                concatenated.extend_from_slice(&args.nth(i).to_string()?);
-            }
-
-            if warning_is_on.get() {
-                eprintln!("Lua warning: {}", concatenated.as_bstr());
-            }
-            Ok(Action::Return(Vec::new()))
-        })),
-    );
-
     vm.globals()
 }
 
 fn base_assert<'gc>(
-    _: &'gc GcContext,
+    gc: &'gc GcContext,
     _: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
     if args.nth(1).as_value()?.to_boolean() {
         Ok(Action::Return(args.without_callee().to_vec()))
     } else if let Some(error_obj) = args.nth(2).get() {
-        Err(ErrorKind::from_error_object(error_obj))
+        Err(ErrorKind::from_error_object(gc, error_obj))
     } else {
         Err(ErrorKind::other("assertion failed!"))
     }
@@ -105,8 +78,8 @@ fn base_collectgarbage<'gc>(
     let result = match opt.as_ref() {
         b"collect" => {
             return Ok(Action::MutateGc {
-                mutator: Box::new(|heap| {
-                    heap.full_gc();
+                mutator: Box::new(|gc| {
+                    gc.full_gc();
                 }),
                 continuation: Continuation::new(|_, _, _| Ok(Action::Return(vec![0.into()]))),
             })
@@ -122,20 +95,14 @@ fn base_collectgarbage<'gc>(
         b"count" => ((gc.total_bytes() as Number) / 1024.0).into(),
         b"step" => {
             let step = args.nth(2).to_integer_or(0)?;
+            let finished_cycle = Rc::new(Cell::new(false));
             return Ok(Action::MutateGc {
-                mutator: Box::new(move |heap| {
-                    let finished_cycle = heap.force_step(step as isize);
-                    heap.with(|gc, vm| {
-                        vm.borrow()
-                            .current_thread()
-                            .borrow_mut(gc)
-                            .stack
-                            .push(finished_cycle.into());
-                    });
+                mutator: Box::new({
+                    let finished_cycle = finished_cycle.clone();
+                    move |gc| finished_cycle.set(gc.force_step(step as isize))
                 }),
-                continuation: Continuation::new(|gc, vm, _| {
-                    let result = vm.current_thread().borrow_mut(gc).stack.pop().unwrap();
-                    Ok(Action::Return(vec![result]))
+                continuation: Continuation::new(move |_, _, _| {
+                    Ok(Action::Return(vec![finished_cycle.get().into()]))
                 }),
             });
         }
@@ -153,9 +120,19 @@ fn base_collectgarbage<'gc>(
             if step_size != 0 {
                 gc.set_step_size(step_size as usize);
             }
-            gc.allocate_string(B("incremental")).into()
+            return Ok(set_gc_mode(gc, GcMode::Incremental));
+        }
+        b"generational" => {
+            let minor_multiplier = args.nth(2).to_integer_or(0)?;
+            let major_multiplier = args.nth(3).to_integer_or(0)?;
+            if minor_multiplier != 0 {
+                gc.set_minor_multiplier(minor_multiplier as usize);
+            }
+            if major_multiplier != 0 {
+                gc.set_major_multiplier(major_multiplier as usize);
+            }
+            return Ok(set_gc_mode(gc, GcMode::Generational));
         }
-        b"generational" => Value::Nil,
         b"setpause" => {
             let pause = args.nth(2).to_integer_or(0)?;
             let prev_pause = gc.pause();
@@ -178,6 +155,22 @@ fn base_collectgarbage<'gc>(
     Ok(Action::Return(vec![result]))
 }
 
+/// Switches the collector to `mode`, returning the name of the previous mode.
+fn set_gc_mode<'gc>(gc: &'gc GcContext, mode: GcMode) -> Action<'gc> {
+    let prev_mode = match gc.mode() {
+        GcMode::Incremental => B("incremental"),
+        GcMode::Generational => B("generational"),
+    };
+    Action::MutateGc {
+        mutator: Box::new(move |gc| {
+            gc.set_mode(mode);
+        }),
+        continuation: Continuation::new(move |gc, _, _| {
+            Ok(Action::Return(vec![gc.allocate_string(prev_mode).into()]))
+        }),
+    }
+}
+
 fn base_dofile<'gc>(
     gc: &'gc GcContext,
     vm: &mut Vm<'gc>,
@@ -205,12 +198,21 @@ fn base_dofile<'gc>(
 }
 
 fn base_error<'gc>(
-    _: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
-    let error_obj = args.nth(1).get().unwrap_or_default();
-    Err(ErrorKind::from_error_object(error_obj))
+    let mut error_obj = args.nth(1).get().unwrap_or_default();
+    let level = args.nth(2).to_integer_or(1)?;
+    if let (Value::String(message), Ok(level @ 1..)) = (error_obj, usize::try_from(level)) {
+        if let Some(location) = vm.current_thread().borrow().location(level) {
+            let mut located = location.into_bytes();
+            located.push(b' ');
+            located.extend_from_slice(&message);
+            error_obj = gc.allocate_string(located).into();
+        }
+    }
+    Err(ErrorKind::from_error_object(gc, error_obj))
 }
 
 fn base_getmetatable<'gc>(
@@ -297,7 +299,7 @@ fn base_load<'gc>(
     } else {
         Value::Table(vm.globals()).into()
     };
-    closure.upvalues.push(gc.allocate_cell(upvalue));
+    closure.upvalues.push(Cell::new(gc.allocate_cell(upvalue)));
 
     Ok(Action::Return(vec![gc.allocate(closure).into()]))
 }
@@ -342,7 +344,7 @@ fn base_loadfile<'gc>(
     } else {
         Value::Table(vm.globals()).into()
     };
-    closure.upvalues.push(gc.allocate_cell(upvalue));
+    closure.upvalues.push(Cell::new(gc.allocate_cell(upvalue)));
 
     Ok(Action::Return(vec![gc.allocate(closure).into()]))
 }
@@ -399,35 +401,62 @@ fn base_pcall<'gc>(
     Ok(Action::ProtectedCall {
         callee: f,
         args: args.without_callee()[1..].to_vec(),
-        continuation: Continuation::new(|gc, _, result: Result<Vec<Value>, ErrorKind>| {
-            Ok(Action::Return(match result {
-                Ok(mut results) => {
-                    results.insert(0, true.into());
-                    results
-                }
-                Err(err) => {
-                    vec![
-                        false.into(),
-                        gc.allocate_string(err.to_string().into_bytes()).into(),
-                    ]
-                }
-            }))
-        }),
+        continuation: Continuation::new(finish_protected_call),
     })
 }
 
+fn finish_protected_call<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    result: Result<Vec<Value<'gc>>, ErrorKind>,
+) -> Result<Action<'gc>, ErrorKind> {
+    Ok(Action::Return(match result {
+        Ok(mut results) => {
+            results.insert(0, true.into());
+            results
+        }
+        Err(err) => vec![false.into(), err.to_error_object(gc)],
+    }))
+}
+
 fn base_print<'gc>(
     _: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    print_from(vm, args, 1)
+}
+
+/// Writes the arguments of `print` from the `nth` one, calling `__tostring`
+/// metamethods one at a time.
+fn print_from<'gc>(
+    vm: &Vm<'gc>,
     args: Vec<Value<'gc>>,
+    nth: usize,
 ) -> Result<Action<'gc>, ErrorKind> {
     let mut stdout = std::io::stdout().lock();
-    if let Some((last, xs)) = args.without_callee().split_last() {
-        for x in xs {
-            x.fmt_bytes(&mut stdout)?;
+    for nth in nth..=args.without_callee().len() {
+        let value = args[nth];
+        if let Some(metamethod) = vm.metamethod_of_object(Metamethod::ToString, value) {
+            return Ok(Action::Call {
+                callee: metamethod,
+                args: vec![value],
+                continuation: Continuation::with_context(args, move |gc, vm, args, results| {
+                    let string = tostring_result(gc, results)?;
+                    let mut stdout = std::io::stdout().lock();
+                    if nth > 1 {
+                        stdout.write_all(b"\t")?;
+                    }
+                    stdout.write_all(string.as_bytes())?;
+                    drop(stdout);
+                    print_from(vm, args, nth + 1)
+                }),
+            });
+        }
+        if nth > 1 {
             stdout.write_all(b"\t")?;
         }
-        last.fmt_bytes(&mut stdout)?;
+        fmt_value(vm, &mut stdout, value)?;
     }
     stdout.write_all(b"\n")?;
     Ok(Action::Return(Vec::new()))
@@ -465,7 +494,7 @@ fn base_rawlen<'gc>(
             return Err(ErrorKind::ArgumentTypeError {
                 nth: 1,
                 expected_type: "table or string",
-                got_type: value.map(|value| value.ty().name()),
+                got_type: value.map(|value| value.type_name()),
             })
         }
     };
@@ -519,7 +548,7 @@ fn base_select<'gc>(
 
 fn base_setmetatable<'gc>(
     gc: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    vm: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
     let table = args.nth(1).as_table()?;
@@ -530,7 +559,7 @@ fn base_setmetatable<'gc>(
             return Err(ErrorKind::ArgumentTypeError {
                 nth: 2,
                 expected_type: "nil or table",
-                got_type: value.map(|value| value.ty().name()),
+                got_type: value.map(|value| value.type_name()),
             })
         }
     };
@@ -545,7 +574,7 @@ fn base_setmetatable<'gc>(
         }
         _ => (),
     }
-    table.borrow_mut(gc).set_metatable(new_metatable);
+    vm.set_metatable_of_object(gc, table.into(), new_metatable);
     Ok(Action::Return(vec![table.into()]))
 }
 
@@ -585,10 +614,7 @@ fn base_tonumber<'gc>(
                     None => None,
                 }
             } else {
-                value
-                    .to_integer()
-                    .map(Value::from)
-                    .or_else(|| value.to_number().map(Value::from))
+                value.to_numeric()
             };
             maybe_value.unwrap_or(Value::Nil)
         }
@@ -599,11 +625,21 @@ fn base_tonumber<'gc>(
 
 fn base_tostring<'gc>(
     gc: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    vm: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
+    let value = args.nth(1).as_value()?;
+    if let Some(metamethod) = vm.metamethod_of_object(Metamethod::ToString, value) {
+        return Ok(Action::Call {
+            callee: metamethod,
+            args: vec![value],
+            continuation: Continuation::new(|gc, _, results| {
+                Ok(Action::Return(vec![tostring_result(gc, results)?.into()]))
+            }),
+        });
+    }
     let mut string = Vec::new();
-    args.nth(1).as_value()?.fmt_bytes(&mut string)?;
+    fmt_value(vm, &mut string, value)?;
     Ok(Action::Return(vec![gc.allocate_string(string).into()]))
 }
 
@@ -615,3 +651,44 @@ fn base_type<'gc>(
     let string = args.nth(1).as_value()?.ty().name().as_bytes();
     Ok(Action::Return(vec![gc.allocate_string(string).into()]))
 }
+
+fn base_warn<'gc>(
+    _: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let first_message = args.nth(1);
+    let first_message = first_message.to_string()?;
+    if args.without_callee().len() == 1 {
+        if let Some(control) = first_message.strip_prefix(b"@") {
+            match control {
+                b"on" => vm.set_warning_on(true),
+                b"off" => vm.set_warning_on(false),
+                _ => (),
+            }
+            return Ok(Action::Return(Vec::new()));
+        }
+    }
+
+    let mut concatenated = first_message.to_vec();
+    for i in 2..args.len() {
+        concatenated.extend_from_slice(&args.nth(i).to_string()?);
+    }
+    vm.warn(concatenated);
+    Ok(Action::Return(Vec::new()))
+}
+
+fn base_xpcall<'gc>(
+    _: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let f = args.nth(1).as_value()?;
+    let handler = args.nth(2).ensure_function()?;
+    Ok(Action::ProtectedCallWithHandler {
+        callee: f,
+        args: args.without_callee()[2..].to_vec(),
+        handler,
+        continuation: Continuation::new(finish_protected_call),
+    })
+}
@@ -1,7 +1,7 @@
 use super::helpers::{set_functions_to_table, ArgumentsExt};
 use crate::{
     gc::{GcCell, GcContext},
-    runtime::{Action, Continuation, ErrorKind, Vm},
+    runtime::{Action, Continuation, ErrorKind, Metamethod, Vm},
     types::{LuaThread, NativeClosure, Table, ThreadStatus, Value},
 };
 use bstr::B;
@@ -39,18 +39,13 @@ fn coroutine_close<'gc>(
         )));
     }
 
-    let mut co = co.borrow_mut(gc);
-    Ok(Action::Return(match &co.status {
-        ThreadStatus::Resumable | ThreadStatus::Unresumable => {
-            co.close(gc);
-            vec![true.into()]
-        }
-        ThreadStatus::Error(err) => {
-            let msg = gc.allocate_string(err.to_string().into_bytes()).into();
-            co.close(gc);
-            vec![false.into(), msg]
-        }
-    }))
+    let (values, error) = close_coroutine(gc, co);
+    close_variables(gc, vm, values, error, |gc, error| {
+        Ok(Action::Return(match error {
+            None => vec![true.into()],
+            Some(err) => vec![false.into(), err.to_error_object(gc)],
+        }))
+    })
 }
 
 fn coroutine_create<'gc>(
@@ -97,10 +92,7 @@ fn coroutine_resume<'gc>(
                     results.insert(0, true.into());
                     results
                 }
-                Err(err) => vec![
-                    false.into(),
-                    gc.allocate_string(err.to_string().into_bytes()).into(),
-                ],
+                Err(err) => vec![false.into(), err.to_error_object(gc)],
             }))
         }),
     })
@@ -143,12 +135,12 @@ fn coroutine_wrap<'gc>(
         Ok(Action::Resume {
             coroutine,
             args: args.without_callee().to_vec(),
-            continuation: Continuation::with_context(coroutine, |gc, _, coroutine, result| {
+            continuation: Continuation::with_context(coroutine, |gc, vm, coroutine, result| {
                 match result {
                     Ok(results) => Ok(Action::Return(results)),
-                    Err(err) => {
-                        coroutine.borrow_mut(gc).close(gc);
-                        Err(err)
+                    Err(_) => {
+                        let (values, error) = close_coroutine(gc, coroutine);
+                        close_variables(gc, vm, values, error, |_, error| Err(error.unwrap()))
                     }
                 }
             }),
@@ -166,6 +158,63 @@ fn coroutine_yield<'gc>(
     Ok(Action::Yield(args.without_callee().to_vec()))
 }
 
+/// Closes the coroutine and returns its pending to-be-closed variables and
+/// the error that killed it.
+fn close_coroutine<'gc>(
+    gc: &'gc GcContext,
+    coroutine: GcCell<'gc, LuaThread<'gc>>,
+) -> (Vec<Value<'gc>>, Option<ErrorKind>) {
+    let mut coroutine = coroutine.borrow_mut(gc);
+    let values = coroutine
+        .tbc_slots
+        .iter()
+        .map(|slot| coroutine.stack[*slot])
+        .collect();
+    let error = match &coroutine.status {
+        ThreadStatus::Resumable | ThreadStatus::Unresumable => None,
+        ThreadStatus::Error(error_object) => Some(ErrorKind::from_error_object(gc, *error_object)),
+    };
+    coroutine.close(gc);
+    (values, error)
+}
+
+type CloseVariablesFinisher =
+    for<'gc> fn(&'gc GcContext, Option<ErrorKind>) -> Result<Action<'gc>, ErrorKind>;
+
+/// Calls `__close` metamethods of the values in reverse order. An error raised
+/// by a metamethod replaces `error` passed to the subsequent ones.
+fn close_variables<'gc>(
+    gc: &'gc GcContext,
+    vm: &Vm<'gc>,
+    mut values: Vec<Value<'gc>>,
+    error: Option<ErrorKind>,
+    finish: CloseVariablesFinisher,
+) -> Result<Action<'gc>, ErrorKind> {
+    let value = if let Some(value) = values.pop() {
+        value
+    } else {
+        return finish(gc, error);
+    };
+    let metamethod = vm
+        .metamethod_of_object(Metamethod::Close, value)
+        .unwrap_or_default();
+    let error_object = error
+        .as_ref()
+        .map(|err| err.to_error_object(gc))
+        .unwrap_or_default();
+    Ok(Action::ProtectedCall {
+        callee: metamethod,
+        args: vec![value, error_object],
+        continuation: Continuation::with_context(values, move |gc, vm, values, result| {
+            let error = match result {
+                Ok(_) => error.clone(),
+                Err(err) => Some(err),
+            };
+            close_variables(gc, vm, values, error, finish)
+        }),
+    })
+}
+
 fn create_coroutine<'gc>(vm: &Vm<'gc>, body: Value<'gc>) -> Result<LuaThread<'gc>, ErrorKind> {
     let mut co = LuaThread::new();
     co.stack.push(body);
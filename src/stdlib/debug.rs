@@ -0,0 +1,631 @@
+use super::helpers::{set_functions_to_table, ArgumentsExt};
+use crate::{
+    gc::{Gc, GcCell, GcContext},
+    runtime::{Action, ErrorKind, Frame, HookMask, LuaHook, Vm},
+    types::{
+        Integer, LineRange, LuaClosure, LuaThread, StackLevel, Table, TracebackFrame, Type,
+        Upvalue, Value,
+    },
+};
+use bstr::B;
+
+pub fn load<'gc>(gc: &'gc GcContext, _: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>> {
+    let mut table = Table::new();
+    set_functions_to_table(
+        gc,
+        &mut table,
+        &[
+            (B("gethook"), debug_gethook),
+            (B("getinfo"), debug_getinfo),
+            (B("getlocal"), debug_getlocal),
+            (B("getmetatable"), debug_getmetatable),
+            (B("getregistry"), debug_getregistry),
+            (B("getupvalue"), debug_getupvalue),
+            (B("getuservalue"), debug_getuservalue),
+            (B("sethook"), debug_sethook),
+            (B("setcstacklimit"), debug_setcstacklimit),
+            (B("setlocal"), debug_setlocal),
+            (B("setmetatable"), debug_setmetatable),
+            (B("setupvalue"), debug_setupvalue),
+            (B("setuservalue"), debug_setuservalue),
+            (B("traceback"), debug_traceback),
+            (B("upvalueid"), debug_upvalueid),
+            (B("upvaluejoin"), debug_upvaluejoin),
+        ],
+    );
+    gc.allocate_cell(table)
+}
+
+fn debug_gethook<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (thread, _) = thread_arg(vm, &args);
+    let Some(hook) = thread.borrow().hook else {
+        return Ok(Action::Return(vec![Value::Nil]));
+    };
+    let mut mask = Vec::new();
+    for (is_set, c) in [
+        (hook.mask.call, b'c'),
+        (hook.mask.ret, b'r'),
+        (hook.mask.line, b'l'),
+    ] {
+        if is_set {
+            mask.push(c);
+        }
+    }
+    Ok(Action::Return(vec![
+        hook.function,
+        gc.allocate_string(mask).into(),
+        Value::Integer(hook.count.into()),
+    ]))
+}
+
+fn debug_getinfo<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (thread, arg) = thread_arg(vm, &args);
+    let options = args.nth(arg + 1);
+    let options = options.to_string_or(B("flnSrtu"))?;
+    if options.iter().any(|c| !b"SlnrutLf".contains(c)) {
+        return Err(ErrorKind::ArgumentError {
+            nth: arg + 1,
+            message: "invalid option",
+        });
+    }
+
+    let thread_ref = thread.borrow();
+    let (func, lua_frame, caller) = match args.nth(arg).get() {
+        Some(
+            value @ (Value::LuaClosure(_) | Value::NativeFunction(_) | Value::NativeClosure(_)),
+        ) => (Some(value), None, None),
+        _ => {
+            let level = args.nth(arg).to_integer()?;
+            let is_running = GcCell::ptr_eq(&thread, &vm.current_thread());
+            match stack_level(&thread_ref, level, is_running) {
+                Some(StackLevel::Native) => (
+                    // the function that suspended the thread is not known
+                    is_running.then(|| args.callee()),
+                    None,
+                    thread_ref.caller_index(thread_ref.frames.len()),
+                ),
+                Some(StackLevel::Frame(i)) => {
+                    let frame = &thread_ref.frames[i];
+                    let func = match frame {
+                        Frame::Lua(frame) => Some(thread_ref.stack[frame.bottom]),
+                        Frame::Native { bottom } => thread_ref.stack.get(*bottom).copied(),
+                        _ => None,
+                    };
+                    (func, frame.as_lua(), thread_ref.caller_index(i))
+                }
+                None => return Ok(Action::Return(vec![Value::Nil])),
+            }
+        }
+    };
+    let proto = func
+        .as_ref()
+        .and_then(Value::as_lua_closure)
+        .map(|closure| closure.proto);
+
+    let mut table = Table::new();
+    let mut set = |name: &[u8], value: Value<'gc>| {
+        table.set_field(gc.allocate_string(name), value);
+    };
+    for option in options.iter() {
+        match option {
+            b'S' => match &proto {
+                Some(proto) => {
+                    let source = String::from_utf8_lossy(&proto.source);
+                    let short_src = crate::chunk_id_from_source(&source);
+                    set(B("source"), proto.source.into());
+                    set(
+                        B("short_src"),
+                        gc.allocate_string(short_src.as_bytes()).into(),
+                    );
+                    let (line_defined, last_line_defined, what) = match &proto.lines_defined {
+                        LineRange::File => (0, 0, B("main")),
+                        LineRange::Lines(range) => (*range.start(), *range.end(), B("Lua")),
+                    };
+                    set(B("linedefined"), Value::Integer(line_defined.into()));
+                    set(
+                        B("lastlinedefined"),
+                        Value::Integer(last_line_defined.into()),
+                    );
+                    set(B("what"), gc.allocate_string(what).into());
+                }
+                None => {
+                    set(B("source"), gc.allocate_string(B("=[C]")).into());
+                    set(B("short_src"), gc.allocate_string(B("[C]")).into());
+                    set(B("linedefined"), Value::Integer(-1));
+                    set(B("lastlinedefined"), Value::Integer(-1));
+                    set(B("what"), gc.allocate_string(B("C")).into());
+                }
+            },
+            b'l' => {
+                let line = lua_frame
+                    .zip(proto.as_ref())
+                    .and_then(|(frame, proto)| proto.current_line(frame))
+                    .map_or(-1, Integer::from);
+                set(B("currentline"), Value::Integer(line));
+            }
+            b'u' => {
+                let (num_upvalues, num_params, is_vararg) = match &func {
+                    Some(Value::LuaClosure(closure)) => (
+                        closure.upvalues.len(),
+                        closure.proto.num_params,
+                        closure.proto.is_vararg,
+                    ),
+                    _ => (0, 0, true),
+                };
+                set(B("nups"), Value::Integer(num_upvalues as Integer));
+                set(B("nparams"), Value::Integer(num_params.into()));
+                set(B("isvararg"), is_vararg.into());
+            }
+            b'n' => {
+                let name = caller.and_then(|i| func_name_of_caller(&thread_ref, i));
+                let (name, kind) = match &name {
+                    Some((name, kind)) => (gc.allocate_string(name.as_bytes()).into(), *kind),
+                    None => (Value::Nil, ""),
+                };
+                set(B("name"), name);
+                set(B("namewhat"), gc.allocate_string(kind.as_bytes()).into());
+            }
+            b'r' => {
+                set(B("ftransfer"), Value::Integer(0));
+                set(B("ntransfer"), Value::Integer(0));
+            }
+            b't' => set(
+                B("istailcall"),
+                lua_frame.is_some_and(|frame| frame.is_tail_call).into(),
+            ),
+            b'L' => {
+                let lines = proto.as_ref().and_then(|proto| {
+                    proto.line_info.as_ref()?;
+                    // skip VARARGPREP, which has the line of the function
+                    // definition
+                    let start = usize::from(proto.is_vararg);
+                    let mut lines = Table::new();
+                    for pc in start..proto.code.len() {
+                        if let Some(line) = proto.func_line(pc as u32) {
+                            lines.set_integer_key(line.into(), true);
+                        }
+                    }
+                    Some(gc.allocate_cell(lines))
+                });
+                set(B("activelines"), lines.map(Value::from).unwrap_or_default());
+            }
+            b'f' => set(B("func"), func.unwrap_or_default()),
+            _ => unreachable!(),
+        }
+    }
+    Ok(Action::Return(vec![gc.allocate_cell(table).into()]))
+}
+
+fn debug_getlocal<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (thread, arg) = thread_arg(vm, &args);
+    let n = args.nth(arg + 1).to_integer()?;
+
+    // information about parameters of a non-active function
+    if let Some(func) = args
+        .nth(arg)
+        .get()
+        .filter(|value| value.ty() == Type::Function)
+    {
+        let name = func
+            .as_lua_closure()
+            .zip(u32::try_from(n).ok())
+            .and_then(|(closure, n)| closure.proto.local_name(n, 0))
+            .map(|name| gc.allocate_string(name.as_bytes()).into());
+        return Ok(Action::Return(vec![name.unwrap_or_default()]));
+    }
+
+    let level = args.nth(arg).to_integer()?;
+    let thread_ref = thread.borrow();
+    let slot = find_local(&thread_ref, vm, &thread, level, n, arg)?;
+    Ok(Action::Return(match slot {
+        Some((name, index)) => vec![
+            gc.allocate_string(name.as_bytes()).into(),
+            thread_ref.stack[index],
+        ],
+        None => vec![Value::Nil],
+    }))
+}
+
+fn debug_getmetatable<'gc>(
+    _: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let object = args.nth(1).as_value()?;
+    let metatable = vm.metatable_of_object(object);
+    Ok(Action::Return(vec![metatable
+        .map(Value::from)
+        .unwrap_or_default()]))
+}
+
+fn debug_getregistry<'gc>(
+    _: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    _: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    Ok(Action::Return(vec![vm.registry().into()]))
+}
+
+fn debug_getupvalue<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let func = args.nth(1).ensure_function()?;
+    let n = args.nth(2).to_integer()?;
+    let Some((name, upvalue)) = lua_upvalue(gc, func, n) else {
+        return Ok(Action::Return(Vec::new()));
+    };
+    let value = match &*upvalue.borrow() {
+        Upvalue::Open { thread, index } => thread.borrow().stack[*index],
+        Upvalue::Closed(value) => *value,
+    };
+    Ok(Action::Return(vec![name, value]))
+}
+
+fn debug_getuservalue<'gc>(
+    _: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let n = args.nth(2).to_integer_or(1)?;
+    let Some(Value::UserData(ud)) = args.nth(1).get() else {
+        return Ok(Action::Return(vec![Value::Nil]));
+    };
+    let value = usize::try_from(n)
+        .ok()
+        .and_then(|n| ud.borrow().user_value(n));
+    Ok(Action::Return(match value {
+        Some(value) => vec![value, true.into()],
+        None => vec![Value::Nil, false.into()],
+    }))
+}
+
+fn debug_sethook<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (thread, arg) = thread_arg(vm, &args);
+    let hook = if args.nth(arg).is_present() {
+        let function = args.nth(arg).ensure_function()?;
+        let mask = args.nth(arg + 1);
+        let mask = mask.to_string()?;
+        let count = args.nth(arg + 2).to_integer_or(0)?;
+        let count = u32::try_from(count.max(0)).unwrap_or(u32::MAX);
+        let mask = HookMask {
+            call: mask.contains(&b'c'),
+            ret: mask.contains(&b'r'),
+            line: mask.contains(&b'l'),
+            count: count > 0,
+        };
+        (!mask.is_empty()).then_some(LuaHook {
+            function,
+            mask,
+            count,
+            countdown: count,
+        })
+    } else {
+        None
+    };
+    thread.borrow_mut(gc).hook = hook;
+    Ok(Action::Return(Vec::new()))
+}
+
+fn debug_setcstacklimit<'gc>(
+    _: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    // deprecated in Lua 5.4.6, which always returns LUAI_MAXCCALLS
+    args.nth(1).to_integer()?;
+    Ok(Action::Return(vec![Value::Integer(200)]))
+}
+
+fn debug_setlocal<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (thread, arg) = thread_arg(vm, &args);
+    let level = args.nth(arg).to_integer()?;
+    let n = args.nth(arg + 1).to_integer()?;
+    let value = args.nth(arg + 2).as_value()?;
+
+    let slot = find_local(&thread.borrow(), vm, &thread, level, n, arg)?;
+    Ok(Action::Return(vec![match slot {
+        Some((name, index)) => {
+            thread.borrow_mut(gc).stack[index] = value;
+            gc.allocate_string(name.as_bytes()).into()
+        }
+        None => Value::Nil,
+    }]))
+}
+
+fn debug_setmetatable<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let object = args.nth(1).as_value()?;
+    let metatable = match args.nth(2).get() {
+        Some(Value::Nil) => None,
+        Some(Value::Table(table)) => Some(table),
+        value => {
+            return Err(ErrorKind::ArgumentTypeError {
+                nth: 2,
+                expected_type: "nil or table",
+                got_type: value.map(|value| value.type_name()),
+            })
+        }
+    };
+    vm.set_metatable_of_object(gc, object, metatable);
+    Ok(Action::Return(vec![object]))
+}
+
+fn debug_setupvalue<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let func = args.nth(1).ensure_function()?;
+    let n = args.nth(2).to_integer()?;
+    let value = args.nth(3).as_value()?;
+    let Some((name, upvalue)) = lua_upvalue(gc, func, n) else {
+        return Ok(Action::Return(Vec::new()));
+    };
+    let mut upvalue = upvalue.borrow_mut(gc);
+    match &mut *upvalue {
+        Upvalue::Open { thread, index } => thread.borrow_mut(gc).stack[*index] = value,
+        Upvalue::Closed(v) => *v = value,
+    }
+    Ok(Action::Return(vec![name]))
+}
+
+fn debug_setuservalue<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let ud = match args.nth(1).get() {
+        Some(Value::UserData(ud)) => ud,
+        value => {
+            return Err(ErrorKind::ArgumentTypeError {
+                nth: 1,
+                expected_type: "userdata",
+                got_type: value.map(|value| value.type_name()),
+            })
+        }
+    };
+    let value = args.nth(2).as_value()?;
+    let n = args.nth(3).to_integer_or(1)?;
+    let is_set = usize::try_from(n)
+        .map(|n| ud.borrow_mut(gc).set_user_value(n, value))
+        .unwrap_or_default();
+    Ok(Action::Return(vec![if is_set {
+        ud.into()
+    } else {
+        Value::Nil
+    }]))
+}
+
+// refer to "luaL_traceback" in lauxlib.c
+fn debug_traceback<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    const LEVELS1: usize = 10; // size of the first part of the stack
+    const LEVELS2: usize = 11; // size of the second part of the stack
+
+    let (thread, arg) = thread_arg(vm, &args);
+    let msg = args.nth(arg);
+    let mut buf = match msg.get() {
+        None | Some(Value::Nil) => Vec::new(),
+        Some(value) => match value.to_string() {
+            Some(msg) => {
+                let mut buf = msg.to_vec();
+                buf.push(b'\n');
+                buf
+            }
+            None => return Ok(Action::Return(vec![value])),
+        },
+    };
+
+    let is_running = GcCell::ptr_eq(&thread, &vm.current_thread());
+    let level = args.nth(arg + 1).to_integer_or(is_running.into())?;
+    let thread_ref = thread.borrow();
+    let mut frames = thread_ref.traceback();
+    if is_running {
+        let func = thread_ref
+            .caller_index(thread_ref.frames.len())
+            .and_then(|i| func_name_of_caller(&thread_ref, i))
+            .map(|(name, _)| name);
+        frames.insert(0, TracebackFrame::Native { func });
+    }
+    let frames = usize::try_from(level)
+        .ok()
+        .and_then(|level| frames.get(level..))
+        .unwrap_or_default();
+
+    buf.extend_from_slice(b"stack traceback:");
+    for (i, frame) in frames.iter().enumerate() {
+        if frames.len() > LEVELS1 + LEVELS2 + 1 && i >= LEVELS1 {
+            let num_skipped = frames.len() - LEVELS1 - LEVELS2;
+            if i == LEVELS1 {
+                buf.extend_from_slice(
+                    format!("\n\t...\t(skipping {num_skipped} levels)").as_bytes(),
+                );
+            }
+            if i < LEVELS1 + num_skipped {
+                continue;
+            }
+        }
+        buf.extend_from_slice(format!("\n\t{frame}").as_bytes());
+    }
+    Ok(Action::Return(vec![gc.allocate_string(buf).into()]))
+}
+
+fn debug_upvalueid<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let func = args.nth(1).ensure_function()?;
+    let n = args.nth(2).to_integer()?;
+    // there is no light userdata, so the address of the upvalue is returned
+    // as an integer
+    Ok(Action::Return(vec![match lua_upvalue(gc, func, n) {
+        Some((_, upvalue)) => Value::Integer(upvalue.as_ptr() as Integer),
+        None => Value::Nil,
+    }]))
+}
+
+fn debug_upvaluejoin<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (f1, n1) = lua_upvalue_index(&args, 1)?;
+    let (f2, n2) = lua_upvalue_index(&args, 3)?;
+    f1.upvalues[n1].set(f2.upvalues[n2].get());
+    f1.write_barrier(gc);
+    Ok(Action::Return(Vec::new()))
+}
+
+/// Returns the thread given as the optional first argument, and the position
+/// of the argument that follows it.
+// refer to "getthread" in ldblib.c
+fn thread_arg<'gc>(vm: &Vm<'gc>, args: &[Value<'gc>]) -> (GcCell<'gc, LuaThread<'gc>>, usize) {
+    match args.nth(1).get() {
+        Some(Value::Thread(thread)) => (thread, 2),
+        _ => (vm.current_thread(), 1),
+    }
+}
+
+/// Returns the function activation at `level` of the call stack of `thread`.
+// refer to "lua_getstack" in ldebug.c
+fn stack_level(thread: &LuaThread, level: Integer, is_running: bool) -> Option<StackLevel> {
+    let level = usize::try_from(level).ok()?;
+    thread.levels(is_running).get(level).copied()
+}
+
+/// Returns the name and the kind of name of the function called by the frame
+/// at `index`, if it is a Lua frame or the frame running the hook.
+fn func_name_of_caller(thread: &LuaThread, index: usize) -> Option<(String, &'static str)> {
+    if Some(index) == thread.hook_frame {
+        return Some(("?".to_owned(), "hook"));
+    }
+    let frame = thread.frames.get(index)?.as_lua()?;
+    let proto = thread.stack[frame.bottom].as_lua_closure()?.proto;
+    let name = proto.func_name_from_pc(frame.last_pc())?;
+    Some((name.name.to_string(), name.kind))
+}
+
+/// Returns the name and the stack index of the `n`-th local variable of the
+/// function at `level`. Negative `n` refers to the vararg values.
+// refer to "luaG_findlocal" in ldebug.c
+fn find_local<'gc>(
+    thread_ref: &LuaThread<'gc>,
+    vm: &Vm<'gc>,
+    thread: &GcCell<'gc, LuaThread<'gc>>,
+    level: Integer,
+    n: Integer,
+    arg: usize,
+) -> Result<Option<(String, usize)>, ErrorKind> {
+    let is_running = GcCell::ptr_eq(thread, &vm.current_thread());
+    let frame_index = match stack_level(thread_ref, level, is_running) {
+        Some(StackLevel::Frame(i)) => i,
+        Some(StackLevel::Native) => return Ok(None),
+        None => {
+            return Err(ErrorKind::ArgumentError {
+                nth: arg,
+                message: "level out of range",
+            })
+        }
+    };
+    let Some(frame) = thread_ref.frames[frame_index].as_lua() else {
+        return Ok(None);
+    };
+    let proto = thread_ref.stack[frame.bottom]
+        .as_lua_closure()
+        .unwrap()
+        .proto;
+
+    if n < 0 {
+        let n = n.unsigned_abs() as usize;
+        return Ok((proto.is_vararg && n <= frame.num_extra_args).then(|| {
+            let index = frame.base - 1 - frame.num_extra_args + n - 1;
+            ("(vararg)".to_owned(), index)
+        }));
+    }
+
+    let Some(n) = u32::try_from(n).ok().filter(|n| *n > 0) else {
+        return Ok(None);
+    };
+    // the n-th active local variable lives in register n - 1
+    let index = frame.base + n as usize - 1;
+    if let Some(name) = proto.local_name(n, frame.last_pc() as u32) {
+        return Ok(Some((name.to_owned(), index)));
+    }
+    // registers up to the function called by this frame, if any, are
+    // temporaries
+    let limit = match thread_ref.frames.get(frame_index + 1) {
+        Some(Frame::Lua(frame)) => frame.bottom,
+        Some(Frame::CallBoundary { bottom }) => *bottom,
+        _ => thread_ref.stack.len(),
+    };
+    Ok((index < limit).then(|| ("(temporary)".to_owned(), index)))
+}
+
+/// Returns the Lua function at argument `nth` and the index of its upvalue
+/// given by the next argument.
+// refer to "checkupval" in ldblib.c
+fn lua_upvalue_index<'gc>(
+    args: &[Value<'gc>],
+    nth: usize,
+) -> Result<(Gc<'gc, LuaClosure<'gc>>, usize), ErrorKind> {
+    let func = args.nth(nth).ensure_function()?;
+    let n = args.nth(nth + 1).to_integer()?;
+    if let Value::LuaClosure(closure) = func {
+        let index = usize::try_from(n).ok().and_then(|n| n.checked_sub(1));
+        if let Some(index) = index.filter(|index| *index < closure.upvalues.len()) {
+            return Ok((closure, index));
+        }
+    }
+    Err(ErrorKind::ArgumentError {
+        nth: nth + 1,
+        message: "invalid upvalue index",
+    })
+}
+
+/// Returns the name and the cell of the `n`-th upvalue of a Lua function.
+// refer to "aux_upvalue" in lapi.c
+fn lua_upvalue<'gc>(
+    gc: &'gc GcContext,
+    func: Value<'gc>,
+    n: Integer,
+) -> Option<(Value<'gc>, GcCell<'gc, Upvalue<'gc>>)> {
+    let closure = func.as_lua_closure()?;
+    let index = usize::try_from(n).ok()?.checked_sub(1)?;
+    let upvalue = closure.upvalues.get(index)?.get();
+    let name = closure
+        .proto
+        .upvalue_names
+        .as_ref()
+        .and_then(|names| names.get(index).copied())
+        .unwrap_or_else(|| gc.allocate_string(B("(no name)")));
+    Some((name.into(), upvalue))
+}
@@ -1,14 +1,15 @@
 use crate::{
-    gc::{GcCell, GcContext},
-    runtime::ErrorKind,
+    gc::{GcCell, GcContext, GcRefMut},
+    runtime::{ErrorKind, Metamethod, Vm},
     types::{
-        Integer, LuaThread, NativeFunction, NativeFunctionPtr, Number, Table, Type, UserData, Value,
+        Integer, LuaString, LuaThread, NativeFunction, NativeFunctionPtr, Number, Table, Type,
+        UserData, Value,
     },
 };
 use std::{
     any::Any,
     borrow::{Borrow, Cow},
-    cell::RefMut,
+    io::Write,
 };
 
 pub trait ArgumentsExt<'gc> {
@@ -120,7 +121,7 @@ impl<'gc> Argument<'gc> {
     pub fn borrow_as_userdata_mut<'a, T: Any>(
         &'a self,
         gc: &'gc GcContext,
-    ) -> Result<RefMut<'a, T>, ErrorKind> {
+    ) -> Result<GcRefMut<'a, T>, ErrorKind> {
         self.to_type("userdata", |value| value.borrow_as_userdata_mut(gc))
     }
 
@@ -138,7 +139,7 @@ impl<'gc> Argument<'gc> {
             if let Some(value) = convert(value) {
                 return Ok(value);
             }
-            Some(value.ty().name())
+            Some(value.type_name())
         } else {
             None
         };
@@ -159,3 +160,39 @@ pub fn set_functions_to_table<'gc>(
         table.set_field(gc.allocate_string(*name), NativeFunction::new(*func));
     }
 }
+
+/// Formats `value` as `tostring` does for values without a `__tostring`
+/// metamethod. The `__name` field of the metatable replaces the name of the
+/// type if it is a string.
+pub fn fmt_value<'gc, W: Write>(vm: &Vm<'gc>, f: &mut W, value: Value<'gc>) -> std::io::Result<()> {
+    let name = match value {
+        Value::Nil
+        | Value::Boolean(_)
+        | Value::Integer(_)
+        | Value::Number(_)
+        | Value::String(_) => None,
+        _ => vm.metamethod_of_object(Metamethod::Name, value),
+    };
+    match (name, value.as_ptr()) {
+        (Some(Value::String(name)), Some(ptr)) => {
+            f.write_all(name.as_bytes())?;
+            write!(f, ": {ptr:p}")
+        }
+        _ => value.fmt_bytes(f),
+    }
+}
+
+/// Returns the string returned by a `__tostring` metamethod, converting a
+/// number to a string.
+pub fn tostring_result<'gc>(
+    gc: &'gc GcContext,
+    results: Vec<Value<'gc>>,
+) -> Result<LuaString<'gc>, ErrorKind> {
+    match results.first() {
+        Some(Value::String(s)) => Ok(*s),
+        Some(value @ (Value::Integer(_) | Value::Number(_))) => {
+            Ok(gc.allocate_string(value.to_string().unwrap()))
+        }
+        _ => Err(ErrorKind::other("'__tostring' must return a string")),
+    }
+}
@@ -6,7 +6,7 @@ use super::{
 use crate::{
     gc::{GcCell, GcContext},
     runtime::{Action, ErrorKind, Metamethod, Vm},
-    types::{Integer, Number, Table, Type, UserData, Value},
+    types::{Integer, NativeFunction, Number, Table, Type, UserData, Value},
 };
 use bstr::{ByteSlice, B};
 use std::{
@@ -56,21 +56,25 @@ pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>
         vm.metamethod_name(Metamethod::Index),
         gc.allocate_cell(methods),
     );
+    metatable.set_field(
+        vm.metamethod_name(Metamethod::Gc),
+        NativeFunction::new(file_gc),
+    );
     let metatable = gc.allocate_cell(metatable);
 
     let registry = vm.registry();
     let mut registry = registry.borrow_mut(gc);
     registry.set_field(gc.allocate_string(LUA_FILEHANDLE), metatable);
 
-    let stdin = gc.allocate_cell(create_file_handle(gc, &registry, LuaFile::stdin()));
+    let stdin = create_file_handle(gc, &registry, LuaFile::stdin());
     table.set_field(gc.allocate_string(B("stdin")), stdin);
     registry.set_field(gc.allocate_string(IO_INPUT), stdin);
 
-    let stdout = gc.allocate_cell(create_file_handle(gc, &registry, LuaFile::stdout()));
+    let stdout = create_file_handle(gc, &registry, LuaFile::stdout());
     table.set_field(gc.allocate_string(B("stdout")), stdout);
     registry.set_field(gc.allocate_string(IO_OUTPUT), stdout);
 
-    let stderr = gc.allocate_cell(create_file_handle(gc, &registry, LuaFile::stderr()));
+    let stderr = create_file_handle(gc, &registry, LuaFile::stderr());
     table.set_field(gc.allocate_string(B("stderr")), stderr);
 
     gc.allocate_cell(table)
@@ -152,7 +156,7 @@ fn io_open<'gc>(
 
     file::translate_and_return_error(gc, || {
         let handle = open_file(gc, &vm.registry().borrow(), &options, filename)?;
-        Ok(vec![gc.allocate_cell(handle).into()])
+        Ok(vec![handle.into()])
     })
 }
 
@@ -206,7 +210,7 @@ fn io_popen<'gc>(
         let registry = vm.registry();
         let registry = registry.borrow();
         let handle = create_file_handle(gc, &registry, Process::from(child));
-        Ok(vec![gc.allocate_cell(handle).into()])
+        Ok(vec![handle.into()])
     })
 }
 
@@ -282,6 +286,21 @@ fn file_close<'gc>(
     process::translate_and_return_error(gc, || handle.close())
 }
 
+// refer to "f_gc" in liolib.c
+fn file_gc<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let handle = args.nth(1);
+    let mut handle = handle.borrow_as_userdata_mut::<FileHandle>(gc)?;
+    if handle.is_open() {
+        // errors are ignored, and standard files are not closed
+        let _ = handle.close();
+    }
+    Ok(Action::Return(Vec::new()))
+}
+
 fn file_flush<'gc>(
     gc: &'gc GcContext,
     _: &mut Vm<'gc>,
@@ -437,8 +456,7 @@ fn common_io_input_or_output<'gc, K: AsRef<[u8]>>(
         let handle = match file.get() {
             None | Some(Value::Nil) => return Ok(vec![registry.borrow().get_field(key)]),
             Some(Value::String(filename)) => {
-                let handle = open_file(gc, &registry.borrow(), options, filename)?;
-                gc.allocate_cell(handle).into()
+                open_file(gc, &registry.borrow(), options, filename)?.into()
             }
             Some(value) => {
                 file.as_userdata::<FileHandle>()?;
@@ -533,7 +551,11 @@ fn common_read<'gc>(
     Ok(values)
 }
 
-fn create_file_handle<'gc, I>(gc: &'gc GcContext, registry: &Table<'gc>, inner: I) -> UserData<'gc>
+fn create_file_handle<'gc, I>(
+    gc: &'gc GcContext,
+    registry: &Table<'gc>,
+    inner: I,
+) -> GcCell<'gc, UserData<'gc>>
 where
     I: Into<LuaFile>,
 {
@@ -543,6 +565,9 @@ where
             .get_field(gc.allocate_string(LUA_FILEHANDLE))
             .as_table(),
     );
+    let handle = gc.allocate_cell(handle);
+    // the metatable of file handles has `__gc`
+    gc.register_finalizer(handle.into());
     handle
 }
 
@@ -551,7 +576,7 @@ fn open_file<'gc, P: AsRef<[u8]>>(
     registry: &Table<'gc>,
     options: &OpenOptions,
     path: P,
-) -> Result<UserData<'gc>, FileError> {
+) -> Result<GcCell<'gc, UserData<'gc>>, FileError> {
     let path = path.as_ref().to_path()?;
     let file = options.open(path)?;
     Ok(create_file_handle(
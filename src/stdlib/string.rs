@@ -1,14 +1,18 @@
 mod format;
+mod pattern;
 
 use super::helpers::{set_functions_to_table, ArgumentsExt};
 use crate::{
     binary_chunk,
     gc::{GcCell, GcContext},
-    runtime::{Action, ErrorKind, Metamethod, Vm},
-    types::{Integer, Table, Type, Value},
+    runtime::{ops, Action, Continuation, ErrorKind, Metamethod, Operation, Vm},
+    types::{
+        Integer, LuaString, NativeClosure, NativeFunction, NativeFunctionPtr, Table, Type, Value,
+    },
 };
 use bstr::{ByteSlice, B};
-use std::ops::Range;
+use pattern::Matcher;
+use std::{cell::Cell, ops::Range};
 
 pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>> {
     let mut table = Table::new();
@@ -21,8 +25,11 @@ pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>
             (B("dump"), string_dump),
             (B("find"), string_find),
             (B("format"), format::string_format),
+            (B("gmatch"), string_gmatch),
+            (B("gsub"), string_gsub),
             (B("len"), string_len),
             (B("lower"), string_lower),
+            (B("match"), string_match),
             (B("sub"), string_sub),
             (B("rep"), string_rep),
             (B("reverse"), string_reverse),
@@ -34,11 +41,78 @@ pub fn load<'gc>(gc: &'gc GcContext, vm: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>
 
     let mut metatable = Table::new();
     metatable.set_field(vm.metamethod_name(Metamethod::Index), string);
+    let arith_metamethods: [(_, NativeFunctionPtr); 8] = [
+        (Metamethod::Add, string_arith_add),
+        (Metamethod::Sub, string_arith_sub),
+        (Metamethod::Mul, string_arith_mul),
+        (Metamethod::Mod, string_arith_mod),
+        (Metamethod::Pow, string_arith_pow),
+        (Metamethod::Div, string_arith_div),
+        (Metamethod::IDiv, string_arith_idiv),
+        (Metamethod::Unm, string_arith_unm),
+    ];
+    for (metamethod, func) in arith_metamethods {
+        metatable.set_field(vm.metamethod_name(metamethod), NativeFunction::new(func));
+    }
     vm.set_metatable_of_type(Type::String, gc.allocate_cell(metatable));
 
     string
 }
 
+macro_rules! string_arith {
+    ($($name:ident => $metamethod:ident,)*) => {
+        $(
+            fn $name<'gc>(
+                _: &'gc GcContext,
+                vm: &mut Vm<'gc>,
+                args: Vec<Value<'gc>>,
+            ) -> Result<Action<'gc>, ErrorKind> {
+                string_arith(vm, args, Metamethod::$metamethod)
+            }
+        )*
+    };
+}
+
+string_arith!(
+    string_arith_add => Add,
+    string_arith_sub => Sub,
+    string_arith_mul => Mul,
+    string_arith_mod => Mod,
+    string_arith_pow => Pow,
+    string_arith_div => Div,
+    string_arith_idiv => IDiv,
+    string_arith_unm => Unm,
+);
+
+// refer to "trymt" and "arith" in lstrlib.c
+fn string_arith<'gc>(
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+    metamethod: Metamethod,
+) -> Result<Action<'gc>, ErrorKind> {
+    let a = args.nth(1).as_value()?;
+    let b = args.nth(2).as_value()?;
+    if let (Some(x), Some(y)) = (a.to_numeric(), b.to_numeric()) {
+        if let Some(result) = ops::arithmetic_by_metamethod(metamethod, x, y)? {
+            return Ok(Action::Return(vec![result]));
+        }
+    }
+
+    if !matches!(b, Value::String(_)) {
+        if let Some(metamethod) = vm.metamethod_of_object(metamethod, b) {
+            return Ok(Action::TailCall {
+                callee: metamethod,
+                args: vec![a, b],
+            });
+        }
+    }
+    let culprit = if a.to_numeric().is_some() { b } else { a };
+    Err(ErrorKind::TypeError {
+        operation: Operation::Arithmetic,
+        ty: culprit.type_name(),
+    })
+}
+
 fn string_byte<'gc>(
     _: &'gc GcContext,
     _: &mut Vm<'gc>,
@@ -97,13 +171,21 @@ fn string_dump<'gc>(
         value => Err(ErrorKind::ArgumentTypeError {
             nth: 1,
             expected_type: "function",
-            got_type: value.map(|value| value.ty().name()),
+            got_type: value.map(|value| value.type_name()),
         }),
     }
 }
 
 fn string_find<'gc>(
-    _: &'gc GcContext,
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    find(gc, &args, true)
+}
+
+fn string_gmatch<'gc>(
+    gc: &'gc GcContext,
     _: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
@@ -112,35 +194,130 @@ fn string_find<'gc>(
     let pattern = args.nth(2);
     let pattern = pattern.to_string()?;
     let init = args.nth(3).to_integer_or(1)?;
-    let plain = args.nth(4).to_boolean().unwrap_or_default();
 
-    let len = s.len();
-    let start = match init {
-        1.. => init - 1,
-        0 => 0,
-        _ if init < -(len as Integer) => 0,
-        _ => len as Integer + init,
-    } as usize;
-    if start >= len {
-        return Ok(Action::Return(vec![Value::Nil]));
+    let start = init_to_index(init, s.len()).min(s.len() + 1);
+    let s = gc.allocate_string(s);
+    let pattern = gc.allocate_string(pattern);
+
+    let position = Cell::new(start);
+    let last_match = Cell::new(None);
+    let iterator = NativeClosure::with_upvalue((s, pattern), move |gc, _, &(s, pattern), _| {
+        let mut matcher = Matcher::new(s.as_bytes(), pattern.as_bytes());
+        for start in position.get()..=s.len() {
+            match matcher.match_at(start, 0)? {
+                Some(end) if Some(end) != last_match.get() => {
+                    position.set(end);
+                    last_match.set(Some(end));
+                    return Ok(Action::Return(matcher.captures(gc, Some(start..end))?));
+                }
+                _ => (),
+            }
+        }
+        Ok(Action::Return(vec![Value::Nil]))
+    });
+
+    Ok(Action::Return(vec![gc.allocate(iterator).into()]))
+}
+
+fn string_gsub<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let s = args.nth(1);
+    let s = s.to_string()?;
+    let pattern = args.nth(2);
+    let pattern = pattern.to_string()?;
+    let replacement = match args.nth(3).get() {
+        Some(
+            value @ (Value::Integer(_)
+            | Value::Number(_)
+            | Value::String(_)
+            | Value::Table(_)
+            | Value::NativeFunction(_)
+            | Value::LuaClosure(_)
+            | Value::NativeClosure(_)),
+        ) => value,
+        value => {
+            return Err(ErrorKind::ArgumentTypeError {
+                nth: 3,
+                expected_type: "string/function/table",
+                got_type: value.map(|value| value.type_name()),
+            })
+        }
+    };
+    let max_count = args.nth(4).to_integer_or((s.len() + 1) as Integer)?;
+
+    let anchor = pattern.first() == Some(&b'^');
+    let mut matcher = Matcher::new(&s, &pattern);
+    let mut output = Vec::new();
+    let mut copied = 0;
+    let mut pending_calls = Vec::new();
+    let mut count = 0;
+    let mut position = 0;
+    let mut last_match = None;
+    while count < max_count {
+        match matcher.match_at(position, anchor as usize)? {
+            Some(end) if Some(end) != last_match => {
+                count += 1;
+                let range = position..end;
+                match replacement {
+                    Value::Table(table) => {
+                        let key = matcher.capture(gc, 0, range.clone())?;
+                        let value = table.borrow().get(key);
+                        output.extend_from_slice(&s[copied..range.start]);
+                        add_replacement_value(&mut output, &s[range], value)?;
+                        copied = end;
+                    }
+                    Value::Integer(_) | Value::Number(_) | Value::String(_) => {
+                        output.extend_from_slice(&s[copied..range.start]);
+                        add_replacement_string(
+                            gc,
+                            &mut output,
+                            &matcher,
+                            &s,
+                            &replacement.to_string().unwrap(),
+                            range,
+                        )?;
+                        copied = end;
+                    }
+                    _ => {
+                        let captures = matcher.captures(gc, Some(range.clone()))?;
+                        pending_calls.push((range.start, range.end, captures));
+                    }
+                }
+                position = end;
+                last_match = Some(end);
+            }
+            _ if position < s.len() => position += 1,
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
     }
 
-    const SPECIALS: &[u8] = b"^$*+?.([%-";
-    if !plain && pattern.find_byteset(SPECIALS).is_some() {
-        todo!("regex")
+    if count == 0 {
+        return Ok(Action::Return(vec![args[1], 0.into()]));
+    }
+    if pending_calls.is_empty() {
+        output.extend_from_slice(&s[copied..]);
+        return Ok(Action::Return(vec![
+            gc.allocate_string(output).into(),
+            count.into(),
+        ]));
     }
 
-    Ok(Action::Return(
-        if let Some(pos) = &s[start..].find(&pattern) {
-            let i = *pos + start;
-            vec![
-                ((i + 1) as Integer).into(),
-                ((i + pattern.len()) as Integer).into(),
-            ]
-        } else {
-            vec![Value::Nil]
-        },
-    ))
+    pending_calls.reverse();
+    call_replacement_function(
+        gc,
+        replacement,
+        gc.allocate_string(s),
+        pending_calls,
+        Vec::new(),
+        0,
+        count,
+    )
 }
 
 fn string_len<'gc>(
@@ -161,6 +338,14 @@ fn string_lower<'gc>(
     Ok(Action::Return(vec![gc.allocate_string(lower).into()]))
 }
 
+fn string_match<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    find(gc, &args, false)
+}
+
 fn string_sub<'gc>(
     gc: &'gc GcContext,
     _: &mut Vm<'gc>,
@@ -180,7 +365,7 @@ fn string_sub<'gc>(
 
 fn string_rep<'gc>(
     gc: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    vm: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
     let s = args.nth(1);
@@ -200,7 +385,12 @@ fn string_rep<'gc>(
         }
 
         let count = n as usize;
-        let mut string = Vec::with_capacity(count * s.len() + (count - 1) * sep.len());
+        let len = count * s.len() + (count - 1) * sep.len();
+        vm.reserve_memory(gc, len)?;
+        let mut string = Vec::new();
+        string
+            .try_reserve_exact(len)
+            .map_err(|_| ErrorKind::MemoryError)?;
         for _ in 0..count - 1 {
             string.extend_from_slice(s.as_ref());
             string.extend_from_slice(sep.as_ref());
@@ -233,6 +423,153 @@ fn string_upper<'gc>(
     Ok(Action::Return(vec![gc.allocate_string(upper).into()]))
 }
 
+fn find<'gc>(
+    gc: &'gc GcContext,
+    args: &[Value<'gc>],
+    is_find: bool,
+) -> Result<Action<'gc>, ErrorKind> {
+    let s = args.nth(1);
+    let s = s.to_string()?;
+    let pattern = args.nth(2);
+    let pattern = pattern.to_string()?;
+    let init = args.nth(3).to_integer_or(1)?;
+
+    let start = init_to_index(init, s.len());
+    if start > s.len() {
+        return Ok(Action::Return(vec![Value::Nil]));
+    }
+
+    let plain = args.nth(4).to_boolean().unwrap_or_default();
+    if is_find && (plain || !pattern::has_specials(&pattern)) {
+        return Ok(Action::Return(
+            if let Some(pos) = &s[start..].find(&pattern) {
+                let i = *pos + start;
+                vec![
+                    ((i + 1) as Integer).into(),
+                    ((i + pattern.len()) as Integer).into(),
+                ]
+            } else {
+                vec![Value::Nil]
+            },
+        ));
+    }
+
+    let anchor = pattern.first() == Some(&b'^');
+    let mut matcher = Matcher::new(&s, &pattern);
+    for start in start..=s.len() {
+        if let Some(end) = matcher.match_at(start, anchor as usize)? {
+            return Ok(Action::Return(if is_find {
+                let mut results = vec![((start + 1) as Integer).into(), (end as Integer).into()];
+                results.extend(matcher.captures(gc, None)?);
+                results
+            } else {
+                matcher.captures(gc, Some(start..end))?
+            }));
+        }
+        if anchor {
+            break;
+        }
+    }
+    Ok(Action::Return(vec![Value::Nil]))
+}
+
+fn add_replacement_string(
+    gc: &GcContext,
+    output: &mut Vec<u8>,
+    matcher: &Matcher,
+    s: &[u8],
+    replacement: &[u8],
+    range: Range<usize>,
+) -> Result<(), ErrorKind> {
+    let mut iter = replacement.iter();
+    while let Some(&ch) = iter.next() {
+        if ch != b'%' {
+            output.push(ch);
+            continue;
+        }
+        match iter.next() {
+            Some(b'%') => output.push(b'%'),
+            Some(b'0') => output.extend_from_slice(&s[range.clone()]),
+            Some(&ch @ b'1'..=b'9') => {
+                let capture = matcher
+                    .capture(gc, (ch - b'1') as usize, range.clone())
+                    .map_err(|err| match err {
+                        ErrorKind::Other(msg) => {
+                            ErrorKind::Other(format!("{msg} in replacement string"))
+                        }
+                        err => err,
+                    })?;
+                output.extend_from_slice(&capture.to_string().unwrap());
+            }
+            _ => return Err(ErrorKind::other("invalid use of '%' in replacement string")),
+        }
+    }
+    Ok(())
+}
+
+fn add_replacement_value(
+    output: &mut Vec<u8>,
+    matched: &[u8],
+    value: Value,
+) -> Result<(), ErrorKind> {
+    if !value.to_boolean() {
+        output.extend_from_slice(matched);
+    } else if let Some(s) = value.to_string() {
+        output.extend_from_slice(&s);
+    } else {
+        return Err(ErrorKind::Other(format!(
+            "invalid replacement value (a {})",
+            value.ty().name()
+        )));
+    }
+    Ok(())
+}
+
+/// Calls the replacement function of `string.gsub` for each of the pending
+/// matches in turn. The matches are popped from the back of `pending_calls`.
+fn call_replacement_function<'gc>(
+    gc: &'gc GcContext,
+    replacement: Value<'gc>,
+    s: LuaString<'gc>,
+    mut pending_calls: Vec<(usize, usize, Vec<Value<'gc>>)>,
+    mut output: Vec<u8>,
+    copied: usize,
+    count: Integer,
+) -> Result<Action<'gc>, ErrorKind> {
+    let (start, end, captures) = if let Some(call) = pending_calls.pop() {
+        call
+    } else {
+        output.extend_from_slice(&s[copied..]);
+        return Ok(Action::Return(vec![
+            gc.allocate_string(output).into(),
+            count.into(),
+        ]));
+    };
+    output.extend_from_slice(&s[copied..start]);
+    Ok(Action::Call {
+        callee: replacement,
+        args: captures,
+        continuation: Continuation::with_context(
+            (replacement, s, (pending_calls, output)),
+            move |gc, _, (replacement, s, (pending_calls, mut output)), results: Vec<Value>| {
+                let value = results.first().copied().unwrap_or_default();
+                add_replacement_value(&mut output, &s[start..end], value)?;
+                call_replacement_function(gc, replacement, s, pending_calls, output, end, count)
+            },
+        ),
+    })
+}
+
+/// Converts the 1-based, possibly negative `init` argument to a 0-based index.
+const fn init_to_index(init: Integer, len: usize) -> usize {
+    (match init {
+        1.. => init - 1,
+        0 => 0,
+        _ if init < -(len as Integer) => 0,
+        _ => len as Integer + init,
+    }) as usize
+}
+
 const fn indices_to_range(i: Integer, j: Integer, len: Integer) -> Range<usize> {
     let start = match i {
         1.. => i - 1,
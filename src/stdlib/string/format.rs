@@ -1,17 +1,30 @@
 use crate::{
     gc::GcContext,
     math,
-    runtime::{Action, ErrorKind, Vm},
-    stdlib::helpers::ArgumentsExt,
-    types::{Integer, Number, Value},
+    runtime::{Action, Continuation, ErrorKind, Metamethod, Vm},
+    stdlib::helpers::{fmt_value, tostring_result, ArgumentsExt},
+    types::{Integer, LuaString, Number, Value},
 };
 use bstr::{ByteSlice, ByteVec};
 use byteorder::WriteBytesExt;
+use std::borrow::Cow;
 
 pub fn string_format<'gc>(
     gc: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    vm: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    format(gc, vm, args, Vec::new())
+}
+
+/// Formats the arguments, starting over after calling each `__tostring`
+/// metamethod of an argument of '%s'. `strings` holds the results of the
+/// metamethods called so far.
+fn format<'gc>(
+    gc: &'gc GcContext,
+    vm: &Vm<'gc>,
+    args: Vec<Value<'gc>>,
+    strings: Vec<LuaString<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
     let format_string = args.nth(1);
     let format_string = format_string.to_string()?;
@@ -20,6 +33,7 @@ pub fn string_format<'gc>(
     let mut arg_nth = 1;
 
     let mut output = Vec::new();
+    let mut num_strings_used = 0;
     'for_spec: while let Some(&ch) = format_iter.next() {
         if ch != b'%' {
             output.push(ch);
@@ -208,7 +222,32 @@ pub fn string_format<'gc>(
                 }
             }
             Some(b's') => {
-                let s = arg.to_string()?;
+                let value = arg.as_value()?;
+                let s = if let Some(metamethod) =
+                    vm.metamethod_of_object(Metamethod::ToString, value)
+                {
+                    let Some(s) = strings.get(num_strings_used) else {
+                        return Ok(Action::Call {
+                            callee: metamethod,
+                            args: vec![value],
+                            continuation: Continuation::with_context(
+                                (args, strings),
+                                |gc, vm, (args, mut strings), results| {
+                                    strings.push(tostring_result(gc, results)?);
+                                    format(gc, vm, args, strings)
+                                },
+                            ),
+                        });
+                    };
+                    num_strings_used += 1;
+                    Cow::Borrowed(s.as_bytes())
+                } else if let Some(s) = value.to_string() {
+                    s
+                } else {
+                    let mut s = Vec::new();
+                    fmt_value(vm, &mut s, value)?;
+                    Cow::Owned(s)
+                };
                 let mut s = s.as_ref();
                 if !spec.has_modifier {
                     output.push_str(s);
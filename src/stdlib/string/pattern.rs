@@ -0,0 +1,416 @@
+use crate::{
+    gc::GcContext,
+    runtime::ErrorKind,
+    types::{Integer, Value},
+};
+use bstr::ByteSlice;
+use std::ops::Range;
+
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+const ESCAPE: u8 = b'%';
+const MAX_CAPTURES: usize = 32;
+const MAX_CALLS: usize = 200;
+
+#[derive(Clone, Copy)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Closed(usize),
+}
+
+#[derive(Clone, Copy)]
+struct Capture {
+    start: usize,
+    len: CaptureLen,
+}
+
+pub fn has_specials(pattern: &[u8]) -> bool {
+    pattern.find_byteset(SPECIALS).is_some()
+}
+
+/// A port of the pattern matcher of the reference implementation.
+pub struct Matcher<'a> {
+    src: &'a [u8],
+    pattern: &'a [u8],
+    level: usize,
+    captures: [Capture; MAX_CAPTURES],
+    match_depth: usize,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(src: &'a [u8], pattern: &'a [u8]) -> Self {
+        Self {
+            src,
+            pattern,
+            level: 0,
+            captures: [Capture {
+                start: 0,
+                len: CaptureLen::Unfinished,
+            }; MAX_CAPTURES],
+            match_depth: MAX_CALLS,
+        }
+    }
+
+    /// Tries to match the pattern starting at `pattern_start` against the
+    /// source starting at `src_start`. Returns the end of the match.
+    pub fn match_at(
+        &mut self,
+        src_start: usize,
+        pattern_start: usize,
+    ) -> Result<Option<usize>, ErrorKind> {
+        self.level = 0;
+        self.match_depth = MAX_CALLS;
+        self.do_match(src_start, pattern_start)
+    }
+
+    /// Returns the captures of the last match. If the pattern has no captures,
+    /// the whole match `range` is returned instead when given.
+    pub fn captures<'gc>(
+        &self,
+        gc: &'gc GcContext,
+        range: Option<Range<usize>>,
+    ) -> Result<Vec<Value<'gc>>, ErrorKind> {
+        let count = if self.level == 0 && range.is_some() {
+            1
+        } else {
+            self.level
+        };
+        (0..count)
+            .map(|i| self.capture(gc, i, range.clone().unwrap_or_default()))
+            .collect()
+    }
+
+    /// Returns the `i`-th capture of the last match, or the whole match
+    /// `range` when `i` is 0 and the pattern has no captures.
+    pub fn capture<'gc>(
+        &self,
+        gc: &'gc GcContext,
+        i: usize,
+        range: Range<usize>,
+    ) -> Result<Value<'gc>, ErrorKind> {
+        if i >= self.level {
+            return if i == 0 {
+                Ok(gc.allocate_string(&self.src[range]).into())
+            } else {
+                Err(ErrorKind::Other(format!(
+                    "invalid capture index %{}",
+                    i + 1
+                )))
+            };
+        }
+        let Capture { start, len } = self.captures[i];
+        match len {
+            CaptureLen::Unfinished => Err(ErrorKind::other("unfinished capture")),
+            CaptureLen::Position => Ok(((start + 1) as Integer).into()),
+            CaptureLen::Closed(len) => Ok(gc.allocate_string(&self.src[start..start + len]).into()),
+        }
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, ErrorKind> {
+        if self.match_depth == 0 {
+            return Err(ErrorKind::other("pattern too complex"));
+        }
+        self.match_depth -= 1;
+
+        let result = loop {
+            let Some(&ch) = self.pattern.get(p) else {
+                break Some(s);
+            };
+            match ch {
+                b'(' => {
+                    break if self.pattern.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CaptureLen::Position)?
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pattern.len() => {
+                    break (s == self.src.len()).then_some(s);
+                }
+                ESCAPE if self.pattern.get(p + 1) == Some(&b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                            continue;
+                        }
+                        None => break None,
+                    }
+                }
+                ESCAPE if self.pattern.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pattern.get(p) != Some(&b'[') {
+                        return Err(ErrorKind::other("missing '[' after '%f' in pattern"));
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(previous, p, ep - 1)
+                        && self.match_bracket_class(current, p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    break None;
+                }
+                ESCAPE
+                    if self
+                        .pattern
+                        .get(p + 1)
+                        .is_some_and(|ch| ch.is_ascii_digit()) =>
+                {
+                    match self.match_capture(s, self.pattern[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let suffix = self.pattern.get(ep).copied();
+                    if !self.single_match(s, p, ep) {
+                        if matches!(suffix, Some(b'*' | b'?' | b'-')) {
+                            p = ep + 1;
+                            continue;
+                        }
+                        break None;
+                    }
+                    match suffix {
+                        Some(b'?') => {
+                            if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                                break Some(end);
+                            }
+                            p = ep + 1;
+                        }
+                        Some(b'+') => break self.max_expand(s + 1, p, ep)?,
+                        Some(b'*') => break self.max_expand(s, p, ep)?,
+                        Some(b'-') => break self.min_expand(s, p, ep)?,
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        };
+
+        self.match_depth += 1;
+        Ok(result)
+    }
+
+    fn class_end(&self, mut p: usize) -> Result<usize, ErrorKind> {
+        let ch = self.pattern[p];
+        p += 1;
+        match ch {
+            ESCAPE => {
+                if p == self.pattern.len() {
+                    return Err(ErrorKind::other("malformed pattern (ends with '%')"));
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pattern.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                loop {
+                    if p == self.pattern.len() {
+                        return Err(ErrorKind::other("malformed pattern (missing ']')"));
+                    }
+                    let ch = self.pattern[p];
+                    p += 1;
+                    if ch == ESCAPE && p < self.pattern.len() {
+                        p += 1;
+                    }
+                    if self.pattern.get(p) == Some(&b']') {
+                        break;
+                    }
+                }
+                Ok(p + 1)
+            }
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&ch) = self.src.get(s) else {
+            return false;
+        };
+        match self.pattern[p] {
+            b'.' => true,
+            ESCAPE => match_class(ch, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(ch, p, ep - 1),
+            pch => pch == ch,
+        }
+    }
+
+    /// `p` points to the opening `[` and `ec` to the closing `]` of the set.
+    fn match_bracket_class(&self, ch: u8, mut p: usize, ec: usize) -> bool {
+        let mut sig = true;
+        if self.pattern[p + 1] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if self.pattern[p] == ESCAPE {
+                p += 1;
+                if match_class(ch, self.pattern[p]) {
+                    return sig;
+                }
+            } else if self.pattern[p + 1] == b'-' && p + 2 < ec {
+                if self.pattern[p] <= ch && ch <= self.pattern[p + 2] {
+                    return sig;
+                }
+                p += 2;
+            } else if self.pattern[p] == ch {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, ErrorKind> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(
+        &mut self,
+        mut s: usize,
+        p: usize,
+        ep: usize,
+    ) -> Result<Option<usize>, ErrorKind> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, ErrorKind> {
+        if self.level >= MAX_CAPTURES {
+            return Err(ErrorKind::other("too many captures"));
+        }
+        self.captures[self.level] = Capture { start: s, len };
+        self.level += 1;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, ErrorKind> {
+        let l = self.capture_to_close()?;
+        self.captures[l].len = CaptureLen::Closed(s - self.captures[l].start);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[l].len = CaptureLen::Unfinished;
+        }
+        Ok(result)
+    }
+
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>, ErrorKind> {
+        let l = self.check_capture(l)?;
+        let Capture { start, len } = self.captures[l];
+        Ok(match len {
+            CaptureLen::Closed(len)
+                if self.src.len() - s >= len
+                    && self.src[start..start + len] == self.src[s..s + len] =>
+            {
+                Some(s + len)
+            }
+            _ => None,
+        })
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, ErrorKind> {
+        if p + 1 >= self.pattern.len() {
+            return Err(ErrorKind::other(
+                "malformed pattern (missing arguments to '%b')",
+            ));
+        }
+        let (open, close) = (self.pattern[p], self.pattern[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &ch) in self.src.iter().enumerate().skip(s + 1) {
+            if ch == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if ch == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize, ErrorKind> {
+        let l = l as isize - b'1' as isize;
+        match usize::try_from(l) {
+            Ok(l) if l < self.level && !matches!(self.captures[l].len, CaptureLen::Unfinished) => {
+                Ok(l)
+            }
+            _ => Err(ErrorKind::Other(format!(
+                "invalid capture index %{}",
+                l + 1
+            ))),
+        }
+    }
+
+    fn capture_to_close(&self) -> Result<usize, ErrorKind> {
+        self.captures[..self.level]
+            .iter()
+            .rposition(|capture| matches!(capture.len, CaptureLen::Unfinished))
+            .ok_or_else(|| ErrorKind::other("invalid pattern capture"))
+    }
+}
+
+fn match_class(ch: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => ch.is_ascii_alphabetic(),
+        b'c' => ch.is_ascii_control(),
+        b'd' => ch.is_ascii_digit(),
+        b'g' => ch.is_ascii_graphic(),
+        b'l' => ch.is_ascii_lowercase(),
+        b'p' => ch.is_ascii_punctuation(),
+        b's' => ch.is_ascii_whitespace() || ch == b'\x0b',
+        b'u' => ch.is_ascii_uppercase(),
+        b'w' => ch.is_ascii_alphanumeric(),
+        b'x' => ch.is_ascii_hexdigit(),
+        // deprecated option
+        b'z' => ch == 0,
+        _ => return class == ch,
+    };
+    matches != class.is_ascii_uppercase()
+}
@@ -1,3 +1,5 @@
+mod sort;
+
 use super::helpers::{set_functions_to_table, ArgumentsExt};
 use crate::{
     gc::{GcCell, GcContext},
@@ -17,6 +19,7 @@ pub fn load<'gc>(gc: &'gc GcContext, _: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>>
             (B("move"), table_move),
             (B("pack"), table_pack),
             (B("remove"), table_remove),
+            (B("sort"), sort::table_sort),
             (B("unpack"), table_unpack),
         ],
     );
@@ -25,21 +28,26 @@ pub fn load<'gc>(gc: &'gc GcContext, _: &mut Vm<'gc>) -> GcCell<'gc, Table<'gc>>
 
 fn table_concat<'gc>(
     gc: &'gc GcContext,
-    _: &mut Vm<'gc>,
+    vm: &mut Vm<'gc>,
     args: Vec<Value<'gc>>,
 ) -> Result<Action<'gc>, ErrorKind> {
     let table = args.nth(1).as_table()?;
-    let table = table.borrow();
+    let table_ref = table.borrow();
     let sep = args.nth(2);
     let sep = sep.to_string_or(B(""))?;
     let i = args.nth(3).to_integer_or(1)?;
-    let j = args.nth(4).to_integer_or_else(|| table.lua_len())?;
+    let j = args.nth(4).to_integer_or_else(|| table_ref.lua_len())?;
 
-    let mut strings = Vec::new();
+    let mut values = Vec::new();
+    let mut len = 0usize;
     for index in i..=j {
-        let value = table.get_integer_key(index);
+        let value = table_ref.get_integer_key(index);
         if let Some(string) = value.to_string() {
-            strings.push(string.to_vec());
+            if !values.is_empty() {
+                len = len.saturating_add(sep.len());
+            }
+            len = len.saturating_add(string.len());
+            values.push(value);
         } else {
             return Err(ErrorKind::Other(format!(
                 "invalid value ({}) at index {} in table for 'concat'",
@@ -48,8 +56,20 @@ fn table_concat<'gc>(
             )));
         }
     }
-
-    let concatenated = strings.join(sep.as_ref());
+    drop(table_ref);
+
+    // the values stay reachable from the table while garbage is collected
+    vm.reserve_memory(gc, len)?;
+    let mut concatenated = Vec::new();
+    concatenated
+        .try_reserve_exact(len)
+        .map_err(|_| ErrorKind::MemoryError)?;
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            concatenated.extend_from_slice(sep.as_ref());
+        }
+        concatenated.extend_from_slice(&value.to_string().unwrap());
+    }
     Ok(Action::Return(vec![gc
         .allocate_string(concatenated)
         .into()]))
@@ -0,0 +1,350 @@
+use crate::{
+    gc::{GarbageCollect, GcContext, Tracer},
+    runtime::{ops, Action, Continuation, ErrorKind, Metamethod, MetamethodChain, Operation, Vm},
+    stdlib::helpers::ArgumentsExt,
+    types::{Integer, Value},
+};
+
+pub fn table_sort<'gc>(
+    gc: &'gc GcContext,
+    vm: &mut Vm<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let table = args.nth(1).as_table()?;
+    let comparator = args.nth(2);
+    let comparator = if comparator.is_present() {
+        Some(comparator.ensure_function()?)
+    } else {
+        None
+    };
+
+    let sort = Sort {
+        table: table.into(),
+        comparator,
+        phase: Phase::Read,
+        len: 0,
+        values: Vec::new(),
+        sorter: Sorter::default(),
+        written: 0,
+    };
+    match vm.metamethod_of_object(Metamethod::Len, table.into()) {
+        Some(metamethod) => Ok(Action::Call {
+            callee: metamethod,
+            args: vec![table.into()],
+            continuation: Continuation::with_context(
+                sort,
+                |gc, vm, mut sort, results: Vec<Value>| {
+                    let len = results.first().copied().unwrap_or_default();
+                    sort.len = len
+                        .to_integer()
+                        .ok_or_else(|| ErrorKind::other("object length is not an integer"))?;
+                    sort.start()?;
+                    sort.resume(gc, vm, false)
+                },
+            ),
+        }),
+        None => {
+            let mut sort = sort;
+            sort.len = table.borrow().lua_len();
+            sort.start()?;
+            sort.resume(gc, vm, false)
+        }
+    }
+}
+
+enum Phase {
+    Read,
+    Sort,
+    Write,
+}
+
+/// State of `table.sort`. Elements are read into `values` with `__index`,
+/// sorted, and then written back with `__newindex`, calling the comparator
+/// and the metamethods through continuations.
+struct Sort<'gc> {
+    table: Value<'gc>,
+    comparator: Option<Value<'gc>>,
+    phase: Phase,
+    len: Integer,
+    values: Vec<Value<'gc>>,
+    sorter: Sorter<'gc>,
+    written: usize,
+}
+
+unsafe impl GarbageCollect for Sort<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.table.trace(tracer);
+        self.comparator.trace(tracer);
+        self.values.trace(tracer);
+        self.sorter.pivot.trace(tracer);
+    }
+}
+
+impl<'gc> Sort<'gc> {
+    fn start(&mut self) -> Result<(), ErrorKind> {
+        if self.len >= i32::MAX as Integer {
+            return Err(ErrorKind::ArgumentError {
+                nth: 1,
+                message: "array too big",
+            });
+        }
+        if self.len < 2 {
+            // nothing to sort, so the table is not touched at all
+            self.len = 0;
+        }
+        self.values.reserve(self.len as usize);
+        Ok(())
+    }
+
+    /// Drives the sort until it finishes or needs to call a function.
+    /// `less` is the result of the last comparison requested.
+    fn resume(
+        mut self,
+        gc: &'gc GcContext,
+        vm: &Vm<'gc>,
+        mut less: bool,
+    ) -> Result<Action<'gc>, ErrorKind> {
+        loop {
+            match self.phase {
+                Phase::Read => {
+                    if self.values.len() as Integer >= self.len {
+                        self.phase = Phase::Sort;
+                        self.sorter.up = self.values.len().saturating_sub(1);
+                        continue;
+                    }
+                    let key = (self.values.len() + 1) as Integer;
+                    let value = self
+                        .table
+                        .borrow_as_table()
+                        .map(|table| table.get_integer_key(key))
+                        .unwrap_or_default();
+                    if !value.is_nil() {
+                        self.values.push(value);
+                        continue;
+                    }
+                    match vm.index_chain(self.table, key.into())? {
+                        MetamethodChain::Done(value) => self.values.push(value),
+                        MetamethodChain::Call { metamethod, table } => {
+                            return Ok(Action::Call {
+                                callee: metamethod,
+                                args: vec![table, key.into()],
+                                continuation: Continuation::with_context(
+                                    self,
+                                    |gc, vm, mut sort, results: Vec<Value>| {
+                                        sort.values
+                                            .push(results.first().copied().unwrap_or_default());
+                                        sort.resume(gc, vm, false)
+                                    },
+                                ),
+                            })
+                        }
+                    }
+                }
+                Phase::Sort => {
+                    let (a, b) = match self.sorter.resume(&mut self.values, less)? {
+                        Some(operands) => operands,
+                        None => {
+                            self.phase = Phase::Write;
+                            continue;
+                        }
+                    };
+                    let callee = if let Some(comparator) = self.comparator {
+                        comparator
+                    } else if let Some(result) = ops::lt(a, b) {
+                        less = result;
+                        continue;
+                    } else {
+                        vm.metamethod_of_object(Metamethod::Lt, a)
+                            .or_else(|| vm.metamethod_of_object(Metamethod::Lt, b))
+                            .ok_or_else(|| ErrorKind::TypeError {
+                                operation: Operation::Compare,
+                                ty: b.type_name(),
+                            })?
+                    };
+                    return Ok(Action::Call {
+                        callee,
+                        args: vec![a, b],
+                        continuation: Continuation::with_context(
+                            self,
+                            |gc, vm, sort, results: Vec<Value>| {
+                                let less =
+                                    results.first().map(Value::to_boolean).unwrap_or_default();
+                                sort.resume(gc, vm, less)
+                            },
+                        ),
+                    });
+                }
+                Phase::Write => {
+                    let Some(&value) = self.values.get(self.written) else {
+                        return Ok(Action::Return(Vec::new()));
+                    };
+                    self.written += 1;
+                    let key = self.written as Integer;
+                    let replaced = self
+                        .table
+                        .borrow_as_table_mut(gc)
+                        .map(|mut table| table.replace_integer_key(key, value))
+                        .unwrap_or_default();
+                    if replaced {
+                        continue;
+                    }
+                    if let MetamethodChain::Call { metamethod, table } =
+                        vm.new_index_chain(gc, self.table, key.into(), value)?
+                    {
+                        return Ok(Action::Call {
+                            callee: metamethod,
+                            args: vec![table, key.into(), value],
+                            continuation: Continuation::with_context(self, |gc, vm, sort, _| {
+                                sort.resume(gc, vm, false)
+                            }),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+enum SortPhase {
+    #[default]
+    Interval,
+    CompareUpLo,
+    ComparePivotLo,
+    CompareUpPivot,
+    Partition,
+    PartitionLower,
+    PartitionUpper,
+}
+
+/// The quicksort of the reference implementation, turned inside out so that
+/// every comparison is requested from the caller.
+#[derive(Default)]
+struct Sorter<'gc> {
+    phase: SortPhase,
+    lo: usize,
+    up: usize,
+    p: usize,
+    i: usize,
+    j: usize,
+    pivot: Value<'gc>,
+    intervals: Vec<(usize, usize)>,
+}
+
+impl<'gc> Sorter<'gc> {
+    /// Advances the sort using `less`, the result of the last requested
+    /// comparison. Returns the next pair of values to compare with `<`, or
+    /// `None` when `values` are sorted.
+    fn resume(
+        &mut self,
+        values: &mut [Value<'gc>],
+        less: bool,
+    ) -> Result<Option<(Value<'gc>, Value<'gc>)>, ErrorKind> {
+        loop {
+            match self.phase {
+                SortPhase::Interval => {
+                    if self.lo >= self.up {
+                        match self.intervals.pop() {
+                            Some((lo, up)) => {
+                                self.lo = lo;
+                                self.up = up;
+                                continue;
+                            }
+                            None => return Ok(None),
+                        }
+                    }
+                    self.phase = SortPhase::CompareUpLo;
+                    return Ok(Some((values[self.up], values[self.lo])));
+                }
+                SortPhase::CompareUpLo => {
+                    if less {
+                        values.swap(self.lo, self.up);
+                    }
+                    if self.up - self.lo == 1 {
+                        self.finish_interval();
+                        continue;
+                    }
+                    self.p = (self.lo + self.up) / 2;
+                    self.phase = SortPhase::ComparePivotLo;
+                    return Ok(Some((values[self.p], values[self.lo])));
+                }
+                SortPhase::ComparePivotLo => {
+                    if less {
+                        values.swap(self.p, self.lo);
+                        self.phase = SortPhase::Partition;
+                        continue;
+                    }
+                    self.phase = SortPhase::CompareUpPivot;
+                    return Ok(Some((values[self.up], values[self.p])));
+                }
+                SortPhase::CompareUpPivot => {
+                    if less {
+                        values.swap(self.p, self.up);
+                    }
+                    self.phase = SortPhase::Partition;
+                }
+                SortPhase::Partition => {
+                    if self.up - self.lo == 2 {
+                        self.finish_interval();
+                        continue;
+                    }
+                    self.pivot = values[self.p];
+                    values.swap(self.p, self.up - 1);
+                    self.i = self.lo + 1;
+                    self.j = self.up - 1;
+                    self.phase = SortPhase::PartitionLower;
+                    return Ok(Some((values[self.i], self.pivot)));
+                }
+                SortPhase::PartitionLower => {
+                    if less {
+                        if self.i == self.up - 1 {
+                            return Err(invalid_order_function());
+                        }
+                        self.i += 1;
+                        return Ok(Some((values[self.i], self.pivot)));
+                    }
+                    self.j -= 1;
+                    self.phase = SortPhase::PartitionUpper;
+                    return Ok(Some((self.pivot, values[self.j])));
+                }
+                SortPhase::PartitionUpper => {
+                    if less {
+                        if self.j < self.i {
+                            return Err(invalid_order_function());
+                        }
+                        self.j -= 1;
+                        return Ok(Some((self.pivot, values[self.j])));
+                    }
+                    if self.j >= self.i {
+                        values.swap(self.i, self.j);
+                        self.i += 1;
+                        self.phase = SortPhase::PartitionLower;
+                        return Ok(Some((values[self.i], self.pivot)));
+                    }
+
+                    // a[lo .. p - 1] <= a[p] == P <= a[p + 1 .. up]
+                    let p = self.i;
+                    values.swap(self.up - 1, p);
+                    self.pivot = Value::Nil;
+                    if p - self.lo < self.up - p {
+                        self.intervals.push((p + 1, self.up));
+                        self.up = p - 1;
+                    } else {
+                        self.intervals.push((self.lo, p - 1));
+                        self.lo = p + 1;
+                    }
+                    self.phase = SortPhase::Interval;
+                }
+            }
+        }
+    }
+
+    fn finish_interval(&mut self) {
+        self.lo = self.up;
+        self.phase = SortPhase::Interval;
+    }
+}
+
+fn invalid_order_function() -> ErrorKind {
+    ErrorKind::other("invalid order function for sorting")
+}
@@ -1,9 +1,11 @@
+mod convert;
 mod function;
 mod string;
 mod table;
 mod thread;
 mod user_data;
 
+pub use convert::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, LuaFn, Variadic};
 pub(crate) use function::Upvalue;
 pub use function::{
     AbsLineInfo, LineRange, LocalVariable, LuaClosure, LuaClosureProto, NativeClosure,
@@ -11,23 +13,17 @@ pub use function::{
 };
 pub use string::LuaString;
 pub use table::{Table, TableError};
-pub(crate) use thread::ThreadStatus;
 pub use thread::{LuaThread, TracebackFrame};
-pub use user_data::UserData;
+pub(crate) use thread::{StackLevel, ThreadStatus};
+pub use user_data::{LuaUserData, UserData, UserDataMethods};
 
 use crate::{
-    gc::{GarbageCollect, Gc, GcCell, GcContext, Tracer},
+    gc::{GarbageCollect, Gc, GcCell, GcContext, GcRefMut, Tracer},
     number_is_valid_integer,
     string::{parse_positive_hex_float, parse_positive_integer_with_base, trim_whitespaces},
 };
 use bstr::ByteSlice;
-use std::{
-    any::Any,
-    borrow::Cow,
-    cell::{Ref, RefMut},
-    fmt::Display,
-    io::Write,
-};
+use std::{any::Any, borrow::Cow, cell::Ref, fmt::Display, io::Write};
 
 macro_rules! types {
     ($($variant:ident => $name:tt,)*) => {
@@ -249,6 +245,32 @@ impl<'gc> Value<'gc> {
         }
     }
 
+    /// Returns the name of the type used in error messages, which is the
+    /// `__name` field of the metatable of a table or userdata if the field
+    /// is a string.
+    pub fn type_name(&self) -> Cow<'static, str> {
+        let metatable = match self {
+            Self::Table(table) => table.borrow().metatable(),
+            Self::UserData(ud) => ud.try_borrow().ok().and_then(|ud| ud.metatable()),
+            _ => None,
+        };
+        if let Some(metatable) = metatable {
+            // without a `GcContext` the field cannot be looked up by its
+            // interned name, so the (usually small) metatable is scanned
+            let metatable = metatable.borrow();
+            let mut key = Value::Nil;
+            while let Ok(Some((k, v))) = metatable.next(key) {
+                match (k, v) {
+                    (Self::String(k), Self::String(name)) if k.as_bytes() == b"__name" => {
+                        return name.to_str_lossy().into_owned().into();
+                    }
+                    _ => key = k,
+                }
+            }
+        }
+        self.ty().name().into()
+    }
+
     pub const fn is_nil(&self) -> bool {
         matches!(self, Value::Nil)
     }
@@ -266,6 +288,22 @@ impl<'gc> Value<'gc> {
         }
     }
 
+    /// Converts a number or a string convertible to a number to a number
+    /// following the rules of `tonumber`. Strings representing integers are
+    /// converted to integers.
+    pub fn to_numeric(&self) -> Option<Self> {
+        match self {
+            Self::Integer(_) | Self::Number(_) => Some(*self),
+            Self::String(s) => {
+                let s = trim_whitespaces(s).to_str().ok()?;
+                parse_integer(s)
+                    .map(Self::Integer)
+                    .or_else(|| parse_number(s).map(Self::Number))
+            }
+            _ => None,
+        }
+    }
+
     pub const fn to_number_without_string_coercion(&self) -> Option<Number> {
         match self {
             Self::Number(x) => Some(*x),
@@ -335,7 +373,7 @@ impl<'gc> Value<'gc> {
         }
     }
 
-    pub fn borrow_as_table_mut(&self, gc: &'gc GcContext) -> Option<RefMut<Table<'gc>>> {
+    pub fn borrow_as_table_mut(&self, gc: &'gc GcContext) -> Option<GcRefMut<Table<'gc>>> {
         if let Self::Table(x) = self {
             Some(x.borrow_mut(gc))
         } else {
@@ -383,7 +421,7 @@ impl<'gc> Value<'gc> {
         }
     }
 
-    pub fn borrow_as_thread_mut(&self, gc: &'gc GcContext) -> Option<RefMut<LuaThread<'gc>>> {
+    pub fn borrow_as_thread_mut(&self, gc: &'gc GcContext) -> Option<GcRefMut<LuaThread<'gc>>> {
         if let Self::Thread(x) = self {
             Some(x.borrow_mut(gc))
         } else {
@@ -409,9 +447,9 @@ impl<'gc> Value<'gc> {
     pub fn borrow_as_userdata_mut<'a, T: Any>(
         &'a self,
         gc: &'gc GcContext,
-    ) -> Option<RefMut<'a, T>> {
+    ) -> Option<GcRefMut<'a, T>> {
         if let Self::UserData(ud) = self {
-            RefMut::filter_map(ud.borrow_mut(gc), |ud| ud.get_mut()).ok()
+            GcRefMut::filter_map(ud.borrow_mut(gc), |ud| ud.get_mut()).ok()
         } else {
             None
         }
@@ -0,0 +1,475 @@
+use super::{Integer, LuaString, Number, Table, Value};
+use crate::{
+    gc::{GcCell, GcContext},
+    runtime::ErrorKind,
+};
+use bstr::ByteSlice;
+use std::{borrow::Cow, collections::HashMap, hash::Hash};
+
+/// A type that can be converted from a Lua value.
+pub trait FromLua<'gc>: Sized {
+    /// Name of the type in `bad argument` errors.
+    const EXPECTED_TYPE: &'static str;
+
+    /// Returns `None` if `value` cannot be converted.
+    fn from_lua(value: Value<'gc>) -> Option<Self>;
+
+    /// Returns the value of a missing argument, or `None` if the argument is
+    /// required.
+    fn from_missing() -> Option<Self> {
+        None
+    }
+
+    /// Returns the expected type and the actual type reported in `bad
+    /// argument` errors when `value` cannot be converted. Containers report
+    /// those of the first element that cannot be converted.
+    fn type_mismatch(value: Value<'gc>) -> (&'static str, Cow<'static, str>) {
+        (Self::EXPECTED_TYPE, value.type_name())
+    }
+}
+
+/// A type that can be converted into a Lua value.
+pub trait IntoLua<'gc> {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind>;
+}
+
+/// A type that can be converted from a list of arguments.
+pub trait FromLuaMulti<'gc>: Sized {
+    /// Converts `args`, where `nth` is the position of the first of them in
+    /// the argument list, used in `bad argument` errors.
+    fn from_lua_multi(args: &[Value<'gc>], nth: usize) -> Result<Self, ErrorKind>;
+}
+
+/// A type that can be converted into a list of return values.
+pub trait IntoLuaMulti<'gc> {
+    fn into_lua_multi(self, gc: &'gc GcContext) -> Result<Vec<Value<'gc>>, ErrorKind>;
+}
+
+/// Any number of values of the same type, taking the remaining arguments or
+/// returning several values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variadic<T>(pub Vec<T>);
+
+impl<T> From<Vec<T>> for Variadic<T> {
+    fn from(values: Vec<T>) -> Self {
+        Self(values)
+    }
+}
+
+impl<T> std::ops::Deref for Variadic<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> std::ops::DerefMut for Variadic<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+fn from_lua_arg<'gc, T: FromLua<'gc>>(
+    value: Option<Value<'gc>>,
+    nth: usize,
+) -> Result<T, ErrorKind> {
+    let converted = match value {
+        Some(value) => T::from_lua(value),
+        None => T::from_missing(),
+    };
+    converted.ok_or_else(|| {
+        let (expected_type, got_type) = match value {
+            Some(value) => {
+                let (expected_type, got_type) = T::type_mismatch(value);
+                (expected_type, Some(got_type))
+            }
+            None => (T::EXPECTED_TYPE, None),
+        };
+        ErrorKind::ArgumentTypeError {
+            nth,
+            expected_type,
+            got_type,
+        }
+    })
+}
+
+impl<'gc> FromLua<'gc> for Value<'gc> {
+    const EXPECTED_TYPE: &'static str = "value";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl<'gc> IntoLua<'gc> for Value<'gc> {
+    fn into_lua(self, _: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(self)
+    }
+}
+
+impl<'gc> FromLua<'gc> for bool {
+    const EXPECTED_TYPE: &'static str = "boolean";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        Some(value.to_boolean())
+    }
+}
+
+impl<'gc> IntoLua<'gc> for bool {
+    fn into_lua(self, _: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(self.into())
+    }
+}
+
+// `u8` is left out so that `Vec<u8>` can be converted from and into strings
+macro_rules! impl_integer {
+    ($($ty:ty)*) => {
+        $(
+            impl<'gc> FromLua<'gc> for $ty {
+                const EXPECTED_TYPE: &'static str = "integer";
+
+                fn from_lua(value: Value<'gc>) -> Option<Self> {
+                    value.to_integer().and_then(|i| i.try_into().ok())
+                }
+            }
+
+            impl<'gc> IntoLua<'gc> for $ty {
+                fn into_lua(self, _: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+                    // integers out of range are converted to floats
+                    Ok(Integer::try_from(self)
+                        .map(Value::Integer)
+                        .unwrap_or(Value::Number(self as Number)))
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8 i16 i32 i64 isize u16 u32 u64 usize);
+
+macro_rules! impl_float {
+    ($($ty:ty)*) => {
+        $(
+            impl<'gc> FromLua<'gc> for $ty {
+                const EXPECTED_TYPE: &'static str = "number";
+
+                fn from_lua(value: Value<'gc>) -> Option<Self> {
+                    value.to_number().map(|x| x as $ty)
+                }
+            }
+
+            impl<'gc> IntoLua<'gc> for $ty {
+                fn into_lua(self, _: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+                    Ok(Value::Number(self.into()))
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32 f64);
+
+impl<'gc> FromLua<'gc> for LuaString<'gc> {
+    const EXPECTED_TYPE: &'static str = "string";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        value.as_lua_string().copied()
+    }
+}
+
+impl<'gc> IntoLua<'gc> for LuaString<'gc> {
+    fn into_lua(self, _: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(self.into())
+    }
+}
+
+/// Numbers are converted to strings. Invalid UTF-8 sequences are replaced
+/// with U+FFFD.
+impl<'gc> FromLua<'gc> for String {
+    const EXPECTED_TYPE: &'static str = "string";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        value
+            .to_string()
+            .map(|bytes| bytes.to_str_lossy().into_owned())
+    }
+}
+
+impl<'gc> IntoLua<'gc> for String {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(gc.allocate_string(self.into_bytes()).into())
+    }
+}
+
+impl<'gc> IntoLua<'gc> for &str {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(gc.allocate_string(self.as_bytes()).into())
+    }
+}
+
+/// Numbers are converted to strings.
+impl<'gc> FromLua<'gc> for Vec<u8> {
+    const EXPECTED_TYPE: &'static str = "string";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        value.to_string().map(|bytes| bytes.into_owned())
+    }
+}
+
+impl<'gc> IntoLua<'gc> for Vec<u8> {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(gc.allocate_string(self).into())
+    }
+}
+
+impl<'gc> IntoLua<'gc> for &[u8] {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(gc.allocate_string(self).into())
+    }
+}
+
+impl<'gc> FromLua<'gc> for GcCell<'gc, Table<'gc>> {
+    const EXPECTED_TYPE: &'static str = "table";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        value.as_table()
+    }
+}
+
+impl<'gc> IntoLua<'gc> for GcCell<'gc, Table<'gc>> {
+    fn into_lua(self, _: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        Ok(self.into())
+    }
+}
+
+/// `nil` and missing arguments are converted to `None`.
+impl<'gc, T: FromLua<'gc>> FromLua<'gc> for Option<T> {
+    const EXPECTED_TYPE: &'static str = T::EXPECTED_TYPE;
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        match value {
+            Value::Nil => Some(None),
+            value => T::from_lua(value).map(Some),
+        }
+    }
+
+    fn from_missing() -> Option<Self> {
+        Some(None)
+    }
+
+    fn type_mismatch(value: Value<'gc>) -> (&'static str, Cow<'static, str>) {
+        T::type_mismatch(value)
+    }
+}
+
+impl<'gc, T: IntoLua<'gc>> IntoLua<'gc> for Option<T> {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        match self {
+            Some(value) => value.into_lua(gc),
+            None => Ok(Value::Nil),
+        }
+    }
+}
+
+/// Converts the sequence `t[1]`, ..., `t[#t]` of a table without invoking
+/// metamethods.
+impl<'gc, T: FromLua<'gc>> FromLua<'gc> for Vec<T> {
+    const EXPECTED_TYPE: &'static str = "table";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        let table = value.borrow_as_table()?;
+        (1..=table.lua_len())
+            .map(|i| T::from_lua(table.get_integer_key(i)))
+            .collect()
+    }
+
+    fn type_mismatch(value: Value<'gc>) -> (&'static str, Cow<'static, str>) {
+        let element = value.borrow_as_table().and_then(|table| {
+            (1..=table.lua_len())
+                .map(|i| table.get_integer_key(i))
+                .find(|element| T::from_lua(*element).is_none())
+        });
+        match element {
+            Some(element) => T::type_mismatch(element),
+            None => (Self::EXPECTED_TYPE, value.type_name()),
+        }
+    }
+}
+
+impl<'gc, T: IntoLua<'gc>> IntoLua<'gc> for Vec<T> {
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        let values = self
+            .into_iter()
+            .map(|value| value.into_lua(gc))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(gc.allocate_cell(Table::from(values)).into())
+    }
+}
+
+/// Converts all entries of a table without invoking metamethods.
+impl<'gc, K, V> FromLua<'gc> for HashMap<K, V>
+where
+    K: FromLua<'gc> + Eq + Hash,
+    V: FromLua<'gc>,
+{
+    const EXPECTED_TYPE: &'static str = "table";
+
+    fn from_lua(value: Value<'gc>) -> Option<Self> {
+        let table = value.borrow_as_table()?;
+        let mut map = HashMap::new();
+        let mut key = Value::Nil;
+        while let Some((k, v)) = table.next(key).ok()? {
+            map.insert(K::from_lua(k)?, V::from_lua(v)?);
+            key = k;
+        }
+        Some(map)
+    }
+
+    fn type_mismatch(value: Value<'gc>) -> (&'static str, Cow<'static, str>) {
+        if let Some(table) = value.borrow_as_table() {
+            let mut key = Value::Nil;
+            while let Ok(Some((k, v))) = table.next(key) {
+                if K::from_lua(k).is_none() {
+                    return K::type_mismatch(k);
+                }
+                if V::from_lua(v).is_none() {
+                    return V::type_mismatch(v);
+                }
+                key = k;
+            }
+        }
+        (Self::EXPECTED_TYPE, value.type_name())
+    }
+}
+
+impl<'gc, K, V> IntoLua<'gc> for HashMap<K, V>
+where
+    K: IntoLua<'gc>,
+    V: IntoLua<'gc>,
+{
+    fn into_lua(self, gc: &'gc GcContext) -> Result<Value<'gc>, ErrorKind> {
+        let mut table = Table::with_size(0, self.len());
+        for (key, value) in self {
+            table.set(key.into_lua(gc)?, value.into_lua(gc)?)?;
+        }
+        Ok(gc.allocate_cell(table).into())
+    }
+}
+
+impl<'gc, T: FromLua<'gc>> FromLuaMulti<'gc> for T {
+    fn from_lua_multi(args: &[Value<'gc>], nth: usize) -> Result<Self, ErrorKind> {
+        from_lua_arg(args.first().copied(), nth)
+    }
+}
+
+impl<'gc, T: IntoLua<'gc>> IntoLuaMulti<'gc> for T {
+    fn into_lua_multi(self, gc: &'gc GcContext) -> Result<Vec<Value<'gc>>, ErrorKind> {
+        Ok(vec![self.into_lua(gc)?])
+    }
+}
+
+impl<'gc, T: FromLua<'gc>> FromLuaMulti<'gc> for Variadic<T> {
+    fn from_lua_multi(args: &[Value<'gc>], nth: usize) -> Result<Self, ErrorKind> {
+        args.iter()
+            .enumerate()
+            .map(|(i, value)| from_lua_arg(Some(*value), nth + i))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl<'gc, T: IntoLua<'gc>> IntoLuaMulti<'gc> for Variadic<T> {
+    fn into_lua_multi(self, gc: &'gc GcContext) -> Result<Vec<Value<'gc>>, ErrorKind> {
+        self.0.into_iter().map(|value| value.into_lua(gc)).collect()
+    }
+}
+
+impl<'gc> FromLuaMulti<'gc> for () {
+    fn from_lua_multi(_: &[Value<'gc>], _: usize) -> Result<Self, ErrorKind> {
+        Ok(())
+    }
+}
+
+impl<'gc> IntoLuaMulti<'gc> for () {
+    fn into_lua_multi(self, _: &'gc GcContext) -> Result<Vec<Value<'gc>>, ErrorKind> {
+        Ok(Vec::new())
+    }
+}
+
+// the last element of a tuple of arguments can take several values, for
+// example `(Integer, Variadic<String>)`
+macro_rules! impl_tuple {
+    ($($ty:ident)* ; $last:ident) => {
+        impl<'gc, $($ty: FromLua<'gc>,)* $last: FromLuaMulti<'gc>> FromLuaMulti<'gc>
+            for ($($ty,)* $last,)
+        {
+            #[allow(non_snake_case, unused_mut)]
+            fn from_lua_multi(args: &[Value<'gc>], nth: usize) -> Result<Self, ErrorKind> {
+                let mut i = 0;
+                $(
+                    let $ty = from_lua_arg(args.get(i).copied(), nth + i)?;
+                    i += 1;
+                )*
+                let $last = $last::from_lua_multi(args.get(i..).unwrap_or_default(), nth + i)?;
+                Ok(($($ty,)* $last,))
+            }
+        }
+
+        impl<'gc, $($ty: IntoLua<'gc>,)* $last: IntoLuaMulti<'gc>> IntoLuaMulti<'gc>
+            for ($($ty,)* $last,)
+        {
+            #[allow(non_snake_case)]
+            fn into_lua_multi(self, gc: &'gc GcContext) -> Result<Vec<Value<'gc>>, ErrorKind> {
+                let ($($ty,)* $last,) = self;
+                let mut values = vec![$($ty.into_lua(gc)?,)*];
+                values.extend($last.into_lua_multi(gc)?);
+                Ok(values)
+            }
+        }
+    };
+}
+
+impl_tuple!(; A);
+impl_tuple!(A; B);
+impl_tuple!(A B; C);
+impl_tuple!(A B C; D);
+impl_tuple!(A B C D; E);
+impl_tuple!(A B C D E; F);
+impl_tuple!(A B C D E F; G);
+impl_tuple!(A B C D E F G; H);
+
+/// A Rust function that can be called with Lua arguments, converting each of
+/// them with `FromLua`. Its last parameter can take several arguments with
+/// `FromLuaMulti`, for example `Variadic<String>`. `A` is the tuple of its
+/// parameter types.
+pub trait LuaFn<'gc, A, R> {
+    /// Calls the function with `args`, which do not include the callee.
+    fn call_with_args(&self, args: &[Value<'gc>]) -> Result<R, ErrorKind>;
+}
+
+macro_rules! impl_lua_fn {
+    ($($ty:ident)*) => {
+        impl<'gc, Func, $($ty,)* R> LuaFn<'gc, ($($ty,)*), R> for Func
+        where
+            Func: Fn($($ty),*) -> Result<R, ErrorKind>,
+            ($($ty,)*): FromLuaMulti<'gc>,
+        {
+            #[allow(non_snake_case)]
+            fn call_with_args(&self, args: &[Value<'gc>]) -> Result<R, ErrorKind> {
+                let ($($ty,)*) = <($($ty,)*)>::from_lua_multi(args, 1)?;
+                self($($ty),*)
+            }
+        }
+    };
+}
+
+impl_lua_fn!();
+impl_lua_fn!(A);
+impl_lua_fn!(A B);
+impl_lua_fn!(A B C);
+impl_lua_fn!(A B C D);
+impl_lua_fn!(A B C D E);
+impl_lua_fn!(A B C D E F);
+impl_lua_fn!(A B C D E F G);
+impl_lua_fn!(A B C D E F G H);
@@ -1,9 +1,10 @@
 use crate::{
     gc::{GarbageCollect, Gc, GcCell, GcContext, Tracer},
     runtime::{Action, ErrorKind, Instruction, Vm},
-    types::{LuaString, LuaThread, Value},
+    types::{IntoLuaMulti, LuaFn, LuaString, LuaThread, Value},
 };
 use std::{
+    cell::Cell,
     fmt::Debug,
     hash::Hash,
     ops::{Range, RangeInclusive},
@@ -57,9 +58,17 @@ pub struct LocalVariable<'gc> {
     pub pc: Range<u32>,
 }
 
+unsafe impl GarbageCollect for LocalVariable<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.name.trace(tracer);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LuaClosureProto<'gc> {
     pub max_stack_size: u8,
+    pub num_params: u8,
+    pub is_vararg: bool,
     pub lines_defined: LineRange,
     pub constants: Box<[Value<'gc>]>,
     pub code: Box<[Instruction]>,
@@ -70,6 +79,7 @@ pub struct LuaClosureProto<'gc> {
     pub abs_line_info: Option<Box<[AbsLineInfo]>>,
     pub line_info: Option<Box<[u8]>>,
     pub local_vars: Option<Box<[LocalVariable<'gc>]>>,
+    pub upvalue_names: Option<Box<[LuaString<'gc>]>>,
 }
 
 unsafe impl GarbageCollect for LuaClosureProto<'_> {
@@ -77,6 +87,8 @@ unsafe impl GarbageCollect for LuaClosureProto<'_> {
         self.constants.trace(tracer);
         self.protos.trace(tracer);
         self.source.trace(tracer);
+        self.local_vars.trace(tracer);
+        self.upvalue_names.trace(tracer);
     }
 }
 
@@ -89,7 +101,7 @@ pub enum LineRange {
 impl LineRange {
     pub const fn base_line(&self) -> u32 {
         match self {
-            Self::File => 1,
+            Self::File => 0,
             Self::Lines(r) => *r.start(),
         }
     }
@@ -98,13 +110,16 @@ impl LineRange {
 #[derive(Debug, Clone)]
 pub struct LuaClosure<'gc> {
     pub(crate) proto: Gc<'gc, LuaClosureProto<'gc>>,
-    pub(crate) upvalues: Vec<GcCell<'gc, Upvalue<'gc>>>,
+    // replaced by `debug.upvaluejoin`
+    pub(crate) upvalues: Vec<Cell<GcCell<'gc, Upvalue<'gc>>>>,
 }
 
 unsafe impl GarbageCollect for LuaClosure<'_> {
     fn trace(&self, tracer: &mut Tracer) {
         self.proto.trace(tracer);
-        self.upvalues.trace(tracer);
+        for upvalue in &self.upvalues {
+            upvalue.get().trace(tracer);
+        }
     }
 }
 
@@ -215,6 +230,22 @@ impl<'gc> NativeClosure<'gc> {
         Self(Box::new(UpvalueNativeClosure { f, upvalue }))
     }
 
+    /// Wraps a Rust function taking arguments of types implementing
+    /// `FromLua`, such as `Fn(Integer, String)`, or all of them as a tuple.
+    /// Arguments that cannot be converted raise `bad argument` errors.
+    pub fn from_fn<F, A, R>(f: F) -> Self
+    where
+        F: 'static + LuaFn<'gc, A, R>,
+        R: IntoLuaMulti<'gc>,
+    {
+        Self::new(move |gc, _, args| {
+            let results = f
+                .call_with_args(args.get(1..).unwrap_or_default())?
+                .into_lua_multi(gc)?;
+            Ok(Action::Return(results))
+        })
+    }
+
     pub fn call(
         &self,
         gc: &'gc GcContext,
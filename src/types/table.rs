@@ -2,12 +2,15 @@ mod bucket;
 
 use super::{Integer, LuaString, NativeClosure, NativeFunction, Number, Value};
 use crate::{
-    gc::{GarbageCollect, GcCell, Tracer},
+    gc::{GarbageCollect, GcCell, Tracer, WeakTablePass},
     number_is_valid_integer,
 };
 use bucket::Bucket;
 use rustc_hash::FxHasher;
-use std::hash::{Hash, Hasher};
+use std::{
+    cell::RefCell,
+    hash::{Hash, Hasher},
+};
 
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum TableError {
@@ -57,9 +60,86 @@ unsafe impl GarbageCollect for Table<'_> {
         self.buckets.trace(tracer);
         self.metatable.trace(tracer);
     }
+
+    fn heap_size(&self) -> usize {
+        self.array.capacity() * std::mem::size_of::<Value>()
+            + self.buckets.capacity() * std::mem::size_of::<Bucket>()
+    }
+
+    // refer to "traversetable" in lgc.c
+    fn trace_cell(cell: &RefCell<Self>, tracer: &mut Tracer) {
+        let table = cell.borrow();
+        let (weak_keys, weak_values) = tracer.weak_mode(table.metatable);
+        match tracer.weak_table_pass() {
+            WeakTablePass::Propagate => {
+                table.metatable.trace(tracer);
+                table.trace_entries(tracer, weak_keys, weak_values);
+                if weak_keys || weak_values {
+                    tracer.register_weak_table();
+                }
+            }
+            WeakTablePass::Converge => table.trace_entries(tracer, weak_keys, weak_values),
+            WeakTablePass::ClearKeys if weak_keys => {
+                drop(table);
+                let mut table = cell.borrow_mut();
+                for bucket in &mut table.buckets {
+                    if bucket.has_value() && tracer.is_cleared(bucket.key()) {
+                        bucket.update_or_remove_item(Value::Nil);
+                    }
+                }
+            }
+            WeakTablePass::ClearValues if weak_values => {
+                drop(table);
+                let mut table = cell.borrow_mut();
+                for value in &mut table.array {
+                    if tracer.is_cleared(*value) {
+                        *value = Value::Nil;
+                    }
+                }
+                for bucket in &mut table.buckets {
+                    if bucket.has_value() && tracer.is_cleared(bucket.value()) {
+                        bucket.update_or_remove_item(Value::Nil);
+                    }
+                }
+            }
+            WeakTablePass::ClearKeys | WeakTablePass::ClearValues => (),
+        }
+    }
 }
 
 impl<'gc> Table<'gc> {
+    /// Traces the entries that are reachable through the table. With weak
+    /// keys, the table is an ephemeron table, where a value is only reachable
+    /// if its key is. Strings are never weak, and are marked by
+    /// `Tracer::is_cleared`.
+    fn trace_entries(&self, tracer: &mut Tracer, weak_keys: bool, weak_values: bool) {
+        for value in &self.array {
+            if weak_values {
+                tracer.is_cleared(*value);
+            } else {
+                value.trace(tracer);
+            }
+        }
+        for bucket in &self.buckets {
+            if !bucket.has_value() {
+                continue;
+            }
+            let key = bucket.key();
+            let value = bucket.value();
+            let is_key_cleared = if weak_keys {
+                tracer.is_cleared(key)
+            } else {
+                key.trace(tracer);
+                false
+            };
+            if weak_values {
+                tracer.is_cleared(value);
+            } else if !is_key_cleared {
+                value.trace(tracer);
+            }
+        }
+    }
+
     pub fn new() -> Self {
         Default::default()
     }
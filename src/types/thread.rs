@@ -1,23 +1,44 @@
 use super::{LineRange, Upvalue, Value};
 use crate::{
     gc::{GarbageCollect, GcCell, GcContext, Tracer},
-    runtime::{ErrorKind, Frame},
+    runtime::{Frame, LuaHook},
 };
 use std::{collections::BTreeMap, fmt::Display};
 
 #[derive(Default)]
 pub struct LuaThread<'gc> {
-    pub(crate) status: ThreadStatus,
+    pub(crate) status: ThreadStatus<'gc>,
     pub(crate) stack: Vec<Value<'gc>>,
     pub(crate) frames: Vec<Frame<'gc>>,
     pub(crate) open_upvalues: BTreeMap<usize, GcCell<'gc, Upvalue<'gc>>>,
+    pub(crate) tbc_slots: Vec<usize>,
+    pub(crate) hook: Option<LuaHook<'gc>>,
+    // index of the frame pushed by `Vm::call` to run the hook, which is
+    // hidden from tracebacks and the debug library. The hook is disabled
+    // until it returns
+    pub(crate) hook_frame: Option<usize>,
+    // whether the hooks have already been called for the instruction the
+    // thread is resumed at, as it was suspended by a hook or has to execute
+    // the instruction again
+    pub(crate) is_hook_yielded: bool,
+    // whether the thread was suspended by a native function like
+    // `coroutine.yield`, which remains level 0 of its call stack although
+    // its frame has been popped
+    pub(crate) is_yielded_by_native: bool,
 }
 
 unsafe impl GarbageCollect for LuaThread<'_> {
     fn trace(&self, tracer: &mut Tracer) {
+        self.status.trace(tracer);
         self.stack.trace(tracer);
         self.frames.trace(tracer);
         self.open_upvalues.trace(tracer);
+        self.hook.trace(tracer);
+    }
+
+    fn heap_size(&self) -> usize {
+        self.stack.capacity() * std::mem::size_of::<Value>()
+            + self.frames.capacity() * std::mem::size_of::<Frame>()
     }
 }
 
@@ -44,39 +65,86 @@ impl<'gc> LuaThread<'gc> {
     }
 
     pub fn traceback(&self) -> Vec<TracebackFrame> {
-        self.frames
-            .iter()
-            .enumerate()
-            .rev()
-            .map(|(i, frame)| match frame {
-                Frame::Lua(frame) => {
-                    let value = self.stack[frame.bottom];
-                    let proto = value.as_lua_closure().unwrap().proto;
-                    TracebackFrame::Lua {
-                        source: String::from_utf8_lossy(&proto.source).to_string(),
-                        line: proto.current_line(frame),
-                        lines_defined: proto.lines_defined.clone(),
+        self.levels(false)
+            .into_iter()
+            .map(|level| match level {
+                StackLevel::Frame(i) => match &self.frames[i] {
+                    Frame::Lua(frame) => {
+                        let value = self.stack[frame.bottom];
+                        let proto = value.as_lua_closure().unwrap().proto;
+                        TracebackFrame::Lua {
+                            source: String::from_utf8_lossy(&proto.source).to_string(),
+                            line: proto.current_line(frame),
+                            lines_defined: proto.lines_defined.clone(),
+                        }
                     }
-                }
-                Frame::Native { .. } => {
-                    let func = self.frames[..i]
-                        .iter()
-                        .rev()
-                        .find_map(Frame::as_lua)
-                        .and_then(|frame| {
-                            let value = self.stack[frame.bottom];
-                            let proto = value.as_lua_closure().unwrap().proto;
-                            proto
-                                .func_name_from_pc(frame.last_pc())
-                                .map(|name| name.name.to_string())
-                        });
-                    TracebackFrame::Native { func }
-                }
-                _ => TracebackFrame::Native { func: None },
+                    _ => TracebackFrame::Native {
+                        func: self.func_name_from_caller(i),
+                    },
+                },
+                StackLevel::Native => TracebackFrame::Native {
+                    func: self.func_name_from_caller(self.frames.len()),
+                },
             })
             .collect()
     }
 
+    /// Returns the `chunkname:currentline:` position of the function at the
+    /// given level of the call stack of the running thread, where level 0 is
+    /// the running native function. Returns `None` if that function is not a
+    /// Lua function or its current line is unknown.
+    // refer to "luaL_where" in lauxlib.c
+    pub(crate) fn location(&self, level: usize) -> Option<String> {
+        let Some(&StackLevel::Frame(i)) = self.levels(true).get(level) else {
+            return None;
+        };
+        let frame = self.frames[i].as_lua()?;
+        let proto = self.stack[frame.bottom].as_lua_closure()?.proto;
+        let line = proto.current_line(frame)?;
+        let source = String::from_utf8_lossy(&proto.source);
+        Some(format!("{}:{line}:", crate::chunk_id_from_source(&source)))
+    }
+
+    /// Returns the function activations of the call stack from the innermost
+    /// one, which is at level 0. `is_running` tells whether the thread is
+    /// running a native function, which has no frame while it runs.
+    ///
+    /// The frame pushed to run the hook and the frames pushed by the VM, e.g.
+    /// to call metamethods, are skipped.
+    // refer to "lua_getstack" in ldebug.c
+    pub(crate) fn levels(&self, is_running: bool) -> Vec<StackLevel> {
+        let frameless = (is_running || self.is_yielded_by_native).then_some(StackLevel::Native);
+        frameless
+            .into_iter()
+            .chain(
+                self.frames
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .filter(|(i, frame)| Some(*i) != self.hook_frame && !frame.is_internal())
+                    .map(|(i, _)| StackLevel::Frame(i)),
+            )
+            .collect()
+    }
+
+    /// Returns the index of the frame that called the function of the frame
+    /// at `index`, skipping the frames pushed by the VM in between.
+    pub(crate) fn caller_index(&self, index: usize) -> Option<usize> {
+        self.frames[..index]
+            .iter()
+            .rposition(|frame| !frame.is_internal())
+    }
+
+    /// Returns the name of the function of the frame at `index` if it was
+    /// called by a Lua function.
+    fn func_name_from_caller(&self, index: usize) -> Option<String> {
+        let frame = self.frames[self.caller_index(index)?].as_lua()?;
+        let proto = self.stack[frame.bottom].as_lua_closure()?.proto;
+        proto
+            .func_name_from_pc(frame.last_pc())
+            .map(|name| name.name.to_string())
+    }
+
     pub(crate) fn close_upvalues(&mut self, gc: &'gc GcContext, boundary: usize) {
         for (_, upvalue) in self.open_upvalues.split_off(&boundary) {
             let mut upvalue = upvalue.borrow_mut(gc);
@@ -88,18 +156,38 @@ impl<'gc> LuaThread<'gc> {
 }
 
 #[derive(Debug)]
-pub enum ThreadStatus {
+pub enum ThreadStatus<'gc> {
     Resumable,
     Unresumable,
-    Error(ErrorKind),
+    /// The thread was killed by an error. The error object is traced rather
+    /// than rooted, so that it can refer to the thread itself.
+    Error(Value<'gc>),
 }
 
-impl Default for ThreadStatus {
+unsafe impl GarbageCollect for ThreadStatus<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Self::Error(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl Default for ThreadStatus<'_> {
     fn default() -> Self {
         Self::Resumable
     }
 }
 
+/// A function activation at a level of the call stack.
+#[derive(Clone, Copy)]
+pub(crate) enum StackLevel {
+    /// A native function that has no frame, which is either the running
+    /// native function or the one that suspended the thread
+    Native,
+    /// An index into the frames of the thread
+    Frame(usize),
+}
+
 #[derive(Debug)]
 pub enum TracebackFrame {
     Lua {
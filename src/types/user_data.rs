@@ -1,37 +1,116 @@
-use super::Table;
-use crate::gc::{GarbageCollect, GcCell, Tracer};
-use std::any::Any;
+use super::{FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, LuaFn, NativeClosure, Table, Value};
+use crate::{
+    gc::{GarbageCollect, GcCell, GcContext, Tracer},
+    runtime::{Action, ErrorKind, Metamethod, Vm},
+};
+use bstr::ByteSlice;
+use std::{
+    any::{Any, TypeId},
+    fmt,
+    marker::PhantomData,
+};
 
-#[derive(Debug)]
 pub struct UserData<'gc> {
-    data: Box<dyn Any>,
+    data: Box<dyn GarbageCollect + 'gc>,
+    type_id: TypeId,
     metatable: Option<GcCell<'gc, Table<'gc>>>,
+    user_values: Box<[Value<'gc>]>,
+}
+
+impl fmt::Debug for UserData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserData")
+            .field("type_id", &self.type_id)
+            .field("metatable", &self.metatable)
+            .field("user_values", &self.user_values)
+            .finish_non_exhaustive()
+    }
 }
 
 unsafe impl GarbageCollect for UserData<'_> {
     fn trace(&self, tracer: &mut Tracer) {
+        self.data.trace(tracer);
         self.metatable.trace(tracer);
+        self.user_values.trace(tracer);
+    }
+
+    fn heap_size(&self) -> usize {
+        std::mem::size_of_val(self.data.as_ref()) + std::mem::size_of_val(self.user_values.as_ref())
     }
 }
 
 impl<'gc> UserData<'gc> {
     pub fn new<T: Any>(data: T) -> Self {
+        Self {
+            data: Box::new(Untraced(data)),
+            type_id: TypeId::of::<Untraced<T>>(),
+            metatable: None,
+            user_values: Box::new([]),
+        }
+    }
+
+    /// Creates a userdata holding the payload of the `LuaUserData` type `T`,
+    /// which is traced along with the userdata.
+    ///
+    /// Unlike `Vm::create_userdata`, the userdata has no metatable.
+    pub fn with_data<T: LuaUserData>(data: T::Data<'gc>) -> Self {
         Self {
             data: Box::new(data),
+            type_id: TypeId::of::<T>(),
             metatable: None,
+            user_values: Box::new([]),
         }
     }
 
+    /// Gives the userdata `n` user values, initialized to nil.
+    pub fn with_user_values(mut self, n: usize) -> Self {
+        self.user_values = vec![Value::Nil; n].into();
+        self
+    }
+
+    /// Returns whether the userdata was created by `new` with a `T`.
     pub fn is<T: Any>(&self) -> bool {
-        self.data.is::<T>()
+        self.type_id == TypeId::of::<Untraced<T>>()
     }
 
     pub fn get<T: Any>(&self) -> Option<&T> {
-        self.data.downcast_ref()
+        // SAFETY: the payload of a userdata identified by `Untraced<T>` is one
+        let data = unsafe { self.downcast::<Untraced<T>>(TypeId::of::<Untraced<T>>()) };
+        data.map(|data| &data.0)
     }
 
     pub fn get_mut<T: Any>(&mut self) -> Option<&mut T> {
-        self.data.downcast_mut()
+        // SAFETY: the payload of a userdata identified by `Untraced<T>` is one
+        let data = unsafe { self.downcast_mut::<Untraced<T>>(TypeId::of::<Untraced<T>>()) };
+        data.map(|data| &mut data.0)
+    }
+
+    /// Returns whether the userdata holds the payload of the `LuaUserData`
+    /// type `T`.
+    pub fn is_data<T: LuaUserData>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    pub fn get_data<T: LuaUserData>(&self) -> Option<&T::Data<'gc>> {
+        // SAFETY: the payload of a userdata identified by `T` is a `T::Data`
+        unsafe { self.downcast(TypeId::of::<T>()) }
+    }
+
+    pub fn get_data_mut<T: LuaUserData>(&mut self) -> Option<&mut T::Data<'gc>> {
+        // SAFETY: the payload of a userdata identified by `T` is a `T::Data`
+        unsafe { self.downcast_mut(TypeId::of::<T>()) }
+    }
+
+    /// # Safety
+    /// The payload must be a `D` if the userdata is identified by `type_id`.
+    unsafe fn downcast<D>(&self, type_id: TypeId) -> Option<&D> {
+        (self.type_id == type_id).then(|| &*(self.data.as_ref() as *const _ as *const D))
+    }
+
+    /// # Safety
+    /// The payload must be a `D` if the userdata is identified by `type_id`.
+    unsafe fn downcast_mut<D>(&mut self, type_id: TypeId) -> Option<&mut D> {
+        (self.type_id == type_id).then(|| &mut *(self.data.as_mut() as *mut _ as *mut D))
     }
 
     pub const fn metatable(&self) -> Option<GcCell<'gc, Table<'gc>>> {
@@ -44,4 +123,421 @@ impl<'gc> UserData<'gc> {
     {
         self.metatable = metatable.into();
     }
+
+    pub fn num_user_values(&self) -> usize {
+        self.user_values.len()
+    }
+
+    /// Returns the `n`-th user value, counting from 1, or `None` if the
+    /// userdata does not have that value.
+    pub fn user_value(&self, n: usize) -> Option<Value<'gc>> {
+        self.user_values.get(n.checked_sub(1)?).copied()
+    }
+
+    /// Sets the `n`-th user value, counting from 1. Returns `false` if the
+    /// userdata does not have that value.
+    pub fn set_user_value(&mut self, n: usize, value: Value<'gc>) -> bool {
+        match n.checked_sub(1).and_then(|i| self.user_values.get_mut(i)) {
+            Some(user_value) => {
+                *user_value = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A Rust type used as userdata, declaring the methods, fields and
+/// metamethods available to Lua.
+///
+/// The metatable is built on the first use of the type and cached in the
+/// registry under `NAME`, so `NAME` must be unique among userdata types.
+///
+/// The payload of the userdata is `Data<'gc>`, which is traced along with
+/// the userdata and so can hold garbage collected values. As the trait is
+/// implemented on a `'static` type, a payload generic over `'gc` implements
+/// it on its `'static` version:
+///
+/// ```ignore
+/// struct Callback<'gc>(Value<'gc>);
+///
+/// impl LuaUserData for Callback<'static> {
+///     const NAME: &'static str = "Callback";
+///     type Data<'gc> = Callback<'gc>;
+/// }
+/// ```
+///
+/// Other types use `type Data<'gc> = Self`.
+pub trait LuaUserData: Any + Sized {
+    /// Name of the type, also stored in the `__name` field of the metatable.
+    const NAME: &'static str;
+
+    /// The payload of the userdata, branded with the lifetime of the heap.
+    type Data<'gc>: GarbageCollect + 'gc;
+
+    fn add_methods<'gc>(methods: &mut UserDataMethods<'gc, Self>) {
+        let _ = methods;
+    }
+}
+
+/// Collects the methods, fields and metamethods of a `LuaUserData` type.
+///
+/// Methods taking `&T` and `&mut T` borrow the userdata for the duration of
+/// the call, and raise an error instead of panicking if it is already
+/// borrowed incompatibly.
+pub struct UserDataMethods<'gc, T> {
+    gc: &'gc GcContext,
+    methods: Table<'gc>,
+    getters: Table<'gc>,
+    setters: Table<'gc>,
+    metatable: Table<'gc>,
+    _marker: PhantomData<T>,
+}
+
+impl<'gc, T: LuaUserData> UserDataMethods<'gc, T> {
+    /// Adds a method called as `ud:name(...)`.
+    pub fn add_method<N, F, A, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + Fn(&T::Data<'gc>, A) -> Result<R, ErrorKind>,
+        A: FromLuaMulti<'gc>,
+        R: IntoLuaMulti<'gc>,
+    {
+        let method =
+            method::<T, _, _, _>(move |this, _, args| borrow::<T, _>(this, |this| f(this, args)));
+        self.set(Target::Methods, name, method);
+    }
+
+    /// Adds a method called as `ud:name(...)` that mutates the userdata.
+    pub fn add_method_mut<N, F, A, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + Fn(&mut T::Data<'gc>, A) -> Result<R, ErrorKind>,
+        A: FromLuaMulti<'gc>,
+        R: IntoLuaMulti<'gc>,
+    {
+        let method = method::<T, _, _, _>(move |this, gc, args| {
+            borrow_mut::<T, _>(gc, this, |this| f(this, args))
+        });
+        self.set(Target::Methods, name, method);
+    }
+
+    /// Adds a function called as `ud.name(...)`.
+    pub fn add_function<N, F, A, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + LuaFn<'gc, A, R>,
+        R: IntoLuaMulti<'gc>,
+    {
+        self.set(Target::Methods, name, NativeClosure::from_fn(f));
+    }
+
+    /// Adds a field read as `ud.name`.
+    pub fn add_field_method_get<N, F, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + Fn(&T::Data<'gc>) -> Result<R, ErrorKind>,
+        R: IntoLua<'gc>,
+    {
+        let getter = method::<T, _, _, _>(move |this, _, ()| borrow::<T, _>(this, &f));
+        self.set(Target::Getters, name, getter);
+    }
+
+    /// Adds a field written as `ud.name = value`.
+    pub fn add_field_method_set<N, F, A>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + Fn(&mut T::Data<'gc>, A) -> Result<(), ErrorKind>,
+        A: FromLua<'gc>,
+    {
+        let setter = method::<T, _, _, _>(move |this, gc, value| {
+            borrow_mut::<T, _>(gc, this, |this| f(this, value))
+        });
+        self.set(Target::Setters, name, setter);
+    }
+
+    /// Adds a metamethod such as `__add` or `__tostring`, receiving the
+    /// userdata as its first argument.
+    ///
+    /// `__index` and `__newindex` are only called for keys that are not
+    /// methods or fields.
+    pub fn add_meta_method<N, F, A, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + Fn(&T::Data<'gc>, A) -> Result<R, ErrorKind>,
+        A: FromLuaMulti<'gc>,
+        R: IntoLuaMulti<'gc>,
+    {
+        let method =
+            method::<T, _, _, _>(move |this, _, args| borrow::<T, _>(this, |this| f(this, args)));
+        self.set(Target::Metatable, name, method);
+    }
+
+    /// Like `add_meta_method`, but the metamethod mutates the userdata.
+    pub fn add_meta_method_mut<N, F, A, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + Fn(&mut T::Data<'gc>, A) -> Result<R, ErrorKind>,
+        A: FromLuaMulti<'gc>,
+        R: IntoLuaMulti<'gc>,
+    {
+        let method = method::<T, _, _, _>(move |this, gc, args| {
+            borrow_mut::<T, _>(gc, this, |this| f(this, args))
+        });
+        self.set(Target::Metatable, name, method);
+    }
+
+    /// Adds a metamethod that takes all operands as arguments, which is
+    /// useful for binary operators whose first operand may not be the
+    /// userdata.
+    pub fn add_meta_function<N, F, A, R>(&mut self, name: N, f: F)
+    where
+        N: AsRef<[u8]>,
+        F: 'static + LuaFn<'gc, A, R>,
+        R: IntoLuaMulti<'gc>,
+    {
+        self.set(Target::Metatable, name, NativeClosure::from_fn(f));
+    }
+
+    fn set<N: AsRef<[u8]>>(&mut self, target: Target, name: N, closure: NativeClosure<'gc>) {
+        let table = match target {
+            Target::Methods => &mut self.methods,
+            Target::Getters => &mut self.getters,
+            Target::Setters => &mut self.setters,
+            Target::Metatable => &mut self.metatable,
+        };
+        table.set_field(
+            self.gc.allocate_string(name.as_ref()),
+            self.gc.allocate(closure),
+        );
+    }
+}
+
+enum Target {
+    Methods,
+    Getters,
+    Setters,
+    Metatable,
+}
+
+/// Wraps a method, checking that the first argument is a userdata of type `T`
+/// and converting the others.
+fn method<'gc, T, F, A, R>(f: F) -> NativeClosure<'gc>
+where
+    T: LuaUserData,
+    F: 'static + Fn(GcCell<'gc, UserData<'gc>>, &'gc GcContext, A) -> Result<R, ErrorKind>,
+    A: FromLuaMulti<'gc>,
+    R: IntoLuaMulti<'gc>,
+{
+    NativeClosure::new(move |gc, _, args| {
+        let this = match args.get(1) {
+            Some(Value::UserData(ud)) => match ud.try_borrow() {
+                Ok(data) if data.is_data::<T>() => Some(*ud),
+                Ok(_) => None,
+                Err(_) => return Err(ErrorKind::other("userdata already mutably borrowed")),
+            },
+            _ => None,
+        };
+        let this = this.ok_or_else(|| ErrorKind::ArgumentTypeError {
+            nth: 1,
+            expected_type: T::NAME,
+            got_type: args.get(1).map(|value| value.type_name()),
+        })?;
+        let args = A::from_lua_multi(args.get(2..).unwrap_or_default(), 2)?;
+        let results = f(this, gc, args)?.into_lua_multi(gc)?;
+        Ok(Action::Return(results))
+    })
+}
+
+fn borrow<'gc, T: LuaUserData, R>(
+    this: GcCell<'gc, UserData<'gc>>,
+    f: impl FnOnce(&T::Data<'gc>) -> Result<R, ErrorKind>,
+) -> Result<R, ErrorKind> {
+    match this.try_borrow() {
+        Ok(this) => f(this.get_data::<T>().unwrap()),
+        Err(_) => Err(ErrorKind::other("userdata already mutably borrowed")),
+    }
+}
+
+fn borrow_mut<'gc, T: LuaUserData, R>(
+    gc: &'gc GcContext,
+    this: GcCell<'gc, UserData<'gc>>,
+    f: impl FnOnce(&mut T::Data<'gc>) -> Result<R, ErrorKind>,
+) -> Result<R, ErrorKind> {
+    match this.try_borrow_mut(gc) {
+        Ok(mut this) => f(this.get_data_mut::<T>().unwrap()),
+        Err(_) => Err(ErrorKind::other("userdata already borrowed")),
+    }
+}
+
+/// Payload of a userdata created by `UserData::new`, which is not traced as
+/// it is `'static`.
+struct Untraced<T>(T);
+
+unsafe impl<T> GarbageCollect for Untraced<T> {
+    fn needs_trace() -> bool {
+        false
+    }
+}
+
+/// Upvalue of `__index` and `__newindex`, holding the methods or fields of
+/// the type and the `__index` or `__newindex` declared by the type.
+struct Dispatch<'gc> {
+    methods: GcCell<'gc, Table<'gc>>,
+    fields: GcCell<'gc, Table<'gc>>,
+    fallback: Value<'gc>,
+}
+
+unsafe impl GarbageCollect for Dispatch<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.methods.trace(tracer);
+        self.fields.trace(tracer);
+        self.fallback.trace(tracer);
+    }
+}
+
+impl<'gc> Vm<'gc> {
+    /// Creates a userdata with the metatable of `T`.
+    pub fn create_userdata<T: LuaUserData>(
+        &self,
+        gc: &'gc GcContext,
+        data: T::Data<'gc>,
+    ) -> GcCell<'gc, UserData<'gc>> {
+        let metatable = self.userdata_metatable::<T>(gc);
+        let mut userdata = UserData::with_data::<T>(data);
+        userdata.set_metatable(metatable);
+        let userdata = gc.allocate_cell(userdata);
+        self.check_finalizer(gc, userdata.into(), Some(metatable));
+        userdata
+    }
+
+    /// Returns the metatable of `T`, building it on first use.
+    pub fn userdata_metatable<T: LuaUserData>(
+        &self,
+        gc: &'gc GcContext,
+    ) -> GcCell<'gc, Table<'gc>> {
+        let name = gc.allocate_string(T::NAME.as_bytes());
+        if let Some(metatable) = self.registry().borrow().get_field(name).as_table() {
+            return metatable;
+        }
+
+        let mut methods = UserDataMethods {
+            gc,
+            methods: Table::new(),
+            getters: Table::new(),
+            setters: Table::new(),
+            metatable: Table::new(),
+            _marker: PhantomData::<T>,
+        };
+        T::add_methods(&mut methods);
+        let UserDataMethods {
+            methods,
+            getters,
+            setters,
+            mut metatable,
+            ..
+        } = methods;
+
+        let index_name = self.metamethod_name(Metamethod::Index);
+        let index = metatable.get_field(index_name);
+        let index = match (methods.next(Value::Nil), getters.next(Value::Nil)) {
+            (Ok(None), Ok(None)) => index,
+            (_, Ok(None)) if index.is_nil() => gc.allocate_cell(methods).into(),
+            _ => gc
+                .allocate(NativeClosure::with_upvalue(
+                    Dispatch {
+                        methods: gc.allocate_cell(methods),
+                        fields: gc.allocate_cell(getters),
+                        fallback: index,
+                    },
+                    userdata_index,
+                ))
+                .into(),
+        };
+        metatable.set_field(index_name, index);
+
+        let new_index_name = self.metamethod_name(Metamethod::NewIndex);
+        if !matches!(setters.next(Value::Nil), Ok(None)) {
+            let new_index = NativeClosure::with_upvalue(
+                Dispatch {
+                    methods: gc.allocate_cell(Table::new()),
+                    fields: gc.allocate_cell(setters),
+                    fallback: metatable.get_field(new_index_name),
+                },
+                userdata_new_index,
+            );
+            metatable.set_field(new_index_name, gc.allocate(new_index));
+        }
+
+        metatable.set_field(self.metamethod_name(Metamethod::Name), name);
+        let metatable = gc.allocate_cell(metatable);
+        self.registry().borrow_mut(gc).set_field(name, metatable);
+        metatable
+    }
+}
+
+fn userdata_index<'gc>(
+    _: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    dispatch: &Dispatch<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let this = args.get(1).copied().unwrap_or_default();
+    let key = args.get(2).copied().unwrap_or_default();
+    let method = dispatch.methods.borrow().get(key);
+    if !method.is_nil() {
+        return Ok(Action::Return(vec![method]));
+    }
+    let getter = dispatch.fields.borrow().get(key);
+    if !getter.is_nil() {
+        return Ok(Action::TailCall {
+            callee: getter,
+            args: vec![this],
+        });
+    }
+    Ok(match dispatch.fallback {
+        Value::Nil => Action::Return(vec![Value::Nil]),
+        Value::Table(table) => Action::Return(vec![table.borrow().get(key)]),
+        fallback => Action::TailCall {
+            callee: fallback,
+            args: vec![this, key],
+        },
+    })
+}
+
+fn userdata_new_index<'gc>(
+    gc: &'gc GcContext,
+    _: &mut Vm<'gc>,
+    dispatch: &Dispatch<'gc>,
+    args: Vec<Value<'gc>>,
+) -> Result<Action<'gc>, ErrorKind> {
+    let this = args.get(1).copied().unwrap_or_default();
+    let key = args.get(2).copied().unwrap_or_default();
+    let value = args.get(3).copied().unwrap_or_default();
+    let setter = dispatch.fields.borrow().get(key);
+    if !setter.is_nil() {
+        return Ok(Action::TailCall {
+            callee: setter,
+            args: vec![this, value],
+        });
+    }
+    match dispatch.fallback {
+        Value::Nil => {
+            let key = match key.as_lua_string() {
+                Some(key) => key.to_str_lossy().into_owned(),
+                None => key.ty().name().to_owned(),
+            };
+            Err(ErrorKind::Other(format!(
+                "attempt to set unknown field '{key}'"
+            )))
+        }
+        Value::Table(table) => {
+            table.borrow_mut(gc).set(key, value)?;
+            Ok(Action::Return(Vec::new()))
+        }
+        fallback => Ok(Action::TailCall {
+            callee: fallback,
+            args: vec![this, key, value],
+        }),
+    }
 }
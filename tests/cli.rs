@@ -0,0 +1,27 @@
+#![cfg(feature = "bin")]
+
+use std::process::Command;
+
+fn run(stat: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_mochi"))
+        .args(["-e", stat])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn uncaught_error_object_uses_tostring() {
+    let stderr = run(r#"error(setmetatable({}, {__tostring = function() return "custom" end}))"#);
+    assert!(stderr.starts_with("Error: custom\n"), "{stderr}");
+}
+
+#[test]
+fn uncaught_error_object_without_tostring() {
+    let stderr = run("error({})");
+    assert!(
+        stderr.starts_with("Error: (error object is a table value)\n"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,37 @@
+use mochi_lua::{runtime::Runtime, types::NativeClosure};
+use std::collections::HashMap;
+
+#[test]
+fn container_conversion_errors_report_elements() {
+    let mut runtime = Runtime::new();
+    runtime.heap().with(|gc, vm| {
+        let mut vm = vm.borrow_mut(gc);
+        vm.load_stdlib(gc);
+        let sum = NativeClosure::from_fn(|values: Vec<i64>| Ok(values.iter().sum::<i64>()));
+        let count = NativeClosure::from_fn(|map: HashMap<String, bool>| Ok(map.len()));
+        let globals = vm.globals();
+        let mut globals = globals.borrow_mut(gc);
+        globals.set_field(gc.allocate_string(b"sum"), gc.allocate(sum));
+        globals.set_field(gc.allocate_string(b"count"), gc.allocate(count));
+    });
+    runtime
+        .execute(|gc, vm| {
+            let closure = vm.borrow().load(
+                gc,
+                "assert(sum({1, 2, 3}) == 6)
+                local function message(...)
+                    local ok, err = pcall(...)
+                    assert(not ok)
+                    return err
+                end
+                assert(message(sum, {1, 'x'}):find('(integer expected, got string)', 1, true))
+                assert(message(sum, {1, {}}):find('(integer expected, got table)', 1, true))
+                assert(message(sum, 1):find('(table expected, got number)', 1, true))
+                assert(count({a = true}) == 1)
+                assert(message(count, {[{}] = true}):find('(string expected, got table)', 1, true))",
+                "=test",
+            )?;
+            Ok(gc.allocate(closure).into())
+        })
+        .unwrap();
+}
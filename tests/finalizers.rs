@@ -0,0 +1,36 @@
+use mochi_lua::{runtime::Runtime, types::NativeClosure};
+use std::{cell::RefCell, rc::Rc};
+
+#[test]
+fn finalizers_run_in_reverse_order_on_close() {
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let mut runtime = Runtime::new();
+    runtime.heap().with(|gc, vm| {
+        let mut vm = vm.borrow_mut(gc);
+        vm.load_stdlib(gc);
+        let order = order.clone();
+        let record = NativeClosure::from_fn(move |i: i64| {
+            order.borrow_mut().push(i);
+            Ok(())
+        });
+        vm.globals()
+            .borrow_mut(gc)
+            .set_field(gc.allocate_string(b"record"), gc.allocate(record));
+    });
+    runtime
+        .execute(|gc, vm| {
+            let closure = vm.borrow().load(
+                gc,
+                "objects = {}
+                for i = 1, 3 do
+                    objects[i] = setmetatable({}, {__gc = function() record(i) end})
+                end",
+                "=test",
+            )?;
+            Ok(gc.allocate(closure).into())
+        })
+        .unwrap();
+    assert!(order.borrow().is_empty());
+    drop(runtime);
+    assert_eq!(*order.borrow(), [3, 2, 1]);
+}
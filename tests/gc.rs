@@ -0,0 +1,17 @@
+use mochi_lua::{runtime::Runtime, types::Table, GcRefMut};
+
+#[test]
+fn borrow_mut_returns_gc_ref_mut() {
+    let mut runtime = Runtime::new();
+    runtime.heap().with(|gc, _| {
+        let cell = gc.allocate_cell(vec![Table::new()]);
+        let mut table: GcRefMut<Table> =
+            GcRefMut::map(cell.borrow_mut(gc), |tables| &mut tables[0]);
+        table.set_field(gc.allocate_string(b"x"), 1);
+        drop(table);
+        assert_eq!(
+            cell.borrow()[0].get_field(gc.allocate_string(b"x")),
+            1.into()
+        );
+    });
+}
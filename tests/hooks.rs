@@ -0,0 +1,43 @@
+use mochi_lua::runtime::{HookAction, HookEvent, HookMask, Runtime};
+use std::{cell::RefCell, rc::Rc};
+
+#[test]
+fn native_functions_are_hooked_as_c_functions() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut runtime = Runtime::new();
+    runtime
+        .heap()
+        .with(|gc, vm| vm.borrow_mut(gc).load_stdlib(gc));
+    let events_ref = events.clone();
+    runtime.set_hook(
+        HookMask {
+            call: true,
+            ret: true,
+            ..Default::default()
+        },
+        0,
+        move |info| {
+            events_ref.borrow_mut().push((
+                info.event,
+                info.frame.is_some(),
+                String::from_utf8_lossy(info.source).into_owned(),
+            ));
+            Ok(HookAction::Continue)
+        },
+    );
+    runtime
+        .execute(|gc, vm| {
+            let closure = vm.borrow().load(gc, "return type(1)", "=test")?;
+            Ok(gc.allocate(closure).into())
+        })
+        .unwrap();
+    assert_eq!(
+        *events.borrow(),
+        [
+            (HookEvent::Call, true, "=test".to_owned()),
+            (HookEvent::Call, false, "=[C]".to_owned()),
+            (HookEvent::Return, false, "=[C]".to_owned()),
+            (HookEvent::Return, true, "=test".to_owned()),
+        ]
+    );
+}
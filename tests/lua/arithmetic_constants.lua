@@ -0,0 +1,29 @@
+-- `x - k` and `x << k` are coded with the negated constant, which must not
+-- be passed to the metamethods
+assert("5" - 1 == 4)
+assert("5" - 2 == 3)
+assert("5" - -3 == 8)
+assert("5" << 1 == 10)
+assert("5" >> 1 == 2)
+assert("5" + 1 == 6)
+
+local mt = {
+  __sub = function(a, b) return {"sub", a, b} end,
+  __shl = function(a, b) return {"shl", a, b} end,
+}
+local t = setmetatable({}, mt)
+
+local r = t - 1
+assert(r[1] == "sub" and r[2] == t and r[3] == 1)
+r = t - 2
+assert(r[3] == 2)
+r = t - -5
+assert(r[3] == -5)
+r = t - 1000
+assert(r[3] == 1000)
+r = t - 2.5
+assert(r[3] == 2.5)
+r = t << 3
+assert(r[1] == "shl" and r[2] == t and r[3] == 3)
+r = 1 - t
+assert(r[2] == 1 and r[3] == t)
@@ -0,0 +1,11 @@
+-- attribute errors are located at the line of the offending statement
+local function check(chunk, expected)
+    local f, err = load(chunk, "=c")
+    assert(not f and err == expected, err)
+end
+
+check("local x <foo> = 1", "c:1: unknown attribute 'foo'")
+check("\nlocal a <close>, b <close> = nil", "c:2: multiple to-be-closed variables in local list")
+check("local x <const> = 1\n\nx = 2", "c:3: attempt to assign to const variable 'x'")
+check("local x <const> = {}\nfunction f()\n  x = 2\nend", "c:3: attempt to assign to const variable 'x'")
+check("local x <close> = nil\nfunction x() end", "c:2: attempt to assign to const variable 'x'")
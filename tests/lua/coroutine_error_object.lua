@@ -0,0 +1,28 @@
+-- dead coroutines keep the error object that killed them
+local co = coroutine.create(function() error({code = 42}) end)
+local ok, err = coroutine.resume(co)
+assert(not ok and err.code == 42)
+assert(coroutine.status(co) == "dead")
+local ok, err2 = coroutine.close(co)
+assert(not ok and err2 == err)
+
+-- the error object does not keep the coroutine alive
+collectgarbage()
+local before = collectgarbage("count")
+for _ = 1, 1000 do
+    local co
+    co = coroutine.create(function()
+        error({co = co, data = string.rep("x", 1000)})
+    end)
+    coroutine.resume(co)
+end
+local gced = setmetatable({}, {__mode = "k"})
+local co
+co = coroutine.create(function() error({co = co}) end)
+coroutine.resume(co)
+gced[co] = true
+co = nil
+collectgarbage()
+collectgarbage()
+assert(next(gced) == nil)
+assert(collectgarbage("count") < before + 500)
@@ -0,0 +1,39 @@
+-- the frames of metamethods called by the VM are not levels of the call
+-- stack
+local t = setmetatable({}, {
+    __index = function()
+        local info = debug.getinfo(2, "Sl")
+        assert(info.what == "main" and info.currentline == 12)
+        local traceback = debug.traceback()
+        assert(not traceback:find("[C]", 1, true), traceback)
+        return true
+    end,
+})
+assert(t.x)
+
+local _, traceback = xpcall(function()
+    error("boom")
+end, debug.traceback)
+local first, second = traceback:match("stack traceback:\n\t([^\n]*)\n\t([^\n]*)")
+assert(first == "[C]: in function 'error'", traceback)
+assert(second:find("debug_levels.lua:15:", 1, true), traceback)
+
+-- level 0 of a coroutine suspended by `coroutine.yield` is `yield`
+local co = coroutine.create(function(a)
+    local b = a + 1
+    coroutine.yield(b)
+end)
+coroutine.resume(co, 1)
+assert(debug.getinfo(co, 0, "S").what == "C")
+assert(debug.getinfo(co, 1, "l").currentline == 24)
+assert(debug.getinfo(co, 2) == nil)
+assert(debug.getlocal(co, 0, 1) == nil)
+local name, value = debug.getlocal(co, 1, 1)
+assert(name == "a" and value == 1)
+name, value = debug.getlocal(co, 1, 2)
+assert(name == "b" and value == 2)
+assert(not pcall(debug.getlocal, co, 2, 1))
+traceback = debug.traceback(co)
+assert(traceback:match("^stack traceback:\n\t%[C%]: in function 'yield'\n\t[^\n]*:24:"), traceback)
+coroutine.resume(co)
+assert(debug.getinfo(co, 0) == nil)
@@ -0,0 +1,37 @@
+-- the frames of metamethods called by the VM are not levels of the call
+-- stack, so that `error` locates errors at the function that triggered them
+local t = setmetatable({}, {
+    __index = function(_, k)
+        error(k, 2)
+    end,
+    __newindex = function(_, k)
+        error(k, 1)
+    end,
+    __concat = function()
+        -- level 3 is `pcall`, which has no position
+        error("concat", 3)
+    end,
+})
+
+local ok, err = pcall(function()
+    return t.x
+end)
+assert(not ok and err:match("^.-error_level%.lua:17: x$"), err)
+
+ok, err = pcall(function()
+    t.y = 1
+end)
+assert(not ok and err:match("^.-error_level%.lua:8: y$"), err)
+
+ok, err = pcall(function()
+    return t .. "s"
+end)
+assert(not ok and err == "concat", err)
+
+-- errors raised by native metamethods are located at the function that
+-- triggered them
+local u = setmetatable({}, {__index = string.rep})
+ok, err = pcall(function()
+    return u.x
+end)
+assert(not ok and err:match("^.-error_level%.lua:35: bad argument"), err)
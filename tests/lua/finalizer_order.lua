@@ -0,0 +1,21 @@
+-- finalizers are called in the reverse order that they were marked
+-- (Lua 5.4 manual §2.5.3)
+local order = {}
+for i = 1, 3 do
+    setmetatable({}, {__gc = function() order[#order + 1] = i end})
+end
+collectgarbage()
+assert(#order == 3)
+assert(order[1] == 3 and order[2] == 2 and order[3] == 1)
+
+-- finalizers are not called while another finalizer is running
+order = {}
+for i = 1, 3 do
+    setmetatable({}, {__gc = function()
+        order[#order + 1] = i
+        collectgarbage()
+        order[#order + 1] = -i
+    end})
+end
+collectgarbage()
+assert(table.concat(order, " ") == "3 -3 2 -2 1 -1")
@@ -0,0 +1,19 @@
+-- goto and label errors are located at the line where they are detected and
+-- mention the line of the offending goto or label
+local function check(chunk, expected)
+    local f, err = load(chunk, "=c")
+    assert(not f and err == expected, err)
+end
+
+check("::a:: ::a::", "c:1: label 'a' already defined on line 1")
+check("::a::\ndo ::b:: end\n::a::", "c:3: label 'a' already defined on line 1")
+check("goto x", "c:1: no visible label 'x' for <goto> at line 1")
+check("function f()\n  goto y\nend", "c:3: no visible label 'y' for <goto> at line 2")
+check("break", "c:1: break outside a loop at line 1")
+check(
+    "goto f\nlocal y = 1\n::f:: print(y)",
+    "c:3: <goto f> at line 1 jumps into the scope of local 'y'"
+)
+
+-- a label at the end of a block is outside the scope of its locals
+assert(load("do goto f; local y = 1 ::f:: end"))
@@ -0,0 +1,28 @@
+-- call and return hooks are called for native functions, which are reported
+-- as C functions
+local events = {}
+local function f() end
+debug.sethook(function(event)
+    local info = debug.getinfo(2, "S")
+    events[#events + 1] = event .. " " .. info.what
+end, "cr")
+f()
+debug.sethook()
+assert(table.concat(events, ",") == "return C,call Lua,return Lua,call C", table.concat(events, ","))
+
+events = {}
+debug.sethook(function(event)
+    local info = debug.getinfo(2, "nf")
+    events[#events + 1] = event .. " " .. tostring(info.name)
+    assert(info.func ~= nil)
+end, "cr")
+local s = tostring(1)
+debug.sethook()
+assert(s == "1")
+assert(table.concat(events, ",") == "return sethook,call tostring,return tostring,call sethook", table.concat(events, ","))
+
+-- results of native functions survive garbage collection during the hook
+debug.sethook(function() collectgarbage() end, "r")
+local r = table.pack(select(1, {1}, {2}))
+debug.sethook()
+assert(r.n == 2 and r[1][1] == 1 and r[2][1] == 2)
@@ -0,0 +1,44 @@
+//! Runs the Lua scripts in `tests/lua`, which check their results with
+//! `assert`.
+
+use mochi_lua::runtime::Runtime;
+
+fn run_script(path: &str) {
+    let mut runtime = Runtime::new();
+    runtime
+        .heap()
+        .with(|gc, vm| vm.borrow_mut(gc).load_stdlib(gc));
+    runtime
+        .execute(|gc, vm| {
+            let closure = vm.borrow().load_file(gc, path)?;
+            Ok(gc.allocate(closure).into())
+        })
+        .unwrap_or_else(|err| panic!("{err}"));
+}
+
+macro_rules! scripts {
+    ($($name:ident,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                run_script(concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/lua/",
+                    stringify!($name),
+                    ".lua"
+                ));
+            }
+        )*
+    };
+}
+
+scripts! {
+    arithmetic_constants,
+    coroutine_error_object,
+    debug_levels,
+    error_level,
+    hook_native,
+    finalizer_order,
+    goto_errors,
+    attribute_errors,
+}
@@ -0,0 +1,62 @@
+use mochi_lua::{
+    gc::{GarbageCollect, Tracer},
+    runtime::{Action, Runtime},
+    types::{LuaUserData, NativeClosure, UserDataMethods, Value},
+};
+
+struct Callback<'gc>(Value<'gc>);
+
+unsafe impl GarbageCollect for Callback<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.0.trace(tracer);
+    }
+}
+
+impl LuaUserData for Callback<'static> {
+    const NAME: &'static str = "Callback";
+    type Data<'gc> = Callback<'gc>;
+
+    fn add_methods<'gc>(methods: &mut UserDataMethods<'gc, Self>) {
+        methods.add_method("get", |callback, ()| Ok(callback.0));
+        methods.add_method_mut("set", |callback, f: Value<'gc>| {
+            callback.0 = f;
+            Ok(())
+        });
+    }
+}
+
+#[test]
+fn userdata_payload_is_traced() {
+    let mut runtime = Runtime::new();
+    runtime.heap().with(|gc, vm| {
+        let mut vm = vm.borrow_mut(gc);
+        vm.load_stdlib(gc);
+        let new = NativeClosure::new(|gc, vm, args| {
+            let f = args.get(1).copied().unwrap_or_default();
+            let callback = vm.create_userdata::<Callback>(gc, Callback(f));
+            Ok(Action::Return(vec![callback.into()]))
+        });
+        vm.globals()
+            .borrow_mut(gc)
+            .set_field(gc.allocate_string(b"Callback"), gc.allocate(new));
+    });
+    runtime
+        .execute(|gc, vm| {
+            let closure = vm.borrow().load(
+                gc,
+                "local weak = setmetatable({}, {__mode = 'v'})
+                weak[1] = function() return 'first' end
+                local callback = Callback(weak[1])
+                collectgarbage()
+                assert(weak[1] and callback:get()() == 'first')
+                weak[2] = function() return 'second' end
+                callback:set(weak[2])
+                collectgarbage()
+                assert(weak[1] == nil and callback:get()() == 'second')
+                assert(getmetatable(callback).__name == 'Callback')",
+                "=test",
+            )?;
+            Ok(gc.allocate(closure).into())
+        })
+        .unwrap();
+}